@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use madr_lib::Battery;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Battery::parse_report(data);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use madr_lib::decode;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = decode::decode(data) {
+        let _ = message.to_string();
+    }
+
+    let _ = decode::dissect(data);
+});
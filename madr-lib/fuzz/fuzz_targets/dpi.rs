@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use madr_lib::dpi;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((a, b)) = dpi::decode_dpi_pair(data) {
+        let _ = (a.to_string(), b.to_string());
+    }
+});
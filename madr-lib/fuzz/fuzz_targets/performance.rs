@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use madr_lib::Performance;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(performance) = Performance::from_bytes(data) {
+        let _ = performance.polling_rate().to_string();
+    }
+});
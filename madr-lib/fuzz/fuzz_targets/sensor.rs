@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use madr_lib::Sensor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(sensor) = Sensor::from_bytes(data) {
+        let _ = sensor.mode().to_string();
+    }
+});
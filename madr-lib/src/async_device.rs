@@ -0,0 +1,185 @@
+// Async API, enabled with the `tokio` feature
+// The `Device` lives on a dedicated worker thread and operations are sent to it one at a
+// time, so no call ever blocks the runtime and no `spawn_blocking` is needed.
+//
+// Cancellation: an operation is submitted when its future is first polled, and from then on
+// runs to completion on the worker even if the future is dropped. Multi-report operations (e.g. a DPI change,
+// which reads a pair and writes it back) are therefore never cut off halfway, only their
+// result is discarded.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::battery::Battery;
+use crate::debounce::{self, Debounce};
+use crate::device::Device;
+use crate::dpi::{DpiStage, Rgb};
+use crate::model::{Capabilities, Model};
+use crate::performance::{self, Performance};
+use crate::sensor::{self, Mode, Sensor};
+use crate::{MadRError, Result, dpi, sleep};
+
+type Job = Box<dyn FnOnce(&Device) + Send>;
+
+/// Handle to a device served by a worker thread. Clones share the same device and worker,
+/// the worker stops once every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct AsyncDevice {
+    jobs: mpsc::Sender<Job>,
+    model: Model,
+    pid: u16,
+    wired: bool,
+}
+
+impl AsyncDevice {
+    /// Find and open the first supported device, see `Device::open`
+    pub async fn open() -> Result<Self> {
+        Self::spawn(Device::open).await
+    }
+
+    /// Open a specific VID/PID, see `Device::open_forced`
+    pub async fn open_forced(vid: u16, pid: u16, wired: bool) -> Result<Self> {
+        Self::spawn(move || Device::open_forced(vid, pid, wired)).await
+    }
+
+    /// Serve the device returned by `open`, which runs on the worker thread
+    pub async fn spawn<F>(open: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Device> + Send + 'static,
+    {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (opened, result) = oneshot::channel();
+
+        thread::Builder::new()
+            .name("madr-device".into())
+            .spawn(move || {
+                let device = match open() {
+                    Ok(device) => device,
+                    Err(e) => {
+                        let _ = opened.send(Err(e));
+                        return;
+                    }
+                };
+
+                let info = (
+                    device.model().clone(),
+                    device.product_id(),
+                    device.is_wired(),
+                );
+                if opened.send(Ok(info)).is_err() {
+                    return;
+                }
+
+                while let Ok(job) = queue.recv() {
+                    job(&device);
+                }
+            })?;
+
+        let (model, pid, wired) = result.await.map_err(|_| MadRError::WorkerStopped)??;
+
+        Ok(Self {
+            jobs,
+            model,
+            pid,
+            wired,
+        })
+    }
+
+    /// Run `operation` on the worker thread. Once the returned future has been polled,
+    /// the operation completes even if the future is dropped.
+    pub async fn run<T, F>(&self, operation: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Device) -> Result<T> + Send + 'static,
+    {
+        let (done, result) = oneshot::channel();
+        let job: Job = Box::new(move |device| {
+            let _ = done.send(operation(device));
+        });
+
+        self.jobs.send(job).map_err(|_| MadRError::WorkerStopped)?;
+        result.await.map_err(|_| MadRError::WorkerStopped)?
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.model.capabilities
+    }
+
+    pub fn product_id(&self) -> u16 {
+        self.pid
+    }
+
+    pub fn is_wired(&self) -> bool {
+        self.wired
+    }
+
+    pub async fn battery(&self) -> Result<Battery> {
+        self.run(Battery::read).await
+    }
+
+    pub async fn performance(&self) -> Result<Performance> {
+        self.run(Performance::read).await
+    }
+
+    pub async fn set_performance(&self, settings: Performance) -> Result<()> {
+        self.run(move |device| performance::apply_setting(device, &settings))
+            .await
+    }
+
+    pub async fn sensor(&self) -> Result<Sensor> {
+        self.run(Sensor::read).await
+    }
+
+    pub async fn set_sensor_mode(&self, mode: Mode) -> Result<()> {
+        self.run(move |device| sensor::apply_setting(device, mode))
+            .await
+    }
+
+    /// See `dpi::read_stage`
+    pub async fn dpi_stage(&self, stage: u8) -> Result<DpiStage> {
+        self.run(move |device| dpi::read_stage(device, stage)).await
+    }
+
+    /// See `dpi::read_stage_color`
+    pub async fn stage_color(&self, stage: u8) -> Result<Rgb> {
+        self.run(move |device| dpi::read_stage_color(device, stage))
+            .await
+    }
+
+    /// See `dpi::apply_dpi_setting`
+    pub async fn set_dpi(
+        &self,
+        stage: u8,
+        x_dpi: Option<u16>,
+        y_dpi: Option<u16>,
+        rgb: Option<String>,
+    ) -> Result<()> {
+        self.run(move |device| dpi::apply_dpi_setting(device, stage, x_dpi, y_dpi, rgb.as_deref()))
+            .await
+    }
+
+    pub async fn debounce(&self) -> Result<Debounce> {
+        self.run(debounce::read).await
+    }
+
+    pub async fn set_debounce(&self, debounce: Debounce) -> Result<()> {
+        self.run(move |device| debounce::apply_setting(device, debounce))
+            .await
+    }
+
+    pub async fn sleep(&self) -> Result<Duration> {
+        self.run(sleep::read).await
+    }
+
+    pub async fn set_sleep(&self, duration: Duration) -> Result<()> {
+        self.run(move |device| sleep::apply_setting(device, duration))
+            .await
+    }
+}
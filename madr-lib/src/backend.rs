@@ -0,0 +1,196 @@
+// One handle for everything that talks to the mouse, whether it goes through madrd or
+// straight to the device, so tools work the same with and without madrd running.
+
+use std::time::Duration;
+
+use crate::Result;
+use crate::battery::Battery;
+use crate::debounce::{self, Debounce};
+use crate::device::Device;
+use crate::dpi::{self, DpiStage, Rgb};
+#[cfg(unix)]
+use crate::ipc::{Client, DeviceInfo};
+use crate::model::{Capabilities, Model};
+use crate::performance::{self, Performance};
+use crate::profile::{Change, Profile};
+use crate::register::Snapshot;
+use crate::sensor::{self, Mode, Sensor};
+use crate::sleep;
+
+/// Where commands go: to madrd if it is running, otherwise straight to the device
+pub enum Backend {
+    #[cfg(unix)]
+    Daemon {
+        client: Client,
+        info: DeviceInfo,
+    },
+    Direct(Device),
+}
+
+impl Backend {
+    /// Connect to madrd, `None` if it is not running
+    #[cfg(unix)]
+    pub fn daemon() -> Result<Option<Self>> {
+        let Some(client) = Client::connect()? else {
+            return Ok(None);
+        };
+
+        let mut info = client.info()?;
+        info.model.verified = info.verified;
+        Ok(Some(Backend::Daemon { client, info }))
+    }
+
+    #[cfg(not(unix))]
+    pub fn daemon() -> Result<Option<Self>> {
+        Ok(None)
+    }
+
+    pub fn model(&self) -> &Model {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { info, .. } => &info.model,
+            Backend::Direct(device) => device.model(),
+        }
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.model().capabilities
+    }
+
+    pub fn product_id(&self) -> u16 {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { info, .. } => info.product_id,
+            Backend::Direct(device) => device.product_id(),
+        }
+    }
+
+    pub fn is_wired(&self) -> bool {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { info, .. } => info.wired,
+            Backend::Direct(device) => device.is_wired(),
+        }
+    }
+
+    pub fn battery(&self) -> Result<Battery> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.battery(),
+            Backend::Direct(device) => Battery::read(device),
+        }
+    }
+
+    pub fn performance(&self) -> Result<Performance> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.performance(),
+            Backend::Direct(device) => Performance::read(device),
+        }
+    }
+
+    pub fn set_performance(&self, settings: &Performance) -> Result<()> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.set_performance(settings),
+            Backend::Direct(device) => performance::apply_setting(device, settings),
+        }
+    }
+
+    pub fn sensor(&self) -> Result<Sensor> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.sensor(),
+            Backend::Direct(device) => Sensor::read(device),
+        }
+    }
+
+    pub fn set_sensor_mode(&self, mode: Mode) -> Result<()> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.set_sensor_mode(mode),
+            Backend::Direct(device) => sensor::apply_setting(device, mode),
+        }
+    }
+
+    pub fn read_stage(&self, stage: u8) -> Result<DpiStage> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.read_stage(stage),
+            Backend::Direct(device) => dpi::read_stage(device, stage),
+        }
+    }
+
+    pub fn read_stage_color(&self, stage: u8) -> Result<Rgb> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.read_stage_color(stage),
+            Backend::Direct(device) => dpi::read_stage_color(device, stage),
+        }
+    }
+
+    pub fn set_dpi(
+        &self,
+        stage: u8,
+        x_dpi: Option<u16>,
+        y_dpi: Option<u16>,
+        rgb: Option<&str>,
+    ) -> Result<()> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => {
+                let rgb = rgb.map(str::parse::<Rgb>).transpose()?;
+                client.set_dpi(stage, x_dpi, y_dpi, rgb)
+            }
+            Backend::Direct(device) => dpi::apply_dpi_setting(device, stage, x_dpi, y_dpi, rgb),
+        }
+    }
+
+    pub fn debounce(&self) -> Result<Debounce> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.debounce(),
+            Backend::Direct(device) => debounce::read(device),
+        }
+    }
+
+    pub fn set_debounce(&self, debounce: Debounce) -> Result<()> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.set_debounce(debounce),
+            Backend::Direct(device) => debounce::apply_setting(device, debounce),
+        }
+    }
+
+    pub fn sleep(&self) -> Result<Duration> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.sleep(),
+            Backend::Direct(device) => sleep::read(device),
+        }
+    }
+
+    pub fn set_sleep(&self, duration: Duration) -> Result<()> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.set_sleep(duration),
+            Backend::Direct(device) => sleep::apply_setting(device, duration),
+        }
+    }
+
+    pub fn apply_profile(&self, profile: &Profile) -> Result<Vec<Change>> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.apply_profile(profile),
+            Backend::Direct(device) => profile.apply(device),
+        }
+    }
+
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        match self {
+            #[cfg(unix)]
+            Backend::Daemon { client, .. } => client.snapshot(),
+            Backend::Direct(device) => Snapshot::capture(device),
+        }
+    }
+}
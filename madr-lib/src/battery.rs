@@ -1,7 +1,11 @@
-use crate::device::Device;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{Channel, Device};
+use crate::model::Feature;
+use crate::register::Command;
 use crate::{MadRError, Result};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Battery {
     percentage: u8,
     voltage_mv: u16,
@@ -11,21 +15,19 @@ pub struct Battery {
 impl Battery {
     /// Read battery status from the device
     pub fn read(device: &Device) -> Result<Self> {
+        device.capabilities().require(Feature::Battery)?;
+
         let mut report = [0u8; 17];
         report[0] = 0x08;
         report[1] = 0x04;
         report[16] = 0x55 - (0x08 + report[1]);
 
-        device.write(&report)?;
-
-        let mut buf = [0u8; 256];
-        let size = device.read_timeout(&mut buf, 20)?;
-
-        let data = &buf[..size];
-        Self::parse_report(data)
+        let data = device.query(Channel::Output, &report, Command::Battery, 0x00)?;
+        Self::parse_report(&data)
     }
 
-    fn parse_report(data: &[u8]) -> Result<Self> {
+    /// Decode a battery status report
+    pub fn parse_report(data: &[u8]) -> Result<Self> {
         if data.len() < 17 || data[0] != 0x08 || data[1] != 0x04 {
             return Err(MadRError::InvalidBatteryFormat);
         }
@@ -1,6 +1,10 @@
-use crate::{MadRError, Result, device::Device};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+use crate::decode::POWER_REGISTER;
+use crate::{MadRError, Result, device::Device, model::Feature, register};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
 pub enum Debounce {
     Ms0 = 0,
     Ms1 = 1,
@@ -32,7 +36,14 @@ impl TryFrom<u8> for Debounce {
     }
 }
 
-fn get_debounce_report(debounce: Debounce) -> Vec<u8> {
+impl From<Debounce> for u8 {
+    fn from(debounce: Debounce) -> Self {
+        debounce as u8
+    }
+}
+
+/// The sleep timeout shares the register, `sleep_tens` keeps it as it is
+pub(crate) fn get_debounce_report(debounce: Debounce, sleep_tens: u8) -> Vec<u8> {
     let debounce_ms = debounce as u8;
     vec![
         0x08,
@@ -45,8 +56,8 @@ fn get_debounce_report(debounce: Debounce) -> Vec<u8> {
         0x55u8.wrapping_sub(debounce_ms),
         0x01,
         0x54,
-        0x06,
-        0x4F,
+        sleep_tens,
+        0x55u8.wrapping_sub(sleep_tens),
         0x00,
         0x55,
         0x00,
@@ -55,10 +66,23 @@ fn get_debounce_report(debounce: Debounce) -> Vec<u8> {
     ]
 }
 
+/// Read the debounce time
+pub fn read(device: &Device) -> Result<Debounce> {
+    device.capabilities().require(Feature::Debounce)?;
+
+    let power = register::read(device, POWER_REGISTER, 0x0A)?;
+    Debounce::try_from(power[0])
+}
+
 /// Apply debounce time
 pub fn apply_setting(device: &Device, debounce: Debounce) -> Result<()> {
-    let report = get_debounce_report(debounce);
-    device.send_feature_report(&report)?;
+    device.capabilities().require(Feature::Debounce)?;
+
+    let _guard = device.lock()?;
+    let power = register::read(device, POWER_REGISTER, 0x0A)?;
+
+    let report = get_debounce_report(debounce, power[4]);
+    device.set(&report)?;
 
     Ok(())
 }
@@ -0,0 +1,456 @@
+// Report decoding
+// Turns raw 17-byte reports, e.g. from a USB capture, back into settings using the same
+// decoders the device API uses. See register.rs for the report layout.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::battery::Battery;
+use crate::dpi::{self, DpiStage, Rgb};
+use crate::performance::Performance;
+use crate::register::{self, Command};
+use crate::sensor::Mode;
+use crate::{MadRError, Result};
+
+/// Register holding the polling rate and active DPI stage
+pub(crate) const PERFORMANCE_REGISTER: u8 = 0x00;
+/// Register holding debounce time and sleep timeout
+pub(crate) const POWER_REGISTER: u8 = 0xA9;
+/// Register holding the sensor mode and a copy of the sleep timeout
+pub(crate) const SENSOR_REGISTER: u8 = 0xB5;
+
+/// Setting stored in a register block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Setting {
+    Performance(Performance),
+    /// DPI of two consecutive stages, starting at `stage`
+    DpiPair {
+        stage: u8,
+        stages: (DpiStage, DpiStage),
+    },
+    /// Colors of two consecutive stages, starting at `stage`
+    RgbPair {
+        stage: u8,
+        colors: (Rgb, Rgb),
+    },
+    Power {
+        debounce_ms: u8,
+        sleep: Duration,
+    },
+    Sensor {
+        mode: Mode,
+        sleep: Duration,
+    },
+    Unknown(Vec<u8>),
+}
+
+/// A single decoded report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    BatteryRequest,
+    Battery(Battery),
+    ReadRequest { address: u8, len: u8 },
+    Read { address: u8, setting: Setting },
+    Write { address: u8, setting: Setting },
+}
+
+impl Message {
+    /// Whether every part of the report was understood
+    pub fn is_known(&self) -> bool {
+        match self {
+            Message::ReadRequest { address, .. } => register_name(*address).is_some(),
+            Message::Read { setting, .. } | Message::Write { setting, .. } => {
+                !matches!(setting, Setting::Unknown(_))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Stage pair stored at `address` in a block of pairs starting at `base`
+fn stage_pair(base: u8, address: u8) -> Option<u8> {
+    let offset = address.checked_sub(base)?;
+    let index = offset / 0x08;
+
+    (offset % 0x08 == 0 && (1..=4).contains(&index)).then(|| index * 2 - 1)
+}
+
+/// Human readable name of a known register block
+pub fn register_name(address: u8) -> Option<String> {
+    if let Some(stage) = stage_pair(dpi::DPI_REGISTER, address) {
+        return Some(format!("DPI pair {}/{}", stage, stage + 1));
+    }
+
+    if let Some(stage) = stage_pair(dpi::RGB_REGISTER, address) {
+        return Some(format!("color pair {}/{}", stage, stage + 1));
+    }
+
+    match address {
+        PERFORMANCE_REGISTER => Some("performance".into()),
+        POWER_REGISTER => Some("debounce/sleep".into()),
+        SENSOR_REGISTER => Some("sensor/sleep".into()),
+        _ => None,
+    }
+}
+
+fn decode_setting(address: u8, report: &[u8]) -> Setting {
+    let payload = || report[6..6 + (report[5] as usize).min(10)].to_vec();
+
+    if let Some(stage) = stage_pair(dpi::DPI_REGISTER, address) {
+        return match dpi::decode_dpi_pair(report) {
+            Ok(stages) => Setting::DpiPair { stage, stages },
+            Err(_) => Setting::Unknown(payload()),
+        };
+    }
+
+    if let Some(stage) = stage_pair(dpi::RGB_REGISTER, address) {
+        return match dpi::decode_rgb_pair(report) {
+            Ok(colors) => Setting::RgbPair { stage, colors },
+            Err(_) => Setting::Unknown(payload()),
+        };
+    }
+
+    match address {
+        PERFORMANCE_REGISTER => Performance::from_bytes(report)
+            .map(Setting::Performance)
+            .unwrap_or_else(|_| Setting::Unknown(payload())),
+        POWER_REGISTER => Setting::Power {
+            debounce_ms: report[6],
+            sleep: Duration::from_secs(report[10] as u64 * 10),
+        },
+        SENSOR_REGISTER => match Mode::try_from(report[10]) {
+            Ok(mode) => Setting::Sensor {
+                mode,
+                sleep: Duration::from_secs(report[8] as u64 * 10),
+            },
+            Err(_) => Setting::Unknown(payload()),
+        },
+        _ => Setting::Unknown(payload()),
+    }
+}
+
+/// Decode a single report. Requests and responses share the same command byte,
+/// a read or battery report with an empty payload is treated as a request.
+pub fn decode(report: &[u8]) -> Result<Message> {
+    if report.len() < register::REPORT_LEN || report[0] != register::REPORT_ID {
+        return Err(MadRError::InvalidReport(format!(
+            "Expected a {} byte report starting with {:#04x}",
+            register::REPORT_LEN,
+            register::REPORT_ID
+        )));
+    }
+
+    let address = report[4];
+    let empty = report[6..16].iter().all(|b| *b == 0);
+
+    let message = match Command::try_from(report[1])? {
+        Command::Battery if empty => Message::BatteryRequest,
+        Command::Battery => Message::Battery(Battery::parse_report(report)?),
+        Command::Read if empty => Message::ReadRequest {
+            address,
+            len: report[5],
+        },
+        Command::Read => Message::Read {
+            address,
+            setting: decode_setting(address, report),
+        },
+        Command::Write => Message::Write {
+            address,
+            setting: decode_setting(address, report),
+        },
+    };
+
+    Ok(message)
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Setting::Performance(p) => write!(
+                f,
+                "polling rate = {}, DPI stage = {}",
+                p.polling_rate(),
+                p.dpi_stage()
+            ),
+            Setting::DpiPair { stage, stages } => write!(
+                f,
+                "DPI pair {}/{} = {}/{}",
+                stage,
+                stage + 1,
+                stages.0,
+                stages.1
+            ),
+            Setting::RgbPair { stage, colors } => write!(
+                f,
+                "color pair {}/{} = {}/{}",
+                stage,
+                stage + 1,
+                colors.0,
+                colors.1
+            ),
+            Setting::Power { debounce_ms, sleep } => write!(
+                f,
+                "debounce = {} ms, sleep = {} s",
+                debounce_ms,
+                sleep.as_secs()
+            ),
+            Setting::Sensor { mode, sleep } => {
+                write!(f, "sensor mode = {}, sleep = {} s", mode, sleep.as_secs())
+            }
+            Setting::Unknown(payload) => {
+                write!(f, "unknown =")?;
+                for byte in payload {
+                    write!(f, " {:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::BatteryRequest => write!(f, "request battery status"),
+            Message::Battery(b) => write!(
+                f,
+                "battery = {}%, {} mV, {}",
+                b.percentage(),
+                b.voltage(),
+                if b.is_charging() {
+                    "charging"
+                } else {
+                    "not charging"
+                }
+            ),
+            Message::ReadRequest { address, len } => match register_name(*address) {
+                Some(name) => write!(f, "read {}", name),
+                None => write!(f, "read {} byte(s) at {:#04x}", len, address),
+            },
+            Message::Read { address, setting } => fmt_access(f, "read", *address, setting),
+            Message::Write { address, setting } => fmt_access(f, "write", *address, setting),
+        }
+    }
+}
+
+fn fmt_access(
+    f: &mut fmt::Formatter<'_>,
+    verb: &str,
+    address: u8,
+    setting: &Setting,
+) -> fmt::Result {
+    match setting {
+        Setting::Unknown(_) => write!(f, "{} register {:#04x} {}", verb, address, setting),
+        _ => write!(f, "{} {}", verb, setting),
+    }
+}
+
+/// A run of bytes in a report and what they mean
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub offset: usize,
+    pub len: usize,
+    pub name: String,
+    pub meaning: String,
+    /// For check bytes, whether the stored value matches the computed one
+    pub valid: Option<bool>,
+}
+
+impl Field {
+    fn new(offset: usize, len: usize, name: impl Into<String>, meaning: impl Into<String>) -> Self {
+        Self {
+            offset,
+            len,
+            name: name.into(),
+            meaning: meaning.into(),
+            valid: None,
+        }
+    }
+
+    /// Check byte at `offset`, equal to 0x55 minus the sum of `len` bytes before it
+    fn check(report: &[u8], offset: usize, len: usize) -> Self {
+        let sum = report[offset - len..offset]
+            .iter()
+            .fold(0u8, |acc, b| acc.wrapping_add(*b));
+        let expected = 0x55u8.wrapping_sub(sum);
+
+        let covered = if len == 1 {
+            format!("0x55 - byte {}", offset - 1)
+        } else {
+            format!("0x55 - sum of bytes {}..{}", offset - len, offset - 1)
+        };
+
+        Self {
+            offset,
+            len: 1,
+            name: "check".into(),
+            meaning: format!("{} = {:#04x}", covered, expected),
+            valid: Some(report[offset] == expected),
+        }
+    }
+}
+
+fn payload_fields(report: &[u8], message: &Message) -> Vec<Field> {
+    let mut fields = Vec::new();
+
+    match message {
+        Message::Battery(b) => {
+            fields.push(Field::new(
+                6,
+                1,
+                "percentage",
+                format!("{}%", b.percentage()),
+            ));
+            fields.push(Field::new(
+                7,
+                1,
+                "charging",
+                if b.is_charging() { "yes" } else { "no" },
+            ));
+            fields.push(Field::new(8, 2, "voltage", format!("{} mV", b.voltage())));
+        }
+        Message::Read { setting, .. } | Message::Write { setting, .. } => match setting {
+            Setting::Performance(p) => {
+                fields.push(Field::new(
+                    6,
+                    1,
+                    "polling rate",
+                    p.polling_rate().to_string(),
+                ));
+                fields.push(Field::check(report, 7, 1));
+                fields.push(Field::new(8, 1, "unknown", "always 0x04"));
+                fields.push(Field::check(report, 9, 1));
+                fields.push(Field::new(
+                    10,
+                    1,
+                    "DPI stage",
+                    format!("stage {} (stored as stage - 1)", p.dpi_stage()),
+                ));
+                fields.push(Field::check(report, 11, 1));
+            }
+            Setting::DpiPair { stage, stages } => {
+                for (i, s) in [stages.0, stages.1].iter().enumerate() {
+                    let offset = 6 + i * 4;
+                    fields.push(Field::new(
+                        offset,
+                        3,
+                        format!("stage {} DPI", stage + i as u8),
+                        format!("{}x{} (low X, low Y, high bits)", s.x_dpi(), s.y_dpi()),
+                    ));
+                    fields.push(Field::check(report, offset + 3, 3));
+                }
+            }
+            Setting::RgbPair { stage, colors } => {
+                for (i, c) in [colors.0, colors.1].iter().enumerate() {
+                    let offset = 6 + i * 4;
+                    fields.push(Field::new(
+                        offset,
+                        3,
+                        format!("stage {} color", stage + i as u8),
+                        format!("R,G,B = {}", c),
+                    ));
+                    fields.push(Field::check(report, offset + 3, 3));
+                }
+            }
+            Setting::Power { debounce_ms, sleep } => {
+                fields.push(Field::new(6, 1, "debounce", format!("{} ms", debounce_ms)));
+                fields.push(Field::check(report, 7, 1));
+                fields.push(Field::new(8, 1, "unknown", format!("{:#04x}", report[8])));
+                fields.push(Field::check(report, 9, 1));
+                fields.push(Field::new(
+                    10,
+                    1,
+                    "sleep",
+                    format!("{} s (tens of seconds)", sleep.as_secs()),
+                ));
+                fields.push(Field::check(report, 11, 1));
+            }
+            Setting::Sensor { mode, sleep } => {
+                fields.push(Field::new(6, 1, "unknown", format!("{:#04x}", report[6])));
+                fields.push(Field::check(report, 7, 1));
+                fields.push(Field::new(
+                    8,
+                    1,
+                    "sleep",
+                    format!("{} s (tens of seconds)", sleep.as_secs()),
+                ));
+                fields.push(Field::check(report, 9, 1));
+                fields.push(Field::new(10, 1, "sensor mode", mode.to_string()));
+                fields.push(Field::check(report, 11, 1));
+            }
+            Setting::Unknown(payload) => {
+                // Assume the usual value/check pairs wherever the check byte matches
+                let mut offset = 6;
+                while offset < 6 + payload.len() {
+                    let pair = offset + 1 < 16
+                        && report[offset + 1] == 0x55u8.wrapping_sub(report[offset]);
+                    fields.push(Field::new(
+                        offset,
+                        1,
+                        "unknown",
+                        format!("{:#04x}", report[offset]),
+                    ));
+                    if pair {
+                        fields.push(Field::check(report, offset + 1, 1));
+                        offset += 2;
+                    } else {
+                        offset += 1;
+                    }
+                }
+            }
+        },
+        Message::BatteryRequest | Message::ReadRequest { .. } => {}
+    }
+
+    // Anything not covered above is padding
+    let mut covered = [false; register::REPORT_LEN];
+    for field in &fields {
+        covered[field.offset..field.offset + field.len].fill(true);
+    }
+
+    for offset in 6..16 {
+        if !covered[offset] {
+            let meaning = if report[offset] == 0 {
+                "unused".to_string()
+            } else {
+                format!("{:#04x}", report[offset])
+            };
+            fields.push(Field::new(offset, 1, "padding", meaning));
+        }
+    }
+
+    fields.sort_by_key(|f| f.offset);
+    fields
+}
+
+/// Explain every byte of a report
+pub fn dissect(report: &[u8]) -> Result<Vec<Field>> {
+    let message = decode(report)?;
+    let command = Command::try_from(report[1])?;
+
+    let mut fields = vec![
+        Field::new(0, 1, "report ID", format!("{:#04x}", report[0])),
+        Field::new(1, 1, "command", format!("{:?}", command).to_lowercase()),
+        Field::new(2, 2, "unused", "always 0x00"),
+        Field::new(
+            4,
+            1,
+            "address",
+            register_name(report[4]).unwrap_or_else(|| "unknown register".into()),
+        ),
+        Field::new(5, 1, "length", format!("{} byte(s)", report[5])),
+    ];
+
+    fields.extend(payload_fields(report, &message));
+
+    let expected = register::checksum(report);
+    fields.push(Field {
+        offset: 16,
+        len: 1,
+        name: "checksum".into(),
+        meaning: format!("0x55 - sum of bytes 0..15 = {:#04x}", expected),
+        valid: Some(report[16] == expected),
+    });
+
+    Ok(fields)
+}
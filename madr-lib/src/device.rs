@@ -1,53 +1,580 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+
+#[cfg(feature = "hidraw")]
+use crate::hidraw::{
+    DeviceInfo, HidrawApi as HidApi, HidrawDevice as HidDevice, MAX_REPORT_DESCRIPTOR_SIZE,
+};
+use crate::lock::ProcessLock;
+use crate::model::{self, Capabilities, Model, Quirk};
+use crate::register::{self, Command, REPORT_LEN};
+use crate::report_descriptor::ReportDescriptor;
+use crate::retry::{Failure, RetryPolicy};
+use crate::trace::{Replay, Tracer};
 use crate::{MadRError, Result};
-use hidapi::{HidApi, HidDevice};
+#[cfg(not(feature = "hidraw"))]
+use hidapi::{DeviceInfo, HidApi, HidDevice, MAX_REPORT_DESCRIPTOR_SIZE};
+
+/// Read timeout used for models with the `slow-responses` quirk
+const SLOW_RESPONSE_TIMEOUT_MS: i32 = 100;
+
+/// How long to wait for another process to finish with the device by default
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Raw report access underneath a `Device`. A `Device` only ever uses its transport from
+/// one thread at a time, so transports need to be `Send` but not `Sync`.
+pub trait Transport: fmt::Debug + Send {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()>;
+    fn write(&self, data: &[u8]) -> Result<usize>;
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize>;
+
+    /// Reopen the underlying handle after a transport error.
+    /// Transports without a handle that can go away have nothing to do.
+    fn reconnect(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "hidraw"))]
+impl Transport for HidDevice {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        HidDevice::send_feature_report(self, report)?;
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        let size = HidDevice::write(self, data)?;
+        Ok(size)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        let size = HidDevice::read_timeout(self, buf, timeout_ms)?;
+        Ok(size)
+    }
+}
+
+/// HID handle that can be reopened when the device re-enumerates
+#[derive(Debug)]
+struct HidTransport {
+    vid: u16,
+    pid: u16,
+    model: Model,
+    device: RefCell<HidDevice>,
+}
+
+impl HidTransport {
+    fn new(device: HidDevice, vid: u16, pid: u16, model: Model) -> Self {
+        Self {
+            vid,
+            pid,
+            model,
+            device: RefCell::new(device),
+        }
+    }
+}
+
+impl Transport for HidTransport {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        Transport::send_feature_report(&*self.device.borrow(), report)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        Transport::write(&*self.device.borrow(), data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        Transport::read_timeout(&*self.device.borrow(), buf, timeout_ms)
+    }
+
+    fn reconnect(&self) -> Result<()> {
+        let api = HidApi::new()?;
+        let (device, _) = open_config_device(&api, self.vid, self.pid, &self.model)?;
+        *self.device.borrow_mut() = device;
+        Ok(())
+    }
+}
+
+/// Read and parse the report descriptor of an open HID device
+fn report_descriptor(hid: &HidDevice) -> Result<ReportDescriptor> {
+    let mut buf = [0u8; MAX_REPORT_DESCRIPTOR_SIZE];
+    let size = hid.get_report_descriptor(&mut buf)?;
+    ReportDescriptor::parse(&buf[..size])
+}
+
+/// Open `info` if it is the configuration interface of `model`. The report descriptor
+/// decides, the interface number of the model is only used if it can't be read.
+fn open_config_interface(
+    api: &HidApi,
+    info: &DeviceInfo,
+    model: &Model,
+) -> Result<Option<HidDevice>> {
+    let fallback = info.interface_number() == model.interface;
+
+    let hid = match info.open_device(api) {
+        Ok(hid) => hid,
+        Err(e) if fallback => return Err(e.into()),
+        Err(_) => return Ok(None),
+    };
+
+    match report_descriptor(&hid) {
+        Ok(descriptor) if descriptor.is_config_interface() => Ok(Some(hid)),
+        Ok(_) => Ok(None),
+        Err(_) if fallback => Ok(Some(hid)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Open the configuration interface of the device with the given VID/PID
+fn open_config_device<'a>(
+    api: &'a HidApi,
+    vid: u16,
+    pid: u16,
+    model: &Model,
+) -> Result<(HidDevice, &'a DeviceInfo)> {
+    open_config_device_where(api, model, |info| {
+        info.vendor_id() == vid && info.product_id() == pid
+    })
+}
+
+/// Open the configuration interface among the HID interfaces `select` accepts
+fn open_config_device_where<'a>(
+    api: &'a HidApi,
+    model: &Model,
+    select: impl Fn(&DeviceInfo) -> bool,
+) -> Result<(HidDevice, &'a DeviceInfo)> {
+    for device_info in api.device_list() {
+        if !select(device_info) {
+            continue;
+        }
+
+        if let Some(device) = open_config_interface(api, device_info, model)? {
+            return Ok((device, device_info));
+        }
+    }
+
+    Err(MadRError::DeviceNotFound)
+}
+
+/// Lock shared with other processes opening the same device. The serial number stays the
+/// same when the device re-enumerates, the path is the fallback for devices without one.
+fn process_lock(info: &DeviceInfo) -> Result<ProcessLock> {
+    let key = match info.serial_number().filter(|s| !s.is_empty()) {
+        Some(serial) => format!(
+            "{:04x}-{:04x}-{}",
+            info.vendor_id(),
+            info.product_id(),
+            serial
+        ),
+        None => info.path().to_string_lossy().into_owned(),
+    };
 
-const VXE_VID: u16 = 0x373b;
-const MADR_WIRED_PID: u16 = 0x103f;
-const MADR_WIRELESS_PID: u16 = 0x1040;
+    ProcessLock::open(&key)
+}
+
+/// Whether requests go out as feature reports or as output reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Channel {
+    Feature,
+    Output,
+}
+
+/// A HID interface belonging to a known vendor
+#[derive(Debug, Clone)]
+pub struct DeviceEntry {
+    pub vid: u16,
+    pub pid: u16,
+    pub interface: i32,
+    pub path: String,
+    pub serial: Option<String>,
+    /// Model matching the VID/PID, if any
+    pub model: Option<Model>,
+    /// Parsed report descriptor, if it could be read
+    pub report_descriptor: Option<ReportDescriptor>,
+}
+
+impl DeviceEntry {
+    /// Whether this is the interface madr-lib talks to
+    pub fn is_config_interface(&self) -> bool {
+        match (&self.report_descriptor, &self.model) {
+            (Some(descriptor), _) => descriptor.is_config_interface(),
+            (None, Some(model)) => self.interface == model.interface,
+            (None, None) => false,
+        }
+    }
+}
 
+/// List every HID interface whose vendor ID belongs to a known model
+pub fn list() -> Result<Vec<DeviceEntry>> {
+    let api = HidApi::new()?;
+    let models = model::all_models()?;
+
+    let entries = api
+        .device_list()
+        .filter(|info| models.iter().any(|m| m.vid == info.vendor_id()))
+        .map(|info| DeviceEntry {
+            vid: info.vendor_id(),
+            pid: info.product_id(),
+            interface: info.interface_number(),
+            path: info.path().to_string_lossy().into_owned(),
+            serial: info.serial_number().map(String::from),
+            model: models
+                .iter()
+                .find(|m| m.matches(info.vendor_id(), info.product_id()))
+                .cloned(),
+            report_descriptor: info
+                .open_device(&api)
+                .ok()
+                .and_then(|hid| report_descriptor(&hid).ok()),
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+/// Parse a device ID given as "VID:PID" in hex, e.g. "373b:1040" or "0x373b:0x1040"
+pub fn parse_device_id(s: &str) -> Result<(u16, u16)> {
+    let (vid, pid) = s.split_once(':').ok_or_else(|| {
+        MadRError::InvalidDeviceId(format!("{}, expected VID:PID, e.g. 373b:1040", s))
+    })?;
+
+    let parse = |x: &str| {
+        u16::from_str_radix(x.trim_start_matches("0x"), 16)
+            .map_err(|e| MadRError::InvalidDeviceId(format!("{}: {}", x, e)))
+    };
+    Ok((parse(vid)?, parse(pid)?))
+}
+
+/// State shared by all clones of a `Device`
 #[derive(Debug)]
+struct Shared {
+    /// Held for a whole transaction, so that every request gets its own response
+    transport: ReentrantMutex<Box<dyn Transport>>,
+    /// Keeps other processes out while `transport` is held, for real devices
+    process_lock: Option<ProcessLock>,
+    /// Number of nested `DeviceGuard`s, only changed with `transport` held
+    lock_depth: AtomicUsize,
+    /// Current response timeout, grows when responses time out
+    timeout_ms: AtomicI32,
+}
+
+/// Handle to a device. Clones share the same connection and can be used from different
+/// threads, their transactions take turns instead of interleaving.
+#[derive(Debug, Clone)]
 pub struct Device {
-    wired: bool,
-    hid: HidDevice,
+    pid: u16,
+    model: Model,
+    retry_policy: RetryPolicy,
+    lock_timeout: Duration,
+    shared: Arc<Shared>,
+}
+
+/// Exclusive access to a device across several transactions, see `Device::lock`
+#[must_use = "the device is unlocked again as soon as the guard is dropped"]
+pub struct DeviceGuard<'a> {
+    transport: ReentrantMutexGuard<'a, Box<dyn Transport>>,
+    shared: &'a Shared,
+}
+
+impl Drop for DeviceGuard<'_> {
+    fn drop(&mut self) {
+        if self.shared.lock_depth.fetch_sub(1, Ordering::Relaxed) == 1
+            && let Some(lock) = &self.shared.process_lock
+        {
+            lock.release();
+        }
+    }
+}
+
+impl fmt::Debug for DeviceGuard<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceGuard").finish_non_exhaustive()
+    }
 }
 
 impl Device {
     pub fn open() -> Result<Self> {
         let api = HidApi::new()?;
+        let models = model::all_models()?;
 
-        let device_info = api.device_list().find(|x| {
-            x.vendor_id() == VXE_VID
-                && (x.product_id() == MADR_WIRED_PID || x.product_id() == MADR_WIRELESS_PID)
-                && x.interface_number() == 1
-        });
+        for device_info in api.device_list() {
+            let vid = device_info.vendor_id();
+            let pid = device_info.product_id();
 
-        if let Some(device_info) = device_info {
-            let device = device_info.open_device(&api)?;
-            return Ok(Device {
-                wired: device_info.product_id() == MADR_WIRED_PID,
-                hid: device,
-            });
+            let Some(model) = models.iter().find(|m| m.matches(vid, pid)) else {
+                continue;
+            };
+
+            if let Some(device) = open_config_interface(&api, device_info, model)? {
+                let transport = HidTransport::new(device, vid, pid, model.clone());
+                return Ok(Device::new(
+                    Box::new(transport),
+                    model.clone(),
+                    pid,
+                    Some(process_lock(device_info)?),
+                ));
+            }
         }
 
         Err(MadRError::DeviceNotFound)
     }
 
+    /// Open a specific VID/PID even if no model matches it, for experimenting with
+    /// untested devices. Unknown devices are treated as a MAD R, connected by cable if `wired`.
+    pub fn open_forced(vid: u16, pid: u16, wired: bool) -> Result<Self> {
+        let model = model::find_model(vid, pid)?.unwrap_or_else(|| Model::generic(vid, pid, wired));
+
+        let api = HidApi::new()?;
+        let (device, device_info) = open_config_device(&api, vid, pid, &model)?;
+        let lock = process_lock(device_info)?;
+        let transport = HidTransport::new(device, vid, pid, model.clone());
+
+        Ok(Device::new(Box::new(transport), model, pid, Some(lock)))
+    }
+
+    /// Open the device behind the HID nodes at `paths` (e.g. /dev/hidraw3), for telling
+    /// apart several devices with the same VID/PID
+    #[cfg(all(feature = "hotplug", target_os = "linux"))]
+    pub(crate) fn open_paths(model: Model, vid: u16, pid: u16, paths: &[String]) -> Result<Self> {
+        let api = HidApi::new()?;
+        let (device, device_info) = open_config_device_where(&api, &model, |info| {
+            info.vendor_id() == vid
+                && info.product_id() == pid
+                && paths.contains(&info.path().to_string_lossy().into_owned())
+        })?;
+        let lock = process_lock(device_info)?;
+        let transport = HidTransport::new(device, vid, pid, model.clone());
+
+        Ok(Device::new(Box::new(transport), model, pid, Some(lock)))
+    }
+
+    /// Use a custom transport instead of a real device. Other processes are not locked out.
+    pub fn with_transport(transport: Box<dyn Transport>, model: Model, pid: u16) -> Self {
+        Device::new(transport, model, pid, None)
+    }
+
+    /// Like `with_transport`, but other processes using the device named `key` are locked out
+    /// of each transaction, as they are for a real device
+    pub fn with_locked_transport(
+        transport: Box<dyn Transport>,
+        model: Model,
+        pid: u16,
+        key: &str,
+    ) -> Result<Self> {
+        Ok(Device::new(
+            transport,
+            model,
+            pid,
+            Some(ProcessLock::open(key)?),
+        ))
+    }
+
+    fn new(
+        transport: Box<dyn Transport>,
+        model: Model,
+        pid: u16,
+        process_lock: Option<ProcessLock>,
+    ) -> Self {
+        let retry_policy = RetryPolicy::default();
+
+        Device {
+            pid,
+            model,
+            shared: Arc::new(Shared {
+                transport: ReentrantMutex::new(transport),
+                process_lock,
+                lock_depth: AtomicUsize::new(0),
+                timeout_ms: AtomicI32::new(retry_policy.read_timeout.as_millis() as i32),
+            }),
+            retry_policy,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+
+    /// Change how failed transactions are retried. The policy belongs to this handle,
+    /// the current response timeout is shared with its clones and starts over.
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        self.shared.timeout_ms.store(
+            retry_policy.read_timeout.as_millis() as i32,
+            Ordering::Relaxed,
+        );
+
+        Device {
+            retry_policy,
+            ..self
+        }
+    }
+
+    /// Change how long to wait for another process using the device before failing
+    /// with `MadRError::DeviceBusy`
+    pub fn with_lock_timeout(self, lock_timeout: Duration) -> Self {
+        Device {
+            lock_timeout,
+            ..self
+        }
+    }
+
+    /// Record every report sent to and received from the device to a JSONL file.
+    /// Has to be set up before the device is cloned.
+    pub fn trace(self, path: impl AsRef<Path>) -> Result<Self> {
+        let wired = self.is_wired();
+        let shared = Arc::try_unwrap(self.shared).map_err(|_| {
+            MadRError::Trace("Tracing can't be started on a device that has been cloned".into())
+        })?;
+
+        let transport = shared.transport.into_inner();
+        let tracer = Tracer::create(path, transport, self.model.vid, self.pid, wired)?;
+        Ok(Device {
+            pid: self.pid,
+            model: self.model,
+            retry_policy: self.retry_policy,
+            lock_timeout: self.lock_timeout,
+            shared: Arc::new(Shared {
+                transport: ReentrantMutex::new(Box::new(tracer)),
+                ..shared
+            }),
+        })
+    }
+
+    /// Play back a trace recorded with `trace` as a fake device
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self> {
+        let (replay, vid, pid, wired) = Replay::open(path)?;
+        let model = model::find_model(vid, pid)?.unwrap_or_else(|| Model::generic(vid, pid, wired));
+
+        Ok(Device::with_transport(Box::new(replay), model, pid))
+    }
+
     pub fn is_wired(&self) -> bool {
-        self.wired
+        self.model.wired_pid == Some(self.pid)
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.model.capabilities
+    }
+
+    pub fn product_id(&self) -> u16 {
+        self.pid
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Keep other handles and other processes out of this device until the guard is dropped,
+    /// e.g. for a read-modify-write. Transactions on the calling thread still go through.
+    /// Fails with `MadRError::DeviceBusy` if another process holds the device for longer
+    /// than the lock timeout.
+    pub fn lock(&self) -> Result<DeviceGuard<'_>> {
+        let transport = self.shared.transport.lock();
+
+        if self.shared.lock_depth.load(Ordering::Relaxed) == 0
+            && let Some(lock) = &self.shared.process_lock
+        {
+            lock.acquire(self.lock_timeout)?;
+        }
+        self.shared.lock_depth.fetch_add(1, Ordering::Relaxed);
+
+        Ok(DeviceGuard {
+            transport,
+            shared: &self.shared,
+        })
+    }
+
+    /// Send a request and wait for its response, retrying according to the retry policy
+    pub(crate) fn query(
+        &self,
+        channel: Channel,
+        request: &[u8],
+        command: Command,
+        address: u8,
+    ) -> Result<[u8; REPORT_LEN]> {
+        self.retry(|| {
+            match channel {
+                Channel::Feature => self.send_feature_report(request)?,
+                Channel::Output => {
+                    self.write(request)?;
+                }
+            }
+
+            register::read_response(self, command, address)
+        })
+    }
+
+    /// Send a write report. It is only sent again if sending it failed,
+    /// as the device does not acknowledge writes.
+    pub(crate) fn set(&self, report: &[u8]) -> Result<()> {
+        self.retry(|| self.send_feature_report(report))
+    }
+
+    /// Retries stay under the lock, a late response must not end up with someone else
+    fn retry<T>(&self, mut transaction: impl FnMut() -> Result<T>) -> Result<T> {
+        let guard = self.lock()?;
+        let policy = &self.retry_policy;
+        let mut backoff = policy.backoff;
+        let mut attempt = 1;
+
+        loop {
+            let error = match transaction() {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            let failure = Failure::of(&error);
+            if attempt >= policy.attempts || failure == Failure::Permanent {
+                return Err(error);
+            }
+
+            thread::sleep(backoff);
+            backoff *= 2;
+
+            match failure {
+                Failure::Timeout => {
+                    let max = policy.max_read_timeout.as_millis() as i32;
+                    let timeout_ms = &self.shared.timeout_ms;
+                    let grown = (timeout_ms.load(Ordering::Relaxed) * 2).min(max);
+                    timeout_ms.store(grown, Ordering::Relaxed);
+                }
+                // The device may still be re-enumerating, the next attempt fails again if so
+                Failure::Transport if policy.reconnect => {
+                    let _ = guard.transport.reconnect();
+                }
+                _ => {}
+            }
+
+            attempt += 1;
+        }
+    }
+
+    /// Time to wait for a response
+    pub(crate) fn response_timeout_ms(&self) -> i32 {
+        self.shared.timeout_ms.load(Ordering::Relaxed)
     }
 
     pub(crate) fn send_feature_report(&self, report: &[u8]) -> Result<()> {
-        self.hid.send_feature_report(report)?;
-        Ok(())
+        self.shared.transport.lock().send_feature_report(report)
     }
 
     pub(crate) fn write(&self, data: &[u8]) -> Result<usize> {
-        let size = self.hid.write(data)?;
-        Ok(size)
+        self.shared.transport.lock().write(data)
     }
 
     pub(crate) fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
-        let size = self.hid.read_timeout(buf, timeout_ms)?;
-        Ok(size)
+        let timeout_ms = if self.model.has_quirk(Quirk::SlowResponses) {
+            timeout_ms.max(SLOW_RESPONSE_TIMEOUT_MS)
+        } else {
+            timeout_ms
+        };
+
+        self.shared.transport.lock().read_timeout(buf, timeout_ms)
     }
 }
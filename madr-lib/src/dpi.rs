@@ -1,11 +1,31 @@
 // See documentation/dpi-and-rgb-encoding.md for details on encoding
 
+use std::fmt;
 use std::str::FromStr;
 
-use crate::device::Device;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{Channel, Device};
+use crate::model::Feature;
+use crate::register::{self, Command};
 use crate::{MadRError, Result};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Register holding the DPI values of stages 1 and 2 is at DPI_REGISTER + 0x08,
+/// every following pair of stages is another 0x08 bytes further
+pub(crate) const DPI_REGISTER: u8 = 0x04;
+/// Same layout as the DPI registers, but for the stage colors
+pub(crate) const RGB_REGISTER: u8 = 0x24;
+/// The DPI registers end where the color registers start, after four pairs of stages
+pub(crate) const MAX_STAGES: u8 = 8;
+
+/// DPI values are stored in steps of 50, starting at 50
+pub(crate) const DPI_UNIT: u16 = 50;
+/// Y values have 10 bits, X values 12; both have to fit
+pub(crate) const MAX_DPI: u16 = 1024 * DPI_UNIT;
+
+/// Serialized as "R,G,B", like it is parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub struct Rgb {
     r: u8,
     g: u8,
@@ -16,6 +36,29 @@ impl Rgb {
     pub fn new(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b }
     }
+
+    pub fn r(&self) -> u8 {
+        self.r
+    }
+
+    pub fn g(&self) -> u8 {
+        self.g
+    }
+
+    pub fn b(&self) -> u8 {
+        self.b
+    }
+
+    /// "#rrggbb"
+    pub fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{}", self.r, self.g, self.b)
+    }
 }
 
 impl FromStr for Rgb {
@@ -45,20 +88,52 @@ impl FromStr for Rgb {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct DpiStage {
+impl From<Rgb> for String {
+    fn from(rgb: Rgb) -> Self {
+        rgb.to_string()
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = MadRError;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DpiStage {
     x_dpi: u16,
     y_dpi: u16,
 }
 
 impl DpiStage {
-    fn new(x_dpi: u16, y_dpi: u16) -> Self {
+    pub fn new(x_dpi: u16, y_dpi: u16) -> Self {
         Self { x_dpi, y_dpi }
     }
+
+    pub fn x_dpi(&self) -> u16 {
+        self.x_dpi
+    }
+
+    pub fn y_dpi(&self) -> u16 {
+        self.y_dpi
+    }
+}
+
+impl fmt::Display for DpiStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.x_dpi == self.y_dpi {
+            write!(f, "{}", self.x_dpi)
+        } else {
+            write!(f, "{}x{}", self.x_dpi, self.y_dpi)
+        }
+    }
 }
 
 fn read_dpi_stages(device: &Device, report_index: u8) -> Result<Vec<u8>> {
-    let report_id = 0x04 + (report_index * 0x08);
+    let report_id = DPI_REGISTER + (report_index * 0x08);
 
     let mut request = vec![0u8; 17];
     request[0] = 0x08;
@@ -67,39 +142,55 @@ fn read_dpi_stages(device: &Device, report_index: u8) -> Result<Vec<u8>> {
     request[5] = 0x08;
     request[16] = 0x3Du8.wrapping_sub(report_id);
 
-    device.send_feature_report(&request)?;
+    let response = device.query(Channel::Feature, &request, Command::Read, report_id)?;
+    Ok(response.to_vec())
+}
+
+/// Read the DPI of a single stage
+pub fn read_stage(device: &Device, stage: u8) -> Result<DpiStage> {
+    device.capabilities().check_stage(stage)?;
 
-    let mut buf = [0u8; 17];
-    device.read_timeout(&mut buf, 20)?;
+    let report = read_dpi_stages(device, stage.div_ceil(2))?;
+    let (stage_a, stage_b) = decode_dpi_pair(&report)?;
 
-    Ok(buf.to_vec())
+    Ok(if stage % 2 == 1 { stage_a } else { stage_b })
 }
 
-fn decode_dpi_pair(report: &[u8]) -> (DpiStage, DpiStage) {
-    let decode_dpi = |x_low: u8, y_low: u8, high_container: u8| -> (u16, u16) {
+/// Decode the two stages of a DPI pair report
+pub fn decode_dpi_pair(report: &[u8]) -> Result<(DpiStage, DpiStage)> {
+    register::require_report(report)?;
+
+    let decode_dpi = |x_low: u8, y_low: u8, high_container: u8| -> Result<(u16, u16)> {
         let x_high = (high_container >> 2) & 0x0F;
         let y_high = (high_container >> 6) & 0x03;
 
         let x_val = ((x_high as u16) << 8) | (x_low as u16);
         let y_val = ((y_high as u16) << 8) | (y_low as u16);
 
-        let x_dpi = (x_val + 1) * 50;
-        let y_dpi = (y_val + 1) * 50;
-
-        (x_dpi, y_dpi)
+        // 12 bits for X can encode more than fits in a u16
+        let to_dpi = |val: u16| {
+            (val + 1).checked_mul(50).ok_or_else(|| {
+                MadRError::InvalidDpiSetting(format!(
+                    "Encoded DPI value {:#05x} is out of range",
+                    val
+                ))
+            })
+        };
+
+        Ok((to_dpi(x_val)?, to_dpi(y_val)?))
     };
 
-    let (x_dpi_a, y_dpi_a) = decode_dpi(report[6], report[7], report[8]);
-    let (x_dpi_b, y_dpi_b) = decode_dpi(report[10], report[11], report[12]);
+    let (x_dpi_a, y_dpi_a) = decode_dpi(report[6], report[7], report[8])?;
+    let (x_dpi_b, y_dpi_b) = decode_dpi(report[10], report[11], report[12])?;
 
     let stage_a = DpiStage::new(x_dpi_a, y_dpi_a);
     let stage_b = DpiStage::new(x_dpi_b, y_dpi_b);
 
-    (stage_a, stage_b)
+    Ok((stage_a, stage_b))
 }
 
 fn read_rgb_stages(device: &Device, report_index: u8) -> Result<Vec<u8>> {
-    let report_id = 0x24 + (report_index * 0x08);
+    let report_id = RGB_REGISTER + (report_index * 0x08);
 
     let mut request = vec![0u8; 17];
     request[0] = 0x08;
@@ -108,15 +199,26 @@ fn read_rgb_stages(device: &Device, report_index: u8) -> Result<Vec<u8>> {
     request[5] = 0x08;
     request[16] = 0x3Du8.wrapping_sub(report_id);
 
-    device.send_feature_report(&request)?;
+    let response = device.query(Channel::Feature, &request, Command::Read, report_id)?;
+    Ok(response.to_vec())
+}
+
+/// Read the color of a single stage
+pub fn read_stage_color(device: &Device, stage: u8) -> Result<Rgb> {
+    let capabilities = device.capabilities();
+    capabilities.check_stage(stage)?;
+    capabilities.require(Feature::DpiColors)?;
 
-    let mut buf = [0u8; 17];
-    device.read_timeout(&mut buf, 20)?;
+    let report = read_rgb_stages(device, stage.div_ceil(2))?;
+    let (rgb_a, rgb_b) = decode_rgb_pair(&report)?;
 
-    Ok(buf.to_vec())
+    Ok(if stage % 2 == 1 { rgb_a } else { rgb_b })
 }
 
-fn decode_rgb_pair(response: &[u8]) -> (Rgb, Rgb) {
+/// Decode the two colors of a color pair report
+pub fn decode_rgb_pair(response: &[u8]) -> Result<(Rgb, Rgb)> {
+    register::require_report(response)?;
+
     let decode = |offset: usize| -> Rgb {
         Rgb {
             r: response[offset],
@@ -125,11 +227,11 @@ fn decode_rgb_pair(response: &[u8]) -> (Rgb, Rgb) {
         }
     };
 
-    (decode(6), decode(10))
+    Ok((decode(6), decode(10)))
 }
 
-fn encode_dpi_pair(report_index: u8, stage_a: &DpiStage, stage_b: &DpiStage) -> Vec<u8> {
-    let report_id = 0x04 + (report_index * 0x08);
+pub(crate) fn encode_dpi_pair(report_index: u8, stage_a: &DpiStage, stage_b: &DpiStage) -> Vec<u8> {
+    let report_id = DPI_REGISTER + (report_index * 0x08);
 
     let encode_dpi = |x: u16, y: u16| -> (u8, u8, u8, u8) {
         let x_val = (x / 50).saturating_sub(1);
@@ -175,8 +277,8 @@ fn encode_dpi_pair(report_index: u8, stage_a: &DpiStage, stage_b: &DpiStage) ->
     ]
 }
 
-fn encode_rgb_pair(report_index: u8, rgb_a: &Rgb, rgb_b: &Rgb) -> Vec<u8> {
-    let report_id = 0x24 + (report_index * 0x08);
+pub(crate) fn encode_rgb_pair(report_index: u8, rgb_a: &Rgb, rgb_b: &Rgb) -> Vec<u8> {
+    let report_id = RGB_REGISTER + (report_index * 0x08);
 
     let checksum_a = 0x55u8
         .wrapping_sub(rgb_a.r)
@@ -222,27 +324,34 @@ pub fn apply_dpi_setting(
         ));
     }
 
-    let report_index: u8 = stage.div_ceil(2);
+    let capabilities = device.capabilities();
+    capabilities.check_stage(stage)?;
 
+    // Validate everything up front, so nothing is written unless all of it can be
     if let Some(x_dpi_val) = x_dpi {
-        let is_in_range = |x| x % 50 == 0 && (100..=30000).contains(&x);
+        capabilities.check_dpi(x_dpi_val)?;
+    }
 
-        if !is_in_range(x_dpi_val) {
-            return Err(MadRError::InvalidDpiSetting(
-                "X DPI must be between 100 and 30000 and a multiple of 50".into(),
-            ));
-        }
+    if let Some(y_dpi_val) = y_dpi {
+        capabilities.check_dpi(y_dpi_val)?;
+    }
 
-        if let Some(y_dpi_val) = y_dpi
-            && !is_in_range(y_dpi_val)
-        {
-            return Err(MadRError::InvalidDpiSetting(
-                "Y DPI must be between 100 and 30000 and a multiple of 50".into(),
-            ));
+    let parsed_rgb = match rgb {
+        Some(rgb_str) => {
+            capabilities.require(Feature::DpiColors)?;
+            Some(Rgb::from_str(rgb_str)?)
         }
+        None => None,
+    };
 
+    let report_index: u8 = stage.div_ceil(2);
+
+    // The other stage of each pair is written back as read
+    let _guard = device.lock()?;
+
+    if let Some(x_dpi_val) = x_dpi {
         let dpi_stages = read_dpi_stages(device, report_index)?;
-        let (mut stage_a, mut stage_b) = decode_dpi_pair(&dpi_stages);
+        let (mut stage_a, mut stage_b) = decode_dpi_pair(&dpi_stages)?;
 
         if stage % 2 == 1 {
             stage_a.x_dpi = x_dpi_val;
@@ -253,14 +362,12 @@ pub fn apply_dpi_setting(
         }
 
         let dpi_report = encode_dpi_pair(report_index, &stage_a, &stage_b);
-        device.send_feature_report(&dpi_report)?;
+        device.set(&dpi_report)?;
     };
 
-    if let Some(rgb_str) = rgb {
-        let parsed = Rgb::from_str(rgb_str)?;
-
+    if let Some(parsed) = parsed_rgb {
         let rgb_stages = read_rgb_stages(device, report_index)?;
-        let (mut rgb_a, mut rgb_b) = decode_rgb_pair(&rgb_stages);
+        let (mut rgb_a, mut rgb_b) = decode_rgb_pair(&rgb_stages)?;
 
         if stage % 2 == 1 {
             rgb_a = parsed;
@@ -269,7 +376,7 @@ pub fn apply_dpi_setting(
         }
 
         let rgb_report = encode_rgb_pair(report_index, &rgb_a, &rgb_b);
-        device.send_feature_report(&rgb_report)?;
+        device.set(&rgb_report)?;
     };
 
     Ok(())
@@ -0,0 +1,207 @@
+// Emulated MAD R
+// Serves the configuration protocol from an in-memory register space, for testing without a mouse:
+//   write (0x07)    payload is stored at the register address, no response
+//   read (0x08)     responds with the requested registers as an input report
+//   battery (0x04)  responds with the emulated battery status
+// Reports with a bad checksum are ignored.
+// The emulator starts out with factory settings, written through the same reports madr-lib sends.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::Result;
+use crate::debounce::{self, Debounce};
+use crate::device::{Device, Transport};
+use crate::dpi::{self, DpiStage, Rgb};
+use crate::model;
+use crate::performance::{self, PollingRate};
+use crate::register::{self, Command, MAX_PAYLOAD_LEN, REPORT_ID, REPORT_LEN};
+use crate::sensor::{self, Mode};
+
+/// Number of addressable registers
+const REGISTER_COUNT: usize = 0x100;
+
+/// 60 seconds, in tens of seconds
+const FACTORY_SLEEP: u8 = 0x06;
+const FACTORY_DPI: [u16; 8] = [400, 800, 1600, 3200, 6400, 12800, 25600, 30000];
+const FACTORY_COLORS: [(u8, u8, u8); 8] = [
+    (255, 0, 0),
+    (0, 255, 0),
+    (0, 0, 255),
+    (255, 255, 0),
+    (0, 255, 255),
+    (255, 0, 255),
+    (255, 255, 255),
+    (255, 128, 0),
+];
+
+#[derive(Debug)]
+struct State {
+    registers: [u8; REGISTER_COUNT],
+    battery_percentage: u8,
+    battery_charging: bool,
+    battery_voltage_mv: u16,
+    /// Input reports waiting to be read
+    pending: VecDeque<Vec<u8>>,
+}
+
+/// In-memory MAD R, usable directly as a `Transport`. Clones share the same state,
+/// so a clone can be kept around to inspect the registers after handing one to a `Device`.
+#[derive(Debug, Clone)]
+pub struct Emulator {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    /// Emulated mouse with factory settings
+    pub fn new() -> Self {
+        let emulator = Self {
+            state: Arc::new(Mutex::new(State {
+                registers: [0; REGISTER_COUNT],
+                battery_percentage: 100,
+                battery_charging: false,
+                battery_voltage_mv: 4100,
+                pending: VecDeque::new(),
+            })),
+        };
+
+        let mut factory = vec![
+            performance::make_combined_report(1, PollingRate::Hz1000),
+            debounce::get_debounce_report(Debounce::default(), FACTORY_SLEEP),
+            sensor::get_magic_report(Mode::default(), FACTORY_SLEEP),
+        ];
+
+        for index in 1..=(FACTORY_DPI.len() / 2) as u8 {
+            let stage = |i: u8| DpiStage::new(FACTORY_DPI[i as usize], FACTORY_DPI[i as usize]);
+            let color = |i: u8| {
+                let (r, g, b) = FACTORY_COLORS[i as usize];
+                Rgb::new(r, g, b)
+            };
+
+            let (a, b) = (index * 2 - 2, index * 2 - 1);
+            factory.push(dpi::encode_dpi_pair(index, &stage(a), &stage(b)));
+            factory.push(dpi::encode_rgb_pair(index, &color(a), &color(b)));
+        }
+
+        for report in factory {
+            emulator.handle(&report);
+        }
+
+        emulator
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Process a feature or output report sent by the host, queueing the response if there is one
+    pub fn handle(&self, report: &[u8]) {
+        if report.len() < REPORT_LEN
+            || report[0] != REPORT_ID
+            || register::checksum(report) != report[REPORT_LEN - 1]
+        {
+            return;
+        }
+
+        let mut state = self.state();
+        let address = report[4] as usize;
+        let len = report[5].min(MAX_PAYLOAD_LEN) as usize;
+
+        let response = match Command::try_from(report[1]) {
+            Ok(Command::Write) => {
+                for (i, value) in report[6..6 + len].iter().enumerate() {
+                    if let Some(register) = state.registers.get_mut(address + i) {
+                        *register = *value;
+                    }
+                }
+                None
+            }
+            Ok(Command::Read) => {
+                let payload: Vec<u8> = (0..len)
+                    .map(|i| state.registers.get(address + i).copied().unwrap_or(0))
+                    .collect();
+                register::build_report(Command::Read, address as u8, &payload).ok()
+            }
+            Ok(Command::Battery) => {
+                let [voltage_high, voltage_low] = state.battery_voltage_mv.to_be_bytes();
+                let payload = [
+                    state.battery_percentage,
+                    state.battery_charging as u8,
+                    voltage_high,
+                    voltage_low,
+                ];
+                register::build_report(Command::Battery, 0, &payload).ok()
+            }
+            Err(_) => None,
+        };
+
+        if let Some(response) = response {
+            state.pending.push_back(response);
+        }
+    }
+
+    /// Next input report for the host, if any
+    pub fn next_input(&self) -> Option<Vec<u8>> {
+        self.state().pending.pop_front()
+    }
+
+    /// Handle to this emulator as the built-in MAD R, connected by cable if `wired`
+    pub fn device(&self, wired: bool) -> Device {
+        device(Box::new(self.clone()), wired)
+    }
+
+    /// Copy of the whole register space
+    pub fn registers(&self) -> Vec<u8> {
+        self.state().registers.to_vec()
+    }
+
+    pub fn set_battery(&self, percentage: u8, charging: bool, voltage_mv: u16) {
+        let mut state = self.state();
+        state.battery_percentage = percentage;
+        state.battery_charging = charging;
+        state.battery_voltage_mv = voltage_mv;
+    }
+}
+
+/// `transport` as the built-in MAD R, connected by cable if `wired`. For an emulator wrapped
+/// in another transport, e.g. a `FaultyTransport`; otherwise use `Emulator::device`.
+pub fn device(transport: Box<dyn Transport>, wired: bool) -> Device {
+    let model = model::builtin_models().remove(0);
+    let pid = if wired {
+        model.wired_pid
+    } else {
+        model.wireless_pid
+    };
+    let pid = pid.expect("built-in MAD R has both product IDs");
+
+    Device::with_transport(transport, model, pid)
+}
+
+impl Transport for Emulator {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        self.handle(report);
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.handle(data);
+        Ok(data.len())
+    }
+
+    /// Never blocks, an empty queue behaves like a timeout
+    fn read_timeout(&self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize> {
+        let Some(report) = self.next_input() else {
+            return Ok(0);
+        };
+
+        let size = report.len().min(buf.len());
+        buf[..size].copy_from_slice(&report[..size]);
+        Ok(size)
+    }
+}
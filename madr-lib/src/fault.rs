@@ -0,0 +1,166 @@
+// Fault injection for robustness testing
+// Wraps a transport (usually an `Emulator`) and breaks individual transport calls the way
+// a bad wireless link does. Calls are numbered from 0 in the order they are made, and a
+// fault can be scheduled for any of them, so tests can try every fault at every point of
+// a multi-report operation.
+
+use std::collections::{HashMap, VecDeque};
+#[cfg(feature = "hidraw")]
+use std::io;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::device::Transport;
+use crate::{MadRError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fault {
+    /// The call fails without reaching the device
+    Error,
+    /// The response is lost, the read times out
+    DropResponse,
+    /// Only the first half of the response arrives
+    TruncateResponse,
+    /// The trailing checksum of the response is wrong
+    CorruptChecksum,
+    /// The previous response is delivered again, the actual one arrives on the next read
+    StaleResponse,
+}
+
+impl Fault {
+    pub const ALL: [Fault; 5] = [
+        Fault::Error,
+        Fault::DropResponse,
+        Fault::TruncateResponse,
+        Fault::CorruptChecksum,
+        Fault::StaleResponse,
+    ];
+}
+
+#[derive(Debug, Default)]
+struct State {
+    calls: usize,
+    faults: HashMap<usize, Fault>,
+    /// Responses held back by `StaleResponse`
+    delayed: VecDeque<Vec<u8>>,
+    last_response: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct FaultyTransport {
+    inner: Box<dyn Transport>,
+    state: Mutex<State>,
+}
+
+impl FaultyTransport {
+    pub fn new(inner: Box<dyn Transport>) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Inject `fault` into the call with index `call`
+    pub fn inject(self, call: usize, fault: Fault) -> Self {
+        self.state().faults.insert(call, fault);
+        self
+    }
+
+    /// Number of calls made so far
+    pub fn calls(&self) -> usize {
+        self.state().calls
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Fault scheduled for the current call, if any
+    fn next_fault(&self) -> Option<Fault> {
+        let mut state = self.state();
+        let call = state.calls;
+        state.calls += 1;
+        state.faults.get(&call).copied()
+    }
+}
+
+/// The error a failed call on the real transport returns, a `HidError` through hidapi
+#[cfg(not(feature = "hidraw"))]
+fn injected_error() -> MadRError {
+    MadRError::HidApiInit(hidapi::HidError::HidApiError {
+        message: "injected fault".into(),
+    })
+}
+
+/// The error a failed call on the real transport returns, an I/O error on the hidraw node
+#[cfg(feature = "hidraw")]
+fn injected_error() -> MadRError {
+    MadRError::Io(io::Error::other("injected fault"))
+}
+
+impl Transport for FaultyTransport {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        match self.next_fault() {
+            Some(Fault::Error) => Err(injected_error()),
+            _ => self.inner.send_feature_report(report),
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        match self.next_fault() {
+            Some(Fault::Error) => Err(injected_error()),
+            _ => self.inner.write(data),
+        }
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        let fault = self.next_fault();
+        if fault == Some(Fault::Error) {
+            return Err(injected_error());
+        }
+
+        let delayed = self.state().delayed.pop_front();
+        let mut response = match delayed {
+            Some(response) => response,
+            None => {
+                let mut inner_buf = vec![0u8; buf.len()];
+                let size = self.inner.read_timeout(&mut inner_buf, timeout_ms)?;
+                inner_buf.truncate(size);
+                inner_buf
+            }
+        };
+
+        if response.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.state();
+        match fault {
+            Some(Fault::DropResponse) => response.clear(),
+            Some(Fault::TruncateResponse) => response.truncate(response.len() / 2),
+            Some(Fault::CorruptChecksum) => {
+                if let Some(last) = response.last_mut() {
+                    *last ^= 0xFF;
+                }
+            }
+            Some(Fault::StaleResponse) => {
+                if let Some(last) = state.last_response.clone() {
+                    state.delayed.push_front(response);
+                    response = last;
+                }
+            }
+            Some(Fault::Error) | None => {}
+        }
+
+        if !response.is_empty() {
+            state.last_response = Some(response.clone());
+        }
+
+        let size = response.len().min(buf.len());
+        buf[..size].copy_from_slice(&response[..size]);
+        Ok(size)
+    }
+
+    fn reconnect(&self) -> Result<()> {
+        self.inner.reconnect()
+    }
+}
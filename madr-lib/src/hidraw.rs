@@ -0,0 +1,265 @@
+// Native Linux hidraw backend, enabled with the `hidraw` feature
+// Mirrors the small part of the hidapi API used by `device`, so that madr-lib can be
+// built without any C dependencies:
+//   enumeration   /sys/class/hidraw/hidrawN/device/uevent (HID_ID, HID_UNIQ)
+//   feature       HIDIOCSFEATURE / HIDIOCGFEATURE ioctls on /dev/hidrawN
+//   input/output  plain read(2) / write(2) on /dev/hidrawN
+// See Documentation/hid/hidraw.rst in the kernel tree.
+
+use std::ffi::c_ulong;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::device::Transport;
+use crate::{MadRError, Result};
+
+const SYSFS_HIDRAW: &str = "/sys/class/hidraw";
+
+/// Bus type of USB devices in HID_ID
+const BUS_USB: u32 = 0x03;
+
+/// Largest report descriptor the kernel hands out (HID_MAX_DESCRIPTOR_SIZE)
+pub const MAX_REPORT_DESCRIPTOR_SIZE: usize = 4096;
+
+// ioctl request encoding from asm-generic/ioctl.h
+const IOC_WRITE: c_ulong = 1;
+const IOC_READ: c_ulong = 2;
+
+const fn ioc(dir: c_ulong, nr: c_ulong, size: usize) -> c_ulong {
+    (dir << 30) | ((size as c_ulong) << 16) | ((b'H' as c_ulong) << 8) | nr
+}
+
+const fn hidiocgrdescsize() -> c_ulong {
+    ioc(IOC_READ, 0x01, size_of::<libc::c_int>())
+}
+
+const fn hidiocgrdesc() -> c_ulong {
+    ioc(IOC_READ, 0x02, size_of::<ReportDescriptorBuf>())
+}
+
+const fn hidiocsfeature(len: usize) -> c_ulong {
+    ioc(IOC_WRITE | IOC_READ, 0x06, len)
+}
+
+const fn hidiocgfeature(len: usize) -> c_ulong {
+    ioc(IOC_WRITE | IOC_READ, 0x07, len)
+}
+
+/// struct hidraw_report_descriptor
+#[repr(C)]
+struct ReportDescriptorBuf {
+    size: u32,
+    value: [u8; MAX_REPORT_DESCRIPTOR_SIZE],
+}
+
+/// Run an ioctl that takes a pointer argument, returning its non-negative result
+fn ioctl<T>(file: &File, request: c_ulong, arg: *mut T) -> Result<usize> {
+    // SAFETY: `arg` points to a buffer at least as large as the size encoded in `request`
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(ret as usize)
+}
+
+/// Counterpart of `hidapi::HidApi`, holds the list of hidraw nodes found in sysfs
+#[derive(Debug)]
+pub struct HidrawApi {
+    devices: Vec<DeviceInfo>,
+}
+
+impl HidrawApi {
+    pub fn new() -> Result<Self> {
+        let mut devices = Vec::new();
+
+        let entries = match fs::read_dir(SYSFS_HIDRAW) {
+            Ok(entries) => entries,
+            // No hidraw driver loaded, so no devices either
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self { devices }),
+            Err(e) => return Err(e.into()),
+        };
+
+        // A node that goes away or can't be read mid-enumeration doesn't hide the others
+        for entry in entries.flatten() {
+            if let Ok(Some(info)) = DeviceInfo::from_sysfs(&entry.path()) {
+                devices.push(info);
+            }
+        }
+
+        devices.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Self { devices })
+    }
+
+    pub fn device_list(&self) -> impl Iterator<Item = &DeviceInfo> {
+        self.devices.iter()
+    }
+}
+
+/// Counterpart of `hidapi::DeviceInfo`
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    path: PathBuf,
+    vendor_id: u16,
+    product_id: u16,
+    interface_number: i32,
+    serial_number: Option<String>,
+}
+
+impl DeviceInfo {
+    /// Read the IDs of /sys/class/hidraw/hidrawN, `None` if its uevent has no HID_ID
+    fn from_sysfs(sysfs: &Path) -> Result<Option<Self>> {
+        let Some(name) = sysfs.file_name() else {
+            return Ok(None);
+        };
+
+        let hid = sysfs.join("device");
+        let uevent = fs::read_to_string(hid.join("uevent"))?;
+
+        let mut id = None;
+        let mut serial_number = None;
+        for line in uevent.lines() {
+            if let Some(value) = line.strip_prefix("HID_ID=") {
+                id = parse_hid_id(value);
+            } else if let Some(value) = line.strip_prefix("HID_UNIQ=") {
+                serial_number = Some(value.to_string()).filter(|s| !s.is_empty());
+            }
+        }
+
+        let Some((bus, vendor_id, product_id)) = id else {
+            return Ok(None);
+        };
+
+        // The parent of a USB HID device is its USB interface
+        let interface_number = if bus == BUS_USB {
+            fs::read_to_string(hid.join("..").join("bInterfaceNumber"))
+                .ok()
+                .and_then(|n| i32::from_str_radix(n.trim(), 16).ok())
+                .unwrap_or(-1)
+        } else {
+            -1
+        };
+
+        Ok(Some(Self {
+            path: Path::new("/dev").join(name),
+            vendor_id,
+            product_id,
+            interface_number,
+            serial_number,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    pub fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    /// USB interface number, -1 for devices on other buses
+    pub fn interface_number(&self) -> i32 {
+        self.interface_number
+    }
+
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    pub fn open_device(&self, _api: &HidrawApi) -> io::Result<HidrawDevice> {
+        HidrawDevice::open(&self.path)
+    }
+}
+
+/// Parse "0003:0000373B:00001040" into bus, vendor and product ID
+fn parse_hid_id(value: &str) -> Option<(u32, u16, u16)> {
+    let mut parts = value.split(':');
+    let bus = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let vid = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let pid = u32::from_str_radix(parts.next()?, 16).ok()?;
+
+    Some((bus, vid as u16, pid as u16))
+}
+
+/// An open /dev/hidrawN node
+#[derive(Debug)]
+pub struct HidrawDevice {
+    file: File,
+}
+
+impl HidrawDevice {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    /// Copy the report descriptor into `buf`, returning its size
+    pub fn get_report_descriptor(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut size: libc::c_int = 0;
+        ioctl(&self.file, hidiocgrdescsize(), &mut size)?;
+
+        let mut descriptor = Box::new(ReportDescriptorBuf {
+            size: size as u32,
+            value: [0; MAX_REPORT_DESCRIPTOR_SIZE],
+        });
+        ioctl(&self.file, hidiocgrdesc(), &mut *descriptor)?;
+
+        let size = (descriptor.size as usize).min(buf.len());
+        buf[..size].copy_from_slice(&descriptor.value[..size]);
+        Ok(size)
+    }
+
+    /// Read a feature report, `buf[0]` must hold the report ID
+    pub fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        ioctl(&self.file, hidiocgfeature(buf.len()), buf.as_mut_ptr())
+    }
+}
+
+impl Transport for HidrawDevice {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        // The kernel only reads from the buffer despite the ioctl being read/write
+        let mut buf = report.to_vec();
+        ioctl(&self.file, hidiocsfeature(buf.len()), buf.as_mut_ptr())?;
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        let size = (&self.file).write(data)?;
+        Ok(size)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        let mut pollfd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        // SAFETY: `pollfd` is a single valid pollfd
+        let ready = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+        if ready < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        // Timed out, same as hidapi
+        if ready == 0 {
+            return Ok(0);
+        }
+
+        if pollfd.revents & (libc::POLLERR | libc::POLLHUP) != 0 {
+            return Err(MadRError::Io(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "hidraw device disconnected",
+            )));
+        }
+
+        let size = (&self.file).read(buf)?;
+        Ok(size)
+    }
+}
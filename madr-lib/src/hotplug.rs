@@ -0,0 +1,334 @@
+// Hotplug monitoring, enabled with the `hotplug` feature. Linux only
+// Listens to the udev netlink socket (the one libudev monitors use) for hidraw nodes being
+// added or removed, so events arrive after udev has set up the node and it can be opened.
+// A mouse or receiver has several hidraw nodes, one per interface; they are grouped by the
+// device they belong to and reported as one `Connected` and one `Disconnected` event.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+use crate::model::{self, Model};
+use crate::{Device, MadRError, Result};
+
+/// Netlink multicast group udev rebroadcasts processed events to
+const UDEV_GROUP: u32 = 2;
+
+/// Messages on the udev group start with this prefix, followed by the rest of the header
+const UDEV_PREFIX: &[u8] = b"libudev\0";
+const UDEV_MAGIC: u32 = 0xfeedcafe;
+/// sizeof(struct udev_monitor_netlink_header)
+const UDEV_HEADER_SIZE: usize = 40;
+
+const MESSAGE_BUFFER_SIZE: usize = 16 * 1024;
+
+/// A supported mouse or receiver
+#[derive(Debug, Clone)]
+pub struct HotplugDevice {
+    pub vid: u16,
+    pub pid: u16,
+    pub model: Model,
+    /// sysfs path of the device, without the /sys prefix
+    pub devpath: String,
+}
+
+impl HotplugDevice {
+    pub fn is_wired(&self) -> bool {
+        self.model.wired_pid == Some(self.pid)
+    }
+
+    /// Open this device through its own hidraw nodes, and not another one with the same
+    /// VID/PID
+    pub fn open(&self) -> Result<Device> {
+        let prefix = format!("{}/", self.devpath);
+        let paths: Vec<String> = hidraw_nodes()
+            .into_iter()
+            .filter(|(_, devpath)| devpath.starts_with(&prefix))
+            .map(|(name, _)| format!("/dev/{}", name))
+            .collect();
+
+        if paths.is_empty() {
+            return Err(MadRError::DeviceNotFound);
+        }
+
+        Device::open_paths(self.model.clone(), self.vid, self.pid, &paths)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Connected(HotplugDevice),
+    Disconnected(HotplugDevice),
+}
+
+/// Parse the name of a HID device in sysfs, e.g. "0003:373B:1040.0005", into VID and PID.
+/// Every field has exactly four hex digits, which tells it apart from e.g. PCI addresses.
+pub fn parse_hid_name(name: &str) -> Option<(u16, u16)> {
+    let (id, instance) = name.split_once('.')?;
+    let fields: Vec<&str> = id.split(':').chain([instance]).collect();
+
+    let is_field = |f: &&str| f.len() == 4 && f.chars().all(|c| c.is_ascii_hexdigit());
+    if fields.len() != 4 || !fields.iter().all(is_field) {
+        return None;
+    }
+
+    let vid = u16::from_str_radix(fields[1], 16).ok()?;
+    let pid = u16::from_str_radix(fields[2], 16).ok()?;
+    Some((vid, pid))
+}
+
+/// Path of the device a hidraw node belongs to, and its VID and PID. The interfaces of a
+/// USB device (e.g. 1-2:1.0 and 1-2:1.1) each have their own HID device, all other HID
+/// devices are their own device.
+pub fn device_of(devpath: &str) -> Option<(String, u16, u16)> {
+    let components: Vec<&str> = devpath.split('/').collect();
+    let (index, (vid, pid)) = components
+        .iter()
+        .enumerate()
+        .find_map(|(i, c)| parse_hid_name(c).map(|id| (i, id)))?;
+
+    let end = if index > 0 && components[index - 1].contains(':') {
+        index - 1
+    } else {
+        index + 1
+    };
+
+    Some((components[..end].join("/"), vid, pid))
+}
+
+/// Properties of a udev netlink message, `None` for anything else
+pub fn parse_message(message: &[u8]) -> Option<HashMap<&str, &str>> {
+    if message.len() < UDEV_HEADER_SIZE || !message.starts_with(UDEV_PREFIX) {
+        return None;
+    }
+
+    let field = |offset: usize| {
+        u32::from_ne_bytes([
+            message[offset],
+            message[offset + 1],
+            message[offset + 2],
+            message[offset + 3],
+        ])
+    };
+
+    if u32::from_be(field(8)) != UDEV_MAGIC {
+        return None;
+    }
+
+    let offset = field(16) as usize;
+    let len = field(20) as usize;
+    let properties = message.get(offset..offset.checked_add(len)?)?;
+
+    Some(
+        properties
+            .split(|b| *b == 0)
+            .filter_map(|p| std::str::from_utf8(p).ok()?.split_once('='))
+            .collect(),
+    )
+}
+
+/// Names and devpaths of the hidraw nodes that exist right now
+fn hidraw_nodes() -> Vec<(String, String)> {
+    let Ok(entries) = fs::read_dir("/sys/class/hidraw") else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let path = fs::canonicalize(entry.path()).ok()?;
+            let devpath = format!("/{}", path.strip_prefix("/sys").ok()?.to_string_lossy());
+            Some((entry.file_name().to_string_lossy().into_owned(), devpath))
+        })
+        .collect()
+}
+
+/// Watches for supported devices being connected and disconnected
+#[derive(Debug)]
+pub struct Watcher {
+    socket: OwnedFd,
+    models: Vec<Model>,
+    /// Connected devices by path, with the devpaths of their hidraw nodes
+    devices: HashMap<String, (HotplugDevice, HashSet<String>)>,
+}
+
+impl Watcher {
+    /// Start watching. Devices that are already connected don't cause an event,
+    /// see `connected`.
+    pub fn new() -> Result<Self> {
+        // SAFETY: plain socket(2) call, the descriptor is owned right after
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // SAFETY: `fd` is a freshly created descriptor nobody else owns
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl is plain data, all zeroes is a valid value
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = UDEV_GROUP;
+
+        // SAFETY: `addr` is a valid sockaddr_nl and its size is passed along
+        let bound = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut watcher = Self {
+            socket,
+            models: model::all_models()?,
+            devices: HashMap::new(),
+        };
+
+        for (_, devpath) in hidraw_nodes() {
+            watcher.handle("add", &devpath);
+        }
+
+        Ok(watcher)
+    }
+
+    /// Supported devices that are connected right now
+    pub fn connected(&self) -> Vec<HotplugDevice> {
+        let mut devices: Vec<HotplugDevice> =
+            self.devices.values().map(|(d, _)| d.clone()).collect();
+        devices.sort_by(|a, b| a.devpath.cmp(&b.devpath));
+        devices
+    }
+
+    /// Wait for the next event, at most `timeout` if given. `None` if it timed out.
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<Event>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut buf = vec![0u8; MESSAGE_BUFFER_SIZE];
+
+        loop {
+            let timeout_ms = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    left.as_millis().min(i32::MAX as u128) as i32
+                }
+                None => -1,
+            };
+
+            let mut pollfd = libc::pollfd {
+                fd: self.socket.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            // SAFETY: `pollfd` is a single valid pollfd
+            let ready = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+            if ready < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error.into());
+            }
+
+            if ready == 0 {
+                return Ok(None);
+            }
+
+            // SAFETY: `buf` is valid for writes of its length
+            let size = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if size < 0 {
+                let error = io::Error::last_os_error();
+                // ENOBUFS means events were lost because the receive buffer overflowed,
+                // there is nothing to do about them but to go on
+                if error.kind() == io::ErrorKind::Interrupted
+                    || error.raw_os_error() == Some(libc::ENOBUFS)
+                {
+                    continue;
+                }
+                return Err(error.into());
+            }
+
+            let Some(properties) = parse_message(&buf[..size as usize]) else {
+                continue;
+            };
+
+            if properties.get("SUBSYSTEM") != Some(&"hidraw") {
+                continue;
+            }
+
+            let (Some(action), Some(devpath)) =
+                (properties.get("ACTION"), properties.get("DEVPATH"))
+            else {
+                continue;
+            };
+
+            let (action, devpath) = (action.to_string(), devpath.to_string());
+            if let Some(event) = self.handle(&action, &devpath) {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Update the connected devices with a hidraw node being added or removed
+    fn handle(&mut self, action: &str, devpath: &str) -> Option<Event> {
+        let (path, vid, pid) = device_of(devpath)?;
+
+        match action {
+            "add" => {
+                if let Some((_, nodes)) = self.devices.get_mut(&path) {
+                    nodes.insert(devpath.to_string());
+                    return None;
+                }
+
+                let model = self.models.iter().find(|m| m.matches(vid, pid))?.clone();
+                let device = HotplugDevice {
+                    vid,
+                    pid,
+                    model,
+                    devpath: path.clone(),
+                };
+
+                let nodes = HashSet::from([devpath.to_string()]);
+                self.devices.insert(path, (device.clone(), nodes));
+                Some(Event::Connected(device))
+            }
+            "remove" => {
+                let (_, nodes) = self.devices.get_mut(&path)?;
+                nodes.remove(devpath);
+                if !nodes.is_empty() {
+                    return None;
+                }
+
+                let (device, _) = self.devices.remove(&path)?;
+                Some(Event::Disconnected(device))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event(None).transpose()
+    }
+}
@@ -0,0 +1,483 @@
+// Local IPC with madrd. Unix only
+// madrd owns the device and serves JSON-RPC 2.0 over a Unix socket, one request or response
+// object per line. Every method is a madr-lib operation, with named params:
+//   info                                       -> DeviceInfo
+//   battery                                    -> Battery
+//   performance                                -> Performance
+//   set_performance   {dpi_stage, polling_rate}
+//   sensor                                     -> Sensor
+//   set_sensor_mode   {mode}
+//   read_stage        {stage}                  -> DpiStage
+//   read_stage_color  {stage}                  -> "R,G,B"
+//   set_dpi           {stage, x_dpi?, y_dpi?, rgb?}
+//   debounce                                   -> u8
+//   set_debounce      {debounce}
+//   sleep                                      -> seconds
+//   set_sleep         {seconds}
+//   apply_profile     {profile}                -> [Change]
+//   read_registers    {address, len}           -> [u8]
+//   snapshot                                   -> [u8; 256]
+// For example:
+//   -> {"jsonrpc":"2.0","id":1,"method":"set_dpi","params":{"stage":2,"x_dpi":1600}}
+//   <- {"jsonrpc":"2.0","id":1,"result":null}
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::battery::Battery;
+use crate::debounce::{self, Debounce};
+use crate::device::Device;
+use crate::dpi::{self, DpiStage, Rgb};
+use crate::model::Model;
+use crate::performance::{self, Performance, PollingRate};
+use crate::profile::{Change, Profile};
+use crate::register::{self, Snapshot};
+use crate::sensor::{self, Mode, Sensor};
+use crate::{MadRError, Result, lock, sleep};
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// How long the client waits for madrd to answer, which includes waiting for the device
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Path of the socket madrd listens on
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(lock::runtime_dir()?.join("madrd.sock"))
+}
+
+/// What is connected to madrd
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub model: Model,
+    /// `Model::verified`, which is not part of the model when serialized
+    pub verified: bool,
+    pub product_id: u16,
+    pub wired: bool,
+}
+
+impl DeviceInfo {
+    pub fn of(device: &Device) -> Self {
+        Self {
+            model: device.model().clone(),
+            verified: device.model().verified,
+            product_id: device.product_id(),
+            wired: device.is_wired(),
+        }
+    }
+}
+
+/// A madr-lib operation, serialized as the method and params of a JSON-RPC request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+    Info,
+    Battery,
+    Performance,
+    SetPerformance {
+        dpi_stage: u8,
+        polling_rate: PollingRate,
+    },
+    Sensor,
+    SetSensorMode {
+        mode: Mode,
+    },
+    ReadStage {
+        stage: u8,
+    },
+    ReadStageColor {
+        stage: u8,
+    },
+    SetDpi {
+        stage: u8,
+        x_dpi: Option<u16>,
+        y_dpi: Option<u16>,
+        rgb: Option<Rgb>,
+    },
+    Debounce,
+    SetDebounce {
+        debounce: Debounce,
+    },
+    Sleep,
+    SetSleep {
+        seconds: u64,
+    },
+    ApplyProfile {
+        profile: Profile,
+    },
+    ReadRegisters {
+        address: u8,
+        len: u8,
+    },
+    Snapshot,
+}
+
+fn json(value: impl Serialize) -> Result<Value> {
+    serde_json::to_value(value).map_err(|e| MadRError::Daemon(e.to_string()))
+}
+
+impl Request {
+    pub const METHODS: &[&str] = &[
+        "info",
+        "battery",
+        "performance",
+        "set_performance",
+        "sensor",
+        "set_sensor_mode",
+        "read_stage",
+        "read_stage_color",
+        "set_dpi",
+        "debounce",
+        "set_debounce",
+        "sleep",
+        "set_sleep",
+        "apply_profile",
+        "read_registers",
+        "snapshot",
+    ];
+
+    /// Whether the request changes settings on the device
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Request::SetPerformance { .. }
+                | Request::SetSensorMode { .. }
+                | Request::SetDpi { .. }
+                | Request::SetDebounce { .. }
+                | Request::SetSleep { .. }
+                | Request::ApplyProfile { .. }
+        )
+    }
+
+    /// Run the operation on `device`
+    pub fn execute(&self, device: &Device) -> Result<Value> {
+        match self {
+            Request::Info => json(DeviceInfo::of(device)),
+            Request::Battery => json(Battery::read(device)?),
+            Request::Performance => json(Performance::read(device)?),
+            Request::SetPerformance {
+                dpi_stage,
+                polling_rate,
+            } => json(performance::apply_setting(
+                device,
+                &Performance::new(*dpi_stage, *polling_rate),
+            )?),
+            Request::Sensor => json(Sensor::read(device)?),
+            Request::SetSensorMode { mode } => json(sensor::apply_setting(device, *mode)?),
+            Request::ReadStage { stage } => json(dpi::read_stage(device, *stage)?),
+            Request::ReadStageColor { stage } => json(dpi::read_stage_color(device, *stage)?),
+            Request::SetDpi {
+                stage,
+                x_dpi,
+                y_dpi,
+                rgb,
+            } => {
+                let rgb = rgb.map(|c| c.to_string());
+                json(dpi::apply_dpi_setting(
+                    device,
+                    *stage,
+                    *x_dpi,
+                    *y_dpi,
+                    rgb.as_deref(),
+                )?)
+            }
+            Request::Debounce => json(debounce::read(device)?),
+            Request::SetDebounce { debounce } => json(debounce::apply_setting(device, *debounce)?),
+            Request::Sleep => json(sleep::read(device)?.as_secs()),
+            Request::SetSleep { seconds } => {
+                json(sleep::apply_setting(device, Duration::from_secs(*seconds))?)
+            }
+            Request::ApplyProfile { profile } => json(profile.apply(device)?),
+            Request::ReadRegisters { address, len } => {
+                json(register::read(device, *address, *len)?)
+            }
+            Request::Snapshot => json(Snapshot::capture(device)?.as_bytes()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// The operation failed on the device
+    pub const DEVICE_ERROR: i64 = -32000;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<MadRError> for RpcError {
+    fn from(error: MadRError) -> Self {
+        Self::new(Self::DEVICE_ERROR, error.to_string())
+    }
+}
+
+/// JSON-RPC request envelope
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+impl RpcRequest {
+    pub fn new(id: u64, request: &Request) -> Result<Self> {
+        let Value::Object(mut fields) = json(request)? else {
+            return Err(MadRError::Daemon("request is not an object".into()));
+        };
+
+        let method = match fields.remove("method") {
+            Some(Value::String(method)) => method,
+            _ => return Err(MadRError::Daemon("request has no method".into())),
+        };
+
+        Ok(Self {
+            jsonrpc: JSONRPC_VERSION.into(),
+            id: id.into(),
+            method,
+            params: fields.remove("params").unwrap_or(Value::Null),
+        })
+    }
+
+    /// The operation this envelope carries
+    pub fn request(&self) -> std::result::Result<Request, RpcError> {
+        if self.jsonrpc != JSONRPC_VERSION {
+            return Err(RpcError::new(
+                RpcError::INVALID_REQUEST,
+                format!("Unsupported JSON-RPC version {}", self.jsonrpc),
+            ));
+        }
+
+        if !Request::METHODS.contains(&self.method.as_str()) {
+            return Err(RpcError::new(
+                RpcError::METHOD_NOT_FOUND,
+                format!("Unknown method {}", self.method),
+            ));
+        }
+
+        let mut fields = Map::new();
+        fields.insert("method".into(), Value::String(self.method.clone()));
+        if !self.params.is_null() {
+            fields.insert("params".into(), self.params.clone());
+        }
+
+        serde_json::from_value(Value::Object(fields))
+            .map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, e.to_string()))
+    }
+}
+
+/// JSON-RPC response envelope
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    /// `null` for operations without a result, absent on failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn success(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.into(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn failure(id: Value, error: RpcError) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.into(),
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+/// Connection to a running madrd. Calls from different threads take turns.
+#[derive(Debug)]
+pub struct Client {
+    connection: Mutex<Connection>,
+}
+
+impl Client {
+    /// Connect to madrd on its default socket, `None` if it is not running
+    pub fn connect() -> Result<Option<Self>> {
+        match Self::connect_to(&socket_path()?) {
+            Ok(client) => Ok(Some(client)),
+            Err(MadRError::Io(e))
+                if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn connect_to(path: &Path) -> Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        writer.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        let reader = BufReader::new(writer.try_clone()?);
+
+        Ok(Self {
+            connection: Mutex::new(Connection {
+                reader,
+                writer,
+                next_id: 1,
+            }),
+        })
+    }
+
+    /// Run `request` on the daemon's device
+    pub fn call<T: DeserializeOwned>(&self, request: &Request) -> Result<T> {
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let id = connection.next_id;
+        connection.next_id += 1;
+
+        let mut line = serde_json::to_string(&RpcRequest::new(id, request)?)
+            .map_err(|e| MadRError::Daemon(e.to_string()))?;
+        line.push('\n');
+        connection.writer.write_all(line.as_bytes())?;
+
+        let mut line = String::new();
+        if connection.reader.read_line(&mut line)? == 0 {
+            return Err(MadRError::Daemon("connection closed".into()));
+        }
+
+        let response: RpcResponse = serde_json::from_str(&line)
+            .map_err(|e| MadRError::Daemon(format!("invalid response: {}", e)))?;
+
+        if response.id != id {
+            return Err(MadRError::Daemon(format!(
+                "expected the response to request {}, got {}",
+                id, response.id
+            )));
+        }
+
+        if let Some(error) = response.error {
+            return Err(MadRError::Daemon(error.message));
+        }
+
+        serde_json::from_value(response.result.unwrap_or(Value::Null))
+            .map_err(|e| MadRError::Daemon(format!("invalid result: {}", e)))
+    }
+
+    pub fn info(&self) -> Result<DeviceInfo> {
+        self.call(&Request::Info)
+    }
+
+    pub fn battery(&self) -> Result<Battery> {
+        self.call(&Request::Battery)
+    }
+
+    pub fn performance(&self) -> Result<Performance> {
+        self.call(&Request::Performance)
+    }
+
+    pub fn set_performance(&self, settings: &Performance) -> Result<()> {
+        self.call(&Request::SetPerformance {
+            dpi_stage: settings.dpi_stage(),
+            polling_rate: settings.polling_rate(),
+        })
+    }
+
+    pub fn sensor(&self) -> Result<Sensor> {
+        self.call(&Request::Sensor)
+    }
+
+    pub fn set_sensor_mode(&self, mode: Mode) -> Result<()> {
+        self.call(&Request::SetSensorMode { mode })
+    }
+
+    pub fn read_stage(&self, stage: u8) -> Result<DpiStage> {
+        self.call(&Request::ReadStage { stage })
+    }
+
+    pub fn read_stage_color(&self, stage: u8) -> Result<Rgb> {
+        self.call(&Request::ReadStageColor { stage })
+    }
+
+    /// See `dpi::apply_dpi_setting`
+    pub fn set_dpi(
+        &self,
+        stage: u8,
+        x_dpi: Option<u16>,
+        y_dpi: Option<u16>,
+        rgb: Option<Rgb>,
+    ) -> Result<()> {
+        self.call(&Request::SetDpi {
+            stage,
+            x_dpi,
+            y_dpi,
+            rgb,
+        })
+    }
+
+    pub fn debounce(&self) -> Result<Debounce> {
+        self.call(&Request::Debounce)
+    }
+
+    pub fn set_debounce(&self, debounce: Debounce) -> Result<()> {
+        self.call(&Request::SetDebounce { debounce })
+    }
+
+    pub fn sleep(&self) -> Result<Duration> {
+        self.call(&Request::Sleep).map(Duration::from_secs)
+    }
+
+    pub fn set_sleep(&self, duration: Duration) -> Result<()> {
+        self.call(&Request::SetSleep {
+            seconds: duration.as_secs(),
+        })
+    }
+
+    pub fn apply_profile(&self, profile: &Profile) -> Result<Vec<Change>> {
+        self.call(&Request::ApplyProfile {
+            profile: profile.clone(),
+        })
+    }
+
+    pub fn read_registers(&self, address: u8, len: u8) -> Result<Vec<u8>> {
+        self.call(&Request::ReadRegisters { address, len })
+    }
+
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        Snapshot::from_bytes(self.call(&Request::Snapshot)?)
+    }
+}
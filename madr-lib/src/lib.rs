@@ -1,22 +1,52 @@
+#[cfg(feature = "tokio")]
+pub mod async_device;
+pub mod backend;
 pub mod battery;
 pub mod debounce;
+pub mod decode;
 pub mod device;
 pub mod dpi;
+pub mod emulator;
+pub mod fault;
+#[cfg(feature = "hidraw")]
+pub mod hidraw;
+#[cfg(all(feature = "hotplug", target_os = "linux"))]
+pub mod hotplug;
+#[cfg(unix)]
+pub mod ipc;
+mod lock;
+pub mod metrics;
+pub mod model;
 pub mod performance;
+pub mod profile;
+pub mod register;
+pub mod report_descriptor;
+pub mod retry;
 pub mod sensor;
 pub mod sleep;
+pub mod telemetry;
+mod trace;
 
+#[cfg(feature = "tokio")]
+pub use async_device::AsyncDevice;
 pub use battery::Battery;
 pub use debounce::Debounce;
 pub use device::Device;
+pub use model::{Capabilities, Feature, Model, Quirk};
 pub use performance::{Performance, PollingRate};
+pub use profile::Profile;
+pub use retry::RetryPolicy;
 pub use sensor::Sensor;
 
 use thiserror::Error;
 
+#[cfg(not(any(feature = "hidapi", feature = "hidraw")))]
+compile_error!("either the `hidapi` or the `hidraw` feature must be enabled");
+
 /// Unified error type for all vxelib operations
 #[derive(Error, Debug)]
 pub enum MadRError {
+    #[cfg(feature = "hidapi")]
     #[error("Failed to initialize HIDAPI: {0}")]
     HidApiInit(#[from] hidapi::HidError),
     #[error("No compatible device found")]
@@ -37,6 +67,31 @@ pub enum MadRError {
     InvalidRgbValue(String),
     #[error("Invalid performance setting: {0}")]
     InvalidPerformanceSetting(String),
+    #[error("No response from device to {0}")]
+    NoResponse(String),
+    #[error("Invalid report: {0}")]
+    InvalidReport(String),
+    #[error("Invalid device ID: {0}")]
+    InvalidDeviceId(String),
+    #[error("Invalid device descriptor: {0}")]
+    InvalidDescriptor(String),
+    #[error("Not supported by this device: {0}")]
+    Unsupported(String),
+    #[error("Device is busy: {0}")]
+    DeviceBusy(String),
+    #[error("Invalid profile: {0}")]
+    InvalidProfile(String),
+    #[error("Invalid battery log: {0}")]
+    InvalidBatteryLog(String),
+    #[error("madrd: {0}")]
+    Daemon(String),
+    #[error("Trace error: {0}")]
+    Trace(String),
+    #[cfg(feature = "tokio")]
+    #[error("Device worker thread stopped")]
+    WorkerStopped,
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, MadRError>;
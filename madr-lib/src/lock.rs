@@ -0,0 +1,125 @@
+// Advisory lock between processes using the same device
+// Every process that opens a device takes an exclusive lock on a file named after it for the
+// duration of each transaction, so that e.g. a battery poll from cron can't pick up the
+// response to a read-modify-write running in another madrctl at the same time.
+// Lock files live in $XDG_RUNTIME_DIR/madr, or in a madr-<uid> directory in the temp directory
+// without it, so only processes of the same user exclude each other. That directory has to be
+// private: another user creating it first could otherwise hold our locks, or plant a socket
+// where madrctl looks for madrd.
+
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{MadRError, Result};
+
+/// How often a busy lock is tried again
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Directory for lock files and the madrd socket
+pub(crate) fn runtime_dir() -> Result<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Ok(PathBuf::from(dir).join("madr")),
+        None => private_temp_dir(),
+    }
+}
+
+/// A directory in the temp directory only this user can use, created if it doesn't exist
+#[cfg(unix)]
+fn private_temp_dir() -> Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let uid = unsafe { libc::getuid() };
+    let dir = env::temp_dir().join(format!("madr-{}", uid));
+
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // Not following symlinks, the directory itself has to be ours
+    let metadata = fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} must be a directory owned by uid {} with mode 0700",
+                dir.display(),
+                uid
+            ),
+        )
+        .into());
+    }
+
+    Ok(dir)
+}
+
+/// The temp directory is per user already
+#[cfg(not(unix))]
+fn private_temp_dir() -> Result<PathBuf> {
+    Ok(env::temp_dir().join("madr"))
+}
+
+/// Lock file name for a device, e.g. its serial number or hidraw path
+fn file_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    format!("{}.lock", name.trim_matches('_'))
+}
+
+#[derive(Debug)]
+pub(crate) struct ProcessLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl ProcessLock {
+    /// Open the lock file for the device identified by `key`, without locking it yet
+    pub(crate) fn open(key: &str) -> Result<Self> {
+        let dir = runtime_dir()?;
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(file_name(key));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        Ok(Self { path, file })
+    }
+
+    /// Take the lock, waiting up to `timeout` for another process to release it
+    pub(crate) fn acquire(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self.file.try_lock() {
+                Ok(()) => return Ok(()),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(POLL_INTERVAL)
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(MadRError::DeviceBusy(format!(
+                        "another process has held {} for more than {} ms",
+                        self.path.display(),
+                        timeout.as_millis()
+                    )));
+                }
+                Err(TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub(crate) fn release(&self) {
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,201 @@
+// Prometheus metrics for the mouse, in the text exposition format
+// Every sample carries labels naming the device, so metrics from several machines can be told
+// apart. Settings that couldn't be read are left out of a scrape rather than reported as 0, and
+// failed reads are counted per reader in madr_read_errors_total:
+//
+//   madr_battery_percent{model="VXE MAD R",vid="373b",pid="1040",serial=""} 87
+//   madr_read_errors_total{reader="battery"} 2
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::battery::Battery;
+use crate::dpi::DpiStage;
+use crate::performance::Performance;
+use crate::sensor::{Mode, Sensor};
+
+/// Content type of the text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Reads counted in madr_read_errors_total, all of them are exported from the start
+pub const READERS: [&str; 5] = ["open", "battery", "performance", "dpi", "sensor"];
+
+const MODES: [Mode; 3] = [Mode::Basic, Mode::Competitive, Mode::Max];
+
+/// The device a scrape is about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub model: String,
+    pub vid: u16,
+    pub pid: u16,
+    /// USB serial number, if the device has one
+    pub serial: Option<String>,
+    pub wired: bool,
+}
+
+impl Target {
+    fn labels(&self) -> String {
+        format!(
+            "model=\"{}\",vid=\"{:04x}\",pid=\"{:04x}\",serial=\"{}\"",
+            escape(&self.model),
+            self.vid,
+            self.pid,
+            escape(self.serial.as_deref().unwrap_or_default())
+        )
+    }
+}
+
+/// What was read for one scrape, `None` where reading failed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scrape {
+    /// `None` if the device couldn't be opened
+    pub target: Option<Target>,
+    pub battery: Option<Battery>,
+    pub performance: Option<Performance>,
+    /// Of the active stage
+    pub dpi: Option<DpiStage>,
+    /// `None` also if the model has no sensor modes
+    pub sensor: Option<Sensor>,
+}
+
+/// Counts failed reads across scrapes and renders scrapes
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    errors: BTreeMap<&'static str, u64>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The value of a read, counting a failure against `reader`, one of `READERS`
+    pub fn record<T, E>(
+        &mut self,
+        reader: &'static str,
+        result: std::result::Result<T, E>,
+    ) -> Option<T> {
+        if result.is_err() {
+            *self.errors.entry(reader).or_default() += 1;
+        }
+
+        result.ok()
+    }
+
+    /// Failed reads of `reader` so far
+    pub fn errors(&self, reader: &str) -> u64 {
+        self.errors.get(reader).copied().unwrap_or_default()
+    }
+
+    /// The scrape in the text exposition format
+    pub fn render(&self, scrape: &Scrape) -> String {
+        let mut out = String::new();
+
+        let up = scrape.target.is_some() && scrape.performance.is_some();
+        let help = "Whether the mouse answered";
+        family(
+            &mut out,
+            "madr_up",
+            "gauge",
+            help,
+            [(String::new(), up as u64)],
+        );
+
+        if let Some(target) = &scrape.target {
+            let labels = target.labels();
+            let with = |extra: String| format!("{},{}", labels, extra);
+            let gauge = |out: &mut String, name: &str, help: &str, value: u64| {
+                family(out, name, "gauge", help, [(labels.clone(), value)]);
+            };
+
+            let connection = [("wired", target.wired), ("wireless", !target.wired)]
+                .map(|(kind, active)| (with(format!("type=\"{}\"", kind)), active as u64));
+            let help = "How the mouse is connected";
+            family(&mut out, "madr_connection", "gauge", help, connection);
+
+            if let Some(battery) = &scrape.battery {
+                let help = "Battery charge in percent";
+                let percentage = battery.percentage() as u64;
+                gauge(&mut out, "madr_battery_percent", help, percentage);
+
+                let help = "Battery voltage in millivolts";
+                let voltage = battery.voltage() as u64;
+                gauge(&mut out, "madr_battery_voltage_millivolts", help, voltage);
+
+                let help = "Whether the battery is charging";
+                let charging = battery.is_charging() as u64;
+                gauge(&mut out, "madr_battery_charging", help, charging);
+            }
+
+            if let Some(performance) = &scrape.performance {
+                let help = "Polling rate in Hz";
+                let rate = performance.polling_rate() as u64;
+                gauge(&mut out, "madr_polling_rate_hertz", help, rate);
+
+                let help = "Active DPI stage, from 1";
+                let stage = performance.dpi_stage() as u64;
+                gauge(&mut out, "madr_dpi_stage", help, stage);
+            }
+
+            if let Some(dpi) = &scrape.dpi {
+                let axes = [("x", dpi.x_dpi()), ("y", dpi.y_dpi())]
+                    .map(|(axis, dpi)| (with(format!("axis=\"{}\"", axis)), dpi as u64));
+                family(
+                    &mut out,
+                    "madr_dpi",
+                    "gauge",
+                    "DPI of the active stage",
+                    axes,
+                );
+            }
+
+            if let Some(sensor) = &scrape.sensor {
+                let modes = MODES.map(|mode| {
+                    let labels = with(format!("mode=\"{}\"", mode));
+                    (labels, (sensor.mode() == mode) as u64)
+                });
+                family(
+                    &mut out,
+                    "madr_sensor_mode",
+                    "gauge",
+                    "Active sensor mode",
+                    modes,
+                );
+            }
+        }
+
+        let errors = READERS.map(|reader| (format!("reader=\"{}\"", reader), self.errors(reader)));
+        let help = "Failed reads of the mouse";
+        family(&mut out, "madr_read_errors_total", "counter", help, errors);
+
+        out
+    }
+}
+
+/// A metric family: its help and type, then a sample for each set of labels
+fn family<const N: usize>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: [(String, u64); N],
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+/// Escape a label value as the exposition format requires
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,328 @@
+// Supported models and what each of them can do
+// To add a model, add an entry to `builtin_models` with its product IDs and capabilities.
+//
+// Untested models can also be described at runtime by dropping a TOML descriptor into
+// $XDG_CONFIG_HOME/madrctl/devices.d/, for example:
+//
+//   name = "VXE R1"
+//   vid = 0x373b
+//   wired_pid = 0x1041
+//   wireless_pid = 0x1042
+//   interface = 1
+//   quirks = ["slow-responses"]
+//
+//   [capabilities]
+//   min_dpi = 100
+//   max_dpi = 26000
+//   dpi_step = 50
+//   dpi_stages = 8
+//   wired_polling_rates = [125, 250, 500, 1000]
+//   wireless_polling_rates = [125, 250, 500, 1000, 2000, 4000]
+//   features = ["dpi-colors", "sensor-mode", "debounce", "sleep", "battery"]
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dpi;
+use crate::performance::PollingRate;
+use crate::{MadRError, Result};
+
+pub const VXE_VID: u16 = 0x373b;
+
+/// Optional features a model may support
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feature {
+    DpiColors,
+    SensorMode,
+    Debounce,
+    Sleep,
+    Battery,
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Feature::DpiColors => write!(f, "DPI stage colors"),
+            Feature::SensorMode => write!(f, "sensor mode"),
+            Feature::Debounce => write!(f, "debounce time"),
+            Feature::Sleep => write!(f, "sleep timeout"),
+            Feature::Battery => write!(f, "battery status"),
+        }
+    }
+}
+
+/// Known deviations from the MAD R protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Quirk {
+    /// Responses take longer than the usual 20 ms to arrive
+    SlowResponses,
+}
+
+impl fmt::Display for Quirk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quirk::SlowResponses => write!(f, "slow responses"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Capabilities {
+    pub min_dpi: u16,
+    pub max_dpi: u16,
+    pub dpi_step: u16,
+    pub dpi_stages: u8,
+    pub wired_polling_rates: Vec<PollingRate>,
+    pub wireless_polling_rates: Vec<PollingRate>,
+    pub features: Vec<Feature>,
+}
+
+impl Capabilities {
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    pub fn polling_rates(&self, wired: bool) -> &[PollingRate] {
+        if wired {
+            &self.wired_polling_rates
+        } else {
+            &self.wireless_polling_rates
+        }
+    }
+
+    pub fn require(&self, feature: Feature) -> Result<()> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(MadRError::Unsupported(feature.to_string()))
+        }
+    }
+
+    pub fn check_dpi(&self, dpi: u16) -> Result<()> {
+        if !dpi.is_multiple_of(self.dpi_step) || !(self.min_dpi..=self.max_dpi).contains(&dpi) {
+            return Err(MadRError::InvalidDpiSetting(format!(
+                "DPI must be between {} and {} and a multiple of {}",
+                self.min_dpi, self.max_dpi, self.dpi_step
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_stage(&self, stage: u8) -> Result<()> {
+        if !(1..=self.dpi_stages).contains(&stage) {
+            return Err(MadRError::InvalidDpiSetting(format!(
+                "DPI stage must be between 1 and {}",
+                self.dpi_stages
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Check that the DPI range and stages can be stored in the mouse's registers
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.dpi_step == 0 || !self.dpi_step.is_multiple_of(dpi::DPI_UNIT) {
+            return Err(format!("dpi_step must be a multiple of {}", dpi::DPI_UNIT));
+        }
+
+        if self.min_dpi > self.max_dpi {
+            return Err("min_dpi must not be above max_dpi".into());
+        }
+
+        if self.min_dpi < dpi::DPI_UNIT || self.max_dpi > dpi::MAX_DPI {
+            return Err(format!(
+                "DPI must be between {} and {}",
+                dpi::DPI_UNIT,
+                dpi::MAX_DPI
+            ));
+        }
+
+        if !(1..=dpi::MAX_STAGES).contains(&self.dpi_stages) {
+            return Err(format!(
+                "dpi_stages must be between 1 and {}",
+                dpi::MAX_STAGES
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_polling_rate(&self, rate: PollingRate, wired: bool) -> Result<()> {
+        if !self.polling_rates(wired).contains(&rate) {
+            let max = self.polling_rates(wired).iter().max();
+            return Err(MadRError::InvalidPerformanceSetting(format!(
+                "{} is not supported {}, maximum is {}",
+                rate,
+                if wired { "when wired" } else { "wirelessly" },
+                max.map_or("none".into(), |r| r.to_string())
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_vid() -> u16 {
+    VXE_VID
+}
+
+fn default_interface() -> i32 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Model {
+    pub name: String,
+    #[serde(default = "default_vid")]
+    pub vid: u16,
+    pub wired_pid: Option<u16>,
+    pub wireless_pid: Option<u16>,
+    /// USB interface carrying the configuration reports, only used when the
+    /// HID report descriptor of the device can't be read
+    #[serde(default = "default_interface")]
+    pub interface: i32,
+    pub capabilities: Capabilities,
+    #[serde(default)]
+    pub quirks: Vec<Quirk>,
+    /// Whether the model has been tested with madr-lib, descriptor files never are
+    #[serde(skip)]
+    pub verified: bool,
+    /// Descriptor file the model was loaded from, if any
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+impl Model {
+    pub fn matches(&self, vid: u16, pid: u16) -> bool {
+        vid == self.vid && (Some(pid) == self.wired_pid || Some(pid) == self.wireless_pid)
+    }
+
+    pub fn has_quirk(&self, quirk: Quirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+
+    /// Model used with `--force-device` when the VID/PID is not known at all,
+    /// assumes the device behaves exactly like a wired or wireless MAD R
+    pub fn generic(vid: u16, pid: u16, wired: bool) -> Self {
+        let mut model = builtin_models().remove(0);
+        model.name = format!("Unknown device {:04x}:{:04x}", vid, pid);
+        model.vid = vid;
+        model.wired_pid = wired.then_some(pid);
+        model.wireless_pid = (!wired).then_some(pid);
+        model.verified = false;
+        model
+    }
+}
+
+/// Every model known to work
+pub fn builtin_models() -> Vec<Model> {
+    use PollingRate::*;
+
+    vec![Model {
+        name: "VXE MAD R".into(),
+        vid: VXE_VID,
+        wired_pid: Some(0x103f),
+        wireless_pid: Some(0x1040),
+        interface: 1,
+        capabilities: Capabilities {
+            min_dpi: 100,
+            max_dpi: 30000,
+            dpi_step: 50,
+            dpi_stages: 8,
+            wired_polling_rates: vec![Hz125, Hz250, Hz500, Hz1000],
+            wireless_polling_rates: vec![Hz125, Hz250, Hz500, Hz1000, Hz2000, Hz4000, Hz8000],
+            features: vec![
+                Feature::DpiColors,
+                Feature::SensorMode,
+                Feature::Debounce,
+                Feature::Sleep,
+                Feature::Battery,
+            ],
+        },
+        quirks: vec![],
+        verified: true,
+        source: None,
+    }]
+}
+
+/// Directory user supplied descriptors are loaded from
+pub fn descriptor_dir() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(config.join("madrctl").join("devices.d"))
+}
+
+/// Load a single descriptor file
+pub fn load_descriptor(path: &Path) -> Result<Model> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| MadRError::InvalidDescriptor(format!("{}: {}", path.display(), e)))?;
+
+    let mut model: Model = toml::from_str(&contents).map_err(|e| {
+        MadRError::InvalidDescriptor(format!("{}: {}", path.display(), e.message()))
+    })?;
+
+    if model.wired_pid.is_none() && model.wireless_pid.is_none() {
+        return Err(MadRError::InvalidDescriptor(format!(
+            "{}: at least one of wired_pid or wireless_pid is required",
+            path.display()
+        )));
+    }
+
+    model
+        .capabilities
+        .validate()
+        .map_err(|e| MadRError::InvalidDescriptor(format!("{}: {}", path.display(), e)))?;
+
+    model.verified = false;
+    model.source = Some(path.to_path_buf());
+    Ok(model)
+}
+
+/// Built-in models followed by every descriptor in `descriptor_dir`.
+/// Built-in models take precedence when both match the same device.
+/// Descriptors that fail to load are skipped, see `load_models` for why.
+pub fn all_models() -> Result<Vec<Model>> {
+    Ok(load_models()?.0)
+}
+
+/// Like `all_models`, but also returns the error of each descriptor that was skipped
+pub fn load_models() -> Result<(Vec<Model>, Vec<MadRError>)> {
+    let mut models = builtin_models();
+    let mut skipped = Vec::new();
+
+    let Some(dir) = descriptor_dir().filter(|d| d.is_dir()) else {
+        return Ok((models, skipped));
+    };
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        match load_descriptor(&path) {
+            Ok(model) => models.push(model),
+            Err(e) => skipped.push(e),
+        }
+    }
+
+    Ok((models, skipped))
+}
+
+/// Find the model with the given vendor and product ID
+pub fn find_model(vid: u16, pid: u16) -> Result<Option<Model>> {
+    Ok(all_models()?.into_iter().find(|m| m.matches(vid, pid)))
+}
@@ -2,10 +2,16 @@
 // DPI stage and polling rate share the same report structure (0x08 0x07 0x00 0x00 0x00 0x06)
 // and can be combined into a single configuration report.
 
-use crate::device::Device;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::{Channel, Device};
+use crate::register::{self, Command};
 use crate::{MadRError, Result};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
 pub enum PollingRate {
     Hz125 = 125,
     Hz250 = 250,
@@ -36,7 +42,19 @@ impl TryFrom<u16> for PollingRate {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl From<PollingRate> for u16 {
+    fn from(rate: PollingRate) -> Self {
+        rate as u16
+    }
+}
+
+impl fmt::Display for PollingRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz", *self as u16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Performance {
     dpi_stage: u8,
     polling_rate: PollingRate,
@@ -65,16 +83,18 @@ impl Performance {
         report[5] = 0x06;
         report[16] = 0x3f;
 
-        device.write(&report)?;
-
-        let mut buf = [0u8; 17];
-        device.read_timeout(&mut buf, 20)?;
-
-        Self::from_bytes(&buf)
+        let response = device.query(Channel::Output, &report, Command::Read, 0x00)?;
+        Self::from_bytes(&response)
     }
 
-    fn from_bytes(data: &[u8]) -> Result<Performance> {
-        let dpi_stage = data[10] + 1; // stored as stage - 1
+    /// Decode a performance register report
+    pub fn from_bytes(data: &[u8]) -> Result<Performance> {
+        register::require_report(data)?;
+
+        // stored as stage - 1
+        let dpi_stage = data[10]
+            .checked_add(1)
+            .ok_or_else(|| MadRError::InvalidPerformanceSetting("Invalid DPI stage".into()))?;
         let polling_rate = match data[6] {
             0x08 => PollingRate::Hz125,
             0x04 => PollingRate::Hz250,
@@ -97,7 +117,7 @@ impl Performance {
     }
 }
 
-fn make_combined_report(dpi_stage: u8, rate: PollingRate) -> Vec<u8> {
+pub(crate) fn make_combined_report(dpi_stage: u8, rate: PollingRate) -> Vec<u8> {
     let rate_byte: u8 = match rate {
         PollingRate::Hz125 => 0x08,
         PollingRate::Hz250 => 0x04,
@@ -131,8 +151,12 @@ fn make_combined_report(dpi_stage: u8, rate: PollingRate) -> Vec<u8> {
 
 /// Apply performance settings to device
 pub fn apply_setting(device: &Device, settings: &Performance) -> Result<()> {
+    let capabilities = device.capabilities();
+    capabilities.check_stage(settings.dpi_stage)?;
+    capabilities.check_polling_rate(settings.polling_rate, device.is_wired())?;
+
     let report = make_combined_report(settings.dpi_stage, settings.polling_rate);
-    device.send_feature_report(&report)?;
+    device.set(&report)?;
 
     Ok(())
 }
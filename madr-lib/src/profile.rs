@@ -0,0 +1,262 @@
+// Profiles: a set of settings to apply in one go, e.g. whenever the mouse is plugged in
+// Every setting is optional, settings that are left out stay as they are on the mouse:
+//
+//   polling_rate = 1000
+//   dpi_stage = 2
+//   sensor = "competitive"
+//   debounce = 4
+//   sleep = 300            # seconds
+//
+//   [[dpi]]
+//   stage = 1
+//   x_dpi = 800
+//   y_dpi = 800            # defaults to x_dpi
+//   rgb = "255,0,0"
+//
+// Settings are only written if they differ from the profile.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::debounce::{self, Debounce};
+use crate::device::Device;
+use crate::dpi::{self, DpiStage, Rgb};
+use crate::performance::{self, Performance, PollingRate};
+use crate::sensor::{self, Mode, Sensor};
+use crate::{Feature, MadRError, Result, sleep};
+
+/// Settings of a single DPI stage
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StageSettings {
+    pub stage: u8,
+    pub x_dpi: Option<u16>,
+    /// Defaults to `x_dpi`
+    pub y_dpi: Option<u16>,
+    pub rgb: Option<Rgb>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub polling_rate: Option<PollingRate>,
+    pub dpi_stage: Option<u8>,
+    #[serde(default)]
+    pub dpi: Vec<StageSettings>,
+    pub sensor: Option<Mode>,
+    pub debounce: Option<Debounce>,
+    /// Sleep timeout in seconds
+    pub sleep: Option<u64>,
+}
+
+/// A setting changed by `Profile::apply`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub setting: String,
+    pub from: String,
+    pub to: String,
+}
+
+impl Change {
+    fn new(setting: impl Into<String>, from: impl ToString, to: impl ToString) -> Self {
+        Self {
+            setting: setting.into(),
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.setting, self.from, self.to)
+    }
+}
+
+impl Profile {
+    /// Parse a profile from TOML
+    pub fn parse(contents: &str) -> Result<Self> {
+        let profile: Profile =
+            toml::from_str(contents).map_err(|e| MadRError::InvalidProfile(e.message().into()))?;
+
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Check what the format can't, for profiles that didn't come from `parse`
+    pub fn validate(&self) -> Result<()> {
+        if let Some(stage) = self
+            .dpi
+            .iter()
+            .find(|s| s.y_dpi.is_some() && s.x_dpi.is_none())
+        {
+            return Err(MadRError::InvalidProfile(format!(
+                "stage {}: y_dpi needs x_dpi",
+                stage.stage
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents).map_err(|e| match e {
+            MadRError::InvalidProfile(message) => {
+                MadRError::InvalidProfile(format!("{}: {}", path.display(), message))
+            }
+            e => e,
+        })
+    }
+
+    /// Check every setting against what `device` supports
+    fn check(&self, device: &Device) -> Result<()> {
+        let capabilities = device.capabilities();
+
+        for stage in &self.dpi {
+            capabilities.check_stage(stage.stage)?;
+
+            for dpi in [stage.x_dpi, stage.y_dpi].into_iter().flatten() {
+                capabilities.check_dpi(dpi)?;
+            }
+
+            if stage.rgb.is_some() {
+                capabilities.require(Feature::DpiColors)?;
+            }
+        }
+
+        if let Some(stage) = self.dpi_stage {
+            capabilities.check_stage(stage)?;
+        }
+
+        if let Some(rate) = self.polling_rate {
+            capabilities.check_polling_rate(rate, device.is_wired())?;
+        }
+
+        if self.sensor.is_some() {
+            capabilities.require(Feature::SensorMode)?;
+        }
+
+        if self.debounce.is_some() {
+            capabilities.require(Feature::Debounce)?;
+        }
+
+        if let Some(secs) = self.sleep {
+            capabilities.require(Feature::Sleep)?;
+            sleep::check(Duration::from_secs(secs))?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the profile as one unit, returning what was changed. Nothing is written unless
+    /// the device supports every setting.
+    pub fn apply(&self, device: &Device) -> Result<Vec<Change>> {
+        self.check(device)?;
+
+        let _guard = device.lock()?;
+        let mut changes = Vec::new();
+
+        for stage in &self.dpi {
+            let number = stage.stage;
+
+            if let Some(x_dpi) = stage.x_dpi {
+                let wanted = DpiStage::new(x_dpi, stage.y_dpi.unwrap_or(x_dpi));
+                let current = dpi::read_stage(device, number)?;
+
+                if current != wanted {
+                    dpi::apply_dpi_setting(device, number, Some(x_dpi), stage.y_dpi, None)?;
+                    changes.push(Change::new(
+                        format!("DPI stage {}", number),
+                        current,
+                        wanted,
+                    ));
+                }
+            }
+
+            if let Some(rgb) = stage.rgb {
+                let current = dpi::read_stage_color(device, number)?;
+
+                if current != rgb {
+                    let rgb_str = rgb.to_string();
+                    dpi::apply_dpi_setting(device, number, None, None, Some(&rgb_str))?;
+                    changes.push(Change::new(
+                        format!("DPI stage {} color", number),
+                        current,
+                        rgb,
+                    ));
+                }
+            }
+        }
+
+        if self.polling_rate.is_some() || self.dpi_stage.is_some() {
+            let current = Performance::read(device)?;
+            let wanted = Performance::new(
+                self.dpi_stage.unwrap_or(current.dpi_stage()),
+                self.polling_rate.unwrap_or(current.polling_rate()),
+            );
+
+            if wanted != current {
+                performance::apply_setting(device, &wanted)?;
+            }
+
+            if wanted.polling_rate() != current.polling_rate() {
+                changes.push(Change::new(
+                    "polling rate",
+                    current.polling_rate(),
+                    wanted.polling_rate(),
+                ));
+            }
+
+            if wanted.dpi_stage() != current.dpi_stage() {
+                changes.push(Change::new(
+                    "active DPI stage",
+                    current.dpi_stage(),
+                    wanted.dpi_stage(),
+                ));
+            }
+        }
+
+        if let Some(mode) = self.sensor {
+            let current = Sensor::read(device)?.mode();
+
+            if current != mode {
+                sensor::apply_setting(device, mode)?;
+                changes.push(Change::new("sensor", current, mode));
+            }
+        }
+
+        if let Some(debounce) = self.debounce {
+            let current = debounce::read(device)?;
+
+            if current != debounce {
+                debounce::apply_setting(device, debounce)?;
+                changes.push(Change::new(
+                    "debounce",
+                    format!("{} ms", current as u8),
+                    format!("{} ms", debounce as u8),
+                ));
+            }
+        }
+
+        if let Some(secs) = self.sleep {
+            let current = sleep::read(device)?.as_secs();
+
+            // The mouse keeps tens of seconds, e.g. 305 s is already set if it has 300 s
+            if current / 10 != secs / 10 {
+                sleep::apply_setting(device, Duration::from_secs(secs))?;
+                changes.push(Change::new(
+                    "sleep timeout",
+                    format!("{} s", current),
+                    format!("{} s", secs),
+                ));
+            }
+        }
+
+        Ok(changes)
+    }
+}
@@ -0,0 +1,256 @@
+// Raw register access
+// Every configuration report is 17 bytes long:
+//   byte 0      report ID (always 0x08)
+//   byte 1      command (0x04 battery, 0x07 write, 0x08 read)
+//   bytes 2-3   unused, always 0x00
+//   byte 4      register address
+//   byte 5      payload length
+//   bytes 6-15  payload
+//   byte 16     checksum, so that all 17 bytes sum to 0x55
+//
+// Payload values are usually followed by a check byte, either per value (0x55 - value)
+// or per group of values (0x55 - sum of the group), see `checksum_pattern`.
+
+use crate::device::{Channel, Device};
+use crate::{MadRError, Result};
+
+pub const REPORT_ID: u8 = 0x08;
+pub const REPORT_LEN: usize = 17;
+pub const MAX_PAYLOAD_LEN: u8 = 10;
+
+/// Number of bytes read per request when capturing a snapshot
+const SNAPSHOT_CHUNK: u8 = 8;
+
+/// Number of unrelated reports skipped while waiting for a response
+const MAX_STALE_RESPONSES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Battery = 0x04,
+    Write = 0x07,
+    Read = 0x08,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = MadRError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0x04 => Ok(Command::Battery),
+            0x07 => Ok(Command::Write),
+            0x08 => Ok(Command::Read),
+            _ => Err(MadRError::InvalidReport(format!(
+                "Unknown command byte: {:#04x}",
+                value
+            ))),
+        }
+    }
+}
+
+/// Compute the trailing checksum byte for the first 16 bytes of a report
+pub fn checksum(report: &[u8]) -> u8 {
+    report
+        .iter()
+        .take(REPORT_LEN - 1)
+        .fold(0x55u8, |acc, b| acc.wrapping_sub(*b))
+}
+
+/// Make sure `data` holds a whole report before indexing into it
+pub(crate) fn require_report(data: &[u8]) -> Result<()> {
+    if data.len() < REPORT_LEN {
+        return Err(MadRError::InvalidReport(format!(
+            "Expected {} bytes, got {}",
+            REPORT_LEN,
+            data.len()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Build a report for the given command, address and payload
+pub fn build_report(command: Command, address: u8, payload: &[u8]) -> Result<Vec<u8>> {
+    if payload.len() > MAX_PAYLOAD_LEN as usize {
+        return Err(MadRError::InvalidReport(format!(
+            "Payload is {} bytes, at most {} fit in a report",
+            payload.len(),
+            MAX_PAYLOAD_LEN
+        )));
+    }
+
+    let mut report = vec![0u8; REPORT_LEN];
+    report[0] = REPORT_ID;
+    report[1] = command as u8;
+    report[4] = address;
+    report[5] = payload.len() as u8;
+    report[6..6 + payload.len()].copy_from_slice(payload);
+    report[16] = checksum(&report);
+
+    Ok(report)
+}
+
+/// Build a read request for `len` bytes starting at `address`
+pub fn read_request(address: u8, len: u8) -> Result<Vec<u8>> {
+    if len > MAX_PAYLOAD_LEN {
+        return Err(MadRError::InvalidReport(format!(
+            "Cannot read {} bytes, at most {} fit in a report",
+            len, MAX_PAYLOAD_LEN
+        )));
+    }
+
+    let mut report = vec![0u8; REPORT_LEN];
+    report[0] = REPORT_ID;
+    report[1] = Command::Read as u8;
+    report[4] = address;
+    report[5] = len;
+    report[16] = checksum(&report);
+
+    Ok(report)
+}
+
+/// Read `len` raw bytes starting at `address`
+pub fn read(device: &Device, address: u8, len: u8) -> Result<Vec<u8>> {
+    let request = read_request(address, len)?;
+    let response = device.query(Channel::Feature, &request, Command::Read, address)?;
+    Ok(response[6..6 + len as usize].to_vec())
+}
+
+/// Wait for the response to a `command` request for `address`.
+/// Truncated or corrupted reports are an error, valid reports answering a different
+/// request (e.g. a late response to an earlier one) are skipped.
+pub(crate) fn read_response(
+    device: &Device,
+    command: Command,
+    address: u8,
+) -> Result<[u8; REPORT_LEN]> {
+    for _ in 0..MAX_STALE_RESPONSES {
+        let mut buf = [0u8; REPORT_LEN];
+        let size = device.read_timeout(&mut buf, device.response_timeout_ms())?;
+
+        if size == 0 {
+            return Err(MadRError::NoResponse(format!(
+                "{:?} request for {:#04x}",
+                command, address
+            )));
+        }
+
+        if size < REPORT_LEN || buf[0] != REPORT_ID || checksum(&buf) != buf[REPORT_LEN - 1] {
+            return Err(MadRError::InvalidReport(format!(
+                "Corrupted response to {:?} request for {:#04x}",
+                command, address
+            )));
+        }
+
+        // Only read responses are known to echo the address
+        if buf[1] == command as u8 && (command != Command::Read || buf[4] == address) {
+            return Ok(buf);
+        }
+    }
+
+    Err(MadRError::InvalidReport(format!(
+        "Unexpected response to {:?} request for {:#04x}",
+        command, address
+    )))
+}
+
+/// A single byte that differs between two snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub address: u8,
+    pub before: u8,
+    pub after: u8,
+}
+
+/// How a register byte is protected by a check byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumPattern {
+    /// First value byte of the group
+    pub start: u8,
+    /// Number of value bytes covered by the check byte
+    pub len: u8,
+    /// Address of the check byte (0x55 - sum of the values)
+    pub check: u8,
+}
+
+/// Contents of the whole 256 byte register space
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    registers: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Read the entire register space from the device
+    pub fn capture(device: &Device) -> Result<Self> {
+        let mut registers = Vec::with_capacity(256);
+
+        for chunk in 0..(256 / SNAPSHOT_CHUNK as usize) {
+            let address = (chunk * SNAPSHOT_CHUNK as usize) as u8;
+            registers.extend(read(device, address, SNAPSHOT_CHUNK)?);
+        }
+
+        Ok(Self { registers })
+    }
+
+    pub fn from_bytes(registers: Vec<u8>) -> Result<Self> {
+        if registers.len() != 256 {
+            return Err(MadRError::InvalidReport(format!(
+                "Snapshot must be 256 bytes, got {}",
+                registers.len()
+            )));
+        }
+
+        Ok(Self { registers })
+    }
+
+    pub fn get(&self, address: u8) -> u8 {
+        self.registers[address as usize]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// List every byte that differs from `other`
+    pub fn diff(&self, other: &Snapshot) -> Vec<Change> {
+        self.registers
+            .iter()
+            .zip(other.registers.iter())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(address, (before, after))| Change {
+                address: address as u8,
+                before: *before,
+                after: *after,
+            })
+            .collect()
+    }
+
+    /// Find the check byte protecting `address`, if any.
+    /// Groups of one to three values followed by a check byte are tried, smallest first.
+    pub fn checksum_pattern(&self, address: u8) -> Option<ChecksumPattern> {
+        let address = address as usize;
+
+        for len in 1..=3usize {
+            for start in address.saturating_sub(len)..=address {
+                let check = start + len;
+                if check > 0xFF || !(start..=check).contains(&address) {
+                    continue;
+                }
+
+                let sum = self.registers[start..check]
+                    .iter()
+                    .fold(0u8, |acc, b| acc.wrapping_add(*b));
+
+                if self.registers[check] == 0x55u8.wrapping_sub(sum) {
+                    return Some(ChecksumPattern {
+                        start: start as u8,
+                        len: len as u8,
+                        check: check as u8,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,196 @@
+// HID report descriptor parsing
+// Just enough of the HID item format to tell which reports an interface has:
+//   prefix byte: bits 0-1 data size (0, 1, 2 or 4 bytes), bits 2-3 type, bits 4-7 tag
+// See "Device Class Definition for HID 1.11", section 6.2.2.
+
+use std::fmt;
+
+use crate::{MadRError, Result};
+
+/// Usage pages from 0xFF00 upwards are vendor defined
+const VENDOR_USAGE_PAGE_START: u16 = 0xFF00;
+
+/// The configuration reports: ID 0x08 followed by 16 bytes of data
+const CONFIG_REPORT_ID: u8 = 0x08;
+const CONFIG_REPORT_DATA_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReportKind {
+    Input,
+    Output,
+    Feature,
+}
+
+impl fmt::Display for ReportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportKind::Input => write!(f, "input"),
+            ReportKind::Output => write!(f, "output"),
+            ReportKind::Feature => write!(f, "feature"),
+        }
+    }
+}
+
+/// A report declared by the descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub kind: ReportKind,
+    /// Report ID, 0 if the descriptor does not use report IDs
+    pub id: u8,
+    pub usage_page: u16,
+    /// Size of the report data in bytes, not counting the report ID
+    pub len: usize,
+}
+
+/// A top level (application) collection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collection {
+    pub usage_page: u16,
+    pub usage: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReportDescriptor {
+    pub collections: Vec<Collection>,
+    pub reports: Vec<Report>,
+}
+
+impl ReportDescriptor {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut descriptor = ReportDescriptor::default();
+
+        // Global state
+        let mut usage_page: u16 = 0;
+        let mut report_size: u32 = 0;
+        let mut report_count: u32 = 0;
+        let mut report_id: u8 = 0;
+        // Local state
+        let mut usage: u16 = 0;
+        let mut depth = 0;
+
+        let mut i = 0;
+        while i < data.len() {
+            let prefix = data[i];
+
+            // Long items carry no information we need
+            if prefix == 0xFE {
+                let len = *data.get(i + 1).ok_or_else(truncated)? as usize;
+                if data.len() < i + 3 + len {
+                    return Err(truncated());
+                }
+                i += 3 + len;
+                continue;
+            }
+
+            let size = match prefix & 0x03 {
+                3 => 4,
+                n => n as usize,
+            };
+            let bytes = data.get(i + 1..i + 1 + size).ok_or_else(truncated)?;
+            let value = bytes
+                .iter()
+                .rev()
+                .fold(0u32, |acc, b| (acc << 8) | *b as u32);
+
+            let item_type = (prefix >> 2) & 0x03;
+            let tag = prefix >> 4;
+
+            match (item_type, tag) {
+                // Main items
+                (0, 0x8) | (0, 0x9) | (0, 0xB) => {
+                    let kind = match tag {
+                        0x8 => ReportKind::Input,
+                        0x9 => ReportKind::Output,
+                        _ => ReportKind::Feature,
+                    };
+                    let bits = report_size.saturating_mul(report_count);
+
+                    match descriptor
+                        .reports
+                        .iter_mut()
+                        .find(|r| r.kind == kind && r.id == report_id)
+                    {
+                        Some(report) => report.len += bits as usize,
+                        None => descriptor.reports.push(Report {
+                            kind,
+                            id: report_id,
+                            usage_page,
+                            len: bits as usize,
+                        }),
+                    }
+                    usage = 0;
+                }
+                // Collection
+                (0, 0xA) => {
+                    if depth == 0 {
+                        descriptor
+                            .collections
+                            .push(Collection { usage_page, usage });
+                    }
+                    depth += 1;
+                    usage = 0;
+                }
+                // End collection
+                (0, 0xC) => {
+                    depth = (depth - 1).max(0);
+                }
+                // Global items
+                (1, 0x0) => usage_page = value as u16,
+                (1, 0x7) => report_size = value,
+                (1, 0x8) => report_id = value as u8,
+                (1, 0x9) => report_count = value,
+                // Local usage, may include the usage page in the upper 16 bits
+                (2, 0x0) => usage = value as u16,
+                _ => {}
+            }
+
+            i += 1 + size;
+        }
+
+        // Lengths were accumulated in bits
+        for report in &mut descriptor.reports {
+            report.len = report.len.div_ceil(8);
+        }
+
+        Ok(descriptor)
+    }
+
+    /// Whether this interface carries the 17-byte vendor configuration report
+    pub fn is_config_interface(&self) -> bool {
+        self.reports.iter().any(|r| {
+            r.kind == ReportKind::Feature
+                && r.usage_page >= VENDOR_USAGE_PAGE_START
+                && r.id == CONFIG_REPORT_ID
+                && r.len == CONFIG_REPORT_DATA_LEN
+        })
+    }
+}
+
+fn truncated() -> MadRError {
+    MadRError::InvalidReport("Truncated HID report descriptor".into())
+}
+
+impl fmt::Display for ReportDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for collection in &self.collections {
+            writeln!(
+                f,
+                "collection: usage page {:#06x}, usage {:#06x}",
+                collection.usage_page, collection.usage
+            )?;
+        }
+
+        let mut reports = self.reports.clone();
+        reports.sort_by_key(|r| (r.id, r.kind));
+
+        for report in reports {
+            writeln!(
+                f,
+                "{} report {:#04x}: {} byte(s), usage page {:#06x}",
+                report.kind, report.id, report.len, report.usage_page
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+// Retrying transactions over a flaky link
+// A transaction is either a request followed by its response, or a single write.
+// Requests are always safe to repeat. Writes carry no acknowledgement, so a write is only
+// sent again when sending it failed, never because something after it went wrong.
+
+use std::time::Duration;
+
+use crate::MadRError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per transaction, including the first one
+    pub attempts: u32,
+    /// Pause before the first retry, doubled before every further one
+    pub backoff: Duration,
+    /// How long to wait for a response at first
+    pub read_timeout: Duration,
+    /// Longest the read timeout may grow to after responses time out
+    pub max_read_timeout: Duration,
+    /// Reopen the device after a transport error, e.g. when the dongle re-enumerates
+    pub reconnect: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(20),
+            read_timeout: Duration::from_millis(20),
+            max_read_timeout: Duration::from_millis(200),
+            reconnect: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fail on the first error, like madr-lib used to
+    pub fn none() -> Self {
+        Self {
+            attempts: 1,
+            reconnect: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// What went wrong in a failed attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Failure {
+    /// The response did not arrive in time
+    Timeout,
+    /// A response arrived, but was corrupted or answered something else
+    BadResponse,
+    /// The transport failed, the handle may be gone
+    Transport,
+    /// Retrying will not help
+    Permanent,
+}
+
+impl Failure {
+    pub(crate) fn of(error: &MadRError) -> Self {
+        match error {
+            MadRError::NoResponse(_) => Failure::Timeout,
+            MadRError::InvalidReport(_) => Failure::BadResponse,
+            #[cfg(feature = "hidapi")]
+            MadRError::HidApiInit(_) => Failure::Transport,
+            MadRError::Io(_) => Failure::Transport,
+            _ => Failure::Permanent,
+        }
+    }
+}
@@ -1,9 +1,15 @@
-use crate::device::Device;
+use serde::{Deserialize, Serialize};
+
+use crate::decode::SENSOR_REGISTER;
+use crate::device::{Channel, Device};
+use crate::model::Feature;
+use crate::register::{self, Command};
 use crate::{MadRError, Result};
 use std::fmt;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Mode {
     #[default]
     Basic = 0,
@@ -47,7 +53,7 @@ impl TryFrom<u8> for Mode {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Sensor {
     mode: Mode,
 }
@@ -55,6 +61,8 @@ pub struct Sensor {
 impl Sensor {
     /// Read sensor configuration from device
     pub fn read(device: &Device) -> Result<Self> {
+        device.capabilities().require(Feature::SensorMode)?;
+
         let mut report = [0u8; 17];
         report[0] = 0x08;
         report[1] = 0x08;
@@ -62,12 +70,12 @@ impl Sensor {
         report[5] = 0x06;
         report[16] = 0x8a;
 
-        device.write(&report)?;
-
-        let mut buf = [0u8; 17];
-        device.read_timeout(&mut buf, 20)?;
+        let data = device.query(Channel::Output, &report, Command::Read, 0xB5)?;
+        Self::from_bytes(&data)
+    }
 
-        let data = &buf;
+    /// Decode a sensor register report
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < 17 || data[0] != 0x08 || data[1] != 0x08 {
             return Err(MadRError::InvalidSensorFormat);
         }
@@ -81,20 +89,21 @@ impl Sensor {
     }
 }
 
-fn get_magic_report(sensor_mode: Mode) -> Vec<u8> {
+/// A copy of the sleep timeout shares the register, `sleep_tens` keeps it as it is
+pub(crate) fn get_magic_report(sensor_mode: Mode, sleep_tens: u8) -> Vec<u8> {
     let setting = sensor_mode as u8;
     vec![
         0x08,
         0x07,
         0x00,
         0x00,
-        0xb5,                         // magic...
-        0x06,                         // ...bytes
-        0x00,    // works with either 00 or 01? after factory reset 00 is correct though
-        0x55,    // 55 - prev
-        0x06,    // magic
-        0x4f,    // magic
-        setting, // sensor setting byte
+        0xb5, // magic...
+        0x06, // ...bytes
+        0x00, // works with either 00 or 01? after factory reset 00 is correct though
+        0x55, // 55 - prev
+        sleep_tens,
+        0x55u8.wrapping_sub(sleep_tens),
+        setting,                      // sensor setting byte
         0x55u8.wrapping_sub(setting), // checksum byte
         0x00,
         0x00,
@@ -106,8 +115,13 @@ fn get_magic_report(sensor_mode: Mode) -> Vec<u8> {
 
 /// Apply sensor setting to device
 pub fn apply_setting(device: &Device, mode: Mode) -> Result<()> {
-    let report = get_magic_report(mode);
-    device.send_feature_report(&report)?;
+    device.capabilities().require(Feature::SensorMode)?;
+
+    let _guard = device.lock()?;
+    let sensor = register::read(device, SENSOR_REGISTER, 0x06)?;
+
+    let report = get_magic_report(mode, sensor[2]);
+    device.set(&report)?;
 
     Ok(())
 }
@@ -1,8 +1,12 @@
-use crate::Result;
+use crate::decode::{POWER_REGISTER, SENSOR_REGISTER};
 use crate::device::Device;
+use crate::model::Feature;
+use crate::register;
+use crate::{MadRError, Result};
 use std::time::Duration;
 
-fn get_sleep_report(tens_of_seconds: u8) -> Vec<u8> {
+/// The debounce time shares the register, `debounce_ms` keeps it as it is
+fn get_sleep_report(tens_of_seconds: u8, debounce_ms: u8) -> Vec<u8> {
     vec![
         0x08,
         0x07,
@@ -10,8 +14,8 @@ fn get_sleep_report(tens_of_seconds: u8) -> Vec<u8> {
         0x00,
         0xA9,
         0x0A,
-        0x04,
-        0x51,
+        debounce_ms,
+        0x55u8.wrapping_sub(debounce_ms),
         0x01,
         0x54,
         tens_of_seconds,
@@ -24,7 +28,8 @@ fn get_sleep_report(tens_of_seconds: u8) -> Vec<u8> {
     ]
 }
 
-fn get_confirmation_report(tens_of_seconds: u8) -> Vec<u8> {
+/// The sensor mode shares the register, `sensor_mode` keeps it as it is
+fn get_confirmation_report(tens_of_seconds: u8, sensor_mode: u8) -> Vec<u8> {
     vec![
         0x08,
         0x07,
@@ -36,8 +41,8 @@ fn get_confirmation_report(tens_of_seconds: u8) -> Vec<u8> {
         0x54,
         tens_of_seconds,
         0x55u8.wrapping_sub(tens_of_seconds),
-        0x00,
-        0x55,
+        sensor_mode,
+        0x55u8.wrapping_sub(sensor_mode),
         0x00,
         0x00,
         0x00,
@@ -46,16 +51,42 @@ fn get_confirmation_report(tens_of_seconds: u8) -> Vec<u8> {
     ]
 }
 
+/// Read the sleep timeout
+pub fn read(device: &Device) -> Result<Duration> {
+    device.capabilities().require(Feature::Sleep)?;
+
+    let power = register::read(device, POWER_REGISTER, 0x0A)?;
+    Ok(Duration::from_secs(power[4] as u64 * 10))
+}
+
+/// Check that the mouse can store `duration`
+pub(crate) fn check(duration: Duration) -> Result<()> {
+    // Stored as a single byte in tens of seconds
+    if !(10..=2550).contains(&duration.as_secs()) {
+        return Err(MadRError::InvalidSleepTimeout(
+            "Sleep timeout must be between 10 seconds and 42.5 minutes".into(),
+        ));
+    }
+
+    Ok(())
+}
+
 /// Apply sleep timeout setting to device
 pub fn apply_setting(device: &Device, duration: Duration) -> Result<()> {
-    let time_ms = duration.as_millis() as u32;
-    let tens_of_seconds = (time_ms / 10000) as u8;
+    device.capabilities().require(Feature::Sleep)?;
+
+    check(duration)?;
+    let tens_of_seconds = (duration.as_secs() / 10) as u8;
+
+    let _guard = device.lock()?;
+    let power = register::read(device, POWER_REGISTER, 0x0A)?;
+    let sensor = register::read(device, SENSOR_REGISTER, 0x06)?;
 
-    let sleep_pkt = get_sleep_report(tens_of_seconds);
-    device.send_feature_report(&sleep_pkt)?;
+    let sleep_pkt = get_sleep_report(tens_of_seconds, power[0]);
+    device.set(&sleep_pkt)?;
 
-    let confirmation = get_confirmation_report(tens_of_seconds);
-    device.send_feature_report(&confirmation)?;
+    let confirmation = get_confirmation_report(tens_of_seconds, sensor[4]);
+    device.set(&confirmation)?;
 
     Ok(())
 }
@@ -0,0 +1,334 @@
+// Battery telemetry: a log of battery samples and what can be derived from it
+// The log is a CSV file, one sample per line, with the settings that affect battery life:
+//
+//   timestamp,percentage,voltage_mv,charging,polling_rate,sensor_mode
+//   1760000000,87,4012,0,8000,competitive
+//
+// The timestamp is in seconds since the Unix epoch. Settings that couldn't be read are left
+// empty. Samples further apart than `MAX_GAP` are not compared, as the logger wasn't running
+// or the mouse was off in between.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::battery::Battery;
+use crate::performance::PollingRate;
+use crate::sensor::Mode;
+use crate::{MadRError, Result};
+
+pub const CSV_HEADER: &str = "timestamp,percentage,voltage_mv,charging,polling_rate,sensor_mode";
+
+/// Longest time between two samples that are still compared
+pub const MAX_GAP: Duration = Duration::from_secs(60 * 60);
+
+/// Least change in percentage a rate is derived from
+const MIN_CHANGE: f64 = 1.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub percentage: u8,
+    pub voltage_mv: u16,
+    pub charging: bool,
+    pub polling_rate: Option<PollingRate>,
+    pub sensor_mode: Option<Mode>,
+}
+
+impl Sample {
+    /// Sample taken right now
+    pub fn new(battery: &Battery, polling_rate: Option<PollingRate>, mode: Option<Mode>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        Self {
+            timestamp,
+            percentage: battery.percentage(),
+            voltage_mv: battery.voltage(),
+            charging: battery.is_charging(),
+            polling_rate,
+            sensor_mode: mode,
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.timestamp,
+            self.percentage,
+            self.voltage_mv,
+            self.charging as u8,
+            self.polling_rate
+                .map_or(String::new(), |r| (r as u16).to_string()),
+            self.sensor_mode.map_or(String::new(), |m| m.to_string()),
+        )
+    }
+
+    pub fn from_csv(line: &str) -> Result<Self> {
+        let invalid = |what: &str| MadRError::InvalidBatteryLog(format!("{}: {:?}", what, line));
+
+        let fields: Vec<&str> = line.trim().split(',').collect();
+        let [
+            timestamp,
+            percentage,
+            voltage_mv,
+            charging,
+            polling_rate,
+            sensor_mode,
+        ] = fields[..]
+        else {
+            return Err(invalid("expected 6 fields"));
+        };
+
+        Ok(Self {
+            timestamp: timestamp
+                .parse()
+                .map_err(|_| invalid("invalid timestamp"))?,
+            percentage: percentage
+                .parse()
+                .map_err(|_| invalid("invalid percentage"))?,
+            voltage_mv: voltage_mv.parse().map_err(|_| invalid("invalid voltage"))?,
+            charging: match charging {
+                "0" => false,
+                "1" => true,
+                _ => return Err(invalid("invalid charging state")),
+            },
+            polling_rate: match polling_rate {
+                "" => None,
+                rate => Some(
+                    rate.parse::<u16>()
+                        .ok()
+                        .and_then(|r| PollingRate::try_from(r).ok())
+                        .ok_or_else(|| invalid("invalid polling rate"))?,
+                ),
+            },
+            sensor_mode: match sensor_mode {
+                "" => None,
+                mode => Some(mode.parse().map_err(|_| invalid("invalid sensor mode"))?),
+            },
+        })
+    }
+}
+
+/// Default log file, $XDG_DATA_HOME/madrctl/battery.csv
+pub fn default_log_path() -> Option<PathBuf> {
+    let data = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share"))
+        })?;
+
+    Some(data.join("madrctl").join("battery.csv"))
+}
+
+/// Append a sample to the log, creating it if needed
+pub fn append(path: &Path, sample: &Sample) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", CSV_HEADER)?;
+    }
+
+    writeln!(file, "{}", sample.to_csv())?;
+    Ok(())
+}
+
+/// Read every sample in the log, oldest first
+pub fn load(path: &Path) -> Result<Vec<Sample>> {
+    let contents = fs::read_to_string(path)?;
+
+    let mut samples = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && line.trim() != CSV_HEADER)
+        .map(|(i, line)| {
+            Sample::from_csv(line).map_err(|e| match e {
+                MadRError::InvalidBatteryLog(message) => MadRError::InvalidBatteryLog(format!(
+                    "{}:{}: {}",
+                    path.display(),
+                    i + 1,
+                    message
+                )),
+                e => e,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    samples.sort_by_key(|s| s.timestamp);
+    Ok(samples)
+}
+
+/// Change in percentage over some time
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rate {
+    /// Percentage points gained while charging or lost while discharging
+    pub change: f64,
+    pub hours: f64,
+}
+
+impl Rate {
+    fn add(&mut self, change: f64, hours: f64) {
+        self.change += change;
+        self.hours += hours;
+    }
+
+    /// Percentage points per hour, `None` without enough data
+    pub fn per_hour(&self) -> Option<f64> {
+        (self.change >= MIN_CHANGE && self.hours > 0.0).then(|| self.change / self.hours)
+    }
+
+    /// How long 100 percentage points last at this rate
+    pub fn full_charge(&self) -> Option<Duration> {
+        self.per_hour()
+            .map(|rate| Duration::from_secs_f64(100.0 / rate * 3600.0))
+    }
+}
+
+/// Uninterrupted time on the charger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChargeSession {
+    pub start: u64,
+    pub end: u64,
+    pub from: u8,
+    pub to: u8,
+}
+
+/// Discharge with the same settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub polling_rate: Option<PollingRate>,
+    pub sensor_mode: Option<Mode>,
+    pub discharge: Rate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub samples: usize,
+    pub first: u64,
+    pub latest: Sample,
+    pub discharge: Rate,
+    pub charge: Rate,
+    /// At the discharge rate of the latest settings if known, otherwise the overall one
+    pub time_to_empty: Option<Duration>,
+    pub time_to_full: Option<Duration>,
+    pub sessions: Vec<ChargeSession>,
+    /// Charge cycles, counted as every 100 percentage points charged
+    pub cycles: f64,
+    /// Sorted by polling rate, then sensor mode
+    pub usage: Vec<Usage>,
+}
+
+impl Report {
+    /// Analyze samples sorted by time, `None` if there are none
+    pub fn new(samples: &[Sample]) -> Option<Self> {
+        let latest = samples.last()?.clone();
+
+        let mut discharge = Rate::default();
+        let mut charge = Rate::default();
+        let mut usage: Vec<Usage> = Vec::new();
+        let mut sessions: Vec<ChargeSession> = Vec::new();
+        let mut session: Option<ChargeSession> = None;
+
+        for (i, sample) in samples.iter().enumerate() {
+            let previous = i
+                .checked_sub(1)
+                .map(|i| &samples[i])
+                .filter(|p| sample.timestamp.saturating_sub(p.timestamp) <= MAX_GAP.as_secs());
+
+            if let Some(p) = previous
+                && p.charging == sample.charging
+            {
+                let hours = sample.timestamp.saturating_sub(p.timestamp) as f64 / 3600.0;
+                let change = sample.percentage as f64 - p.percentage as f64;
+
+                if p.charging {
+                    charge.add(change, hours);
+                } else {
+                    discharge.add(-change, hours);
+
+                    // The time until the next sample is spent with the settings of this one
+                    let same_settings = |u: &Usage| {
+                        u.polling_rate == p.polling_rate && u.sensor_mode == p.sensor_mode
+                    };
+                    match usage.iter_mut().find(|u| same_settings(u)) {
+                        Some(u) => u.discharge.add(-change, hours),
+                        None => usage.push(Usage {
+                            polling_rate: p.polling_rate,
+                            sensor_mode: p.sensor_mode,
+                            discharge: Rate {
+                                change: -change,
+                                hours,
+                            },
+                        }),
+                    }
+                }
+            }
+
+            if previous.is_none() || !sample.charging {
+                sessions.extend(session.take());
+            }
+
+            if sample.charging {
+                let session = session.get_or_insert(ChargeSession {
+                    start: sample.timestamp,
+                    end: sample.timestamp,
+                    from: sample.percentage,
+                    to: sample.percentage,
+                });
+                session.end = sample.timestamp;
+                session.to = sample.percentage;
+            }
+        }
+        sessions.extend(session);
+
+        usage.sort_by_key(|u| (u.polling_rate, u.sensor_mode.map(|m| m as u8)));
+
+        let cycles = sessions
+            .iter()
+            .map(|s| s.to.saturating_sub(s.from) as f64)
+            .sum::<f64>()
+            / 100.0;
+
+        let time_to_empty = if latest.charging {
+            None
+        } else {
+            usage
+                .iter()
+                .find(|u| {
+                    u.polling_rate == latest.polling_rate && u.sensor_mode == latest.sensor_mode
+                })
+                .and_then(|u| u.discharge.per_hour())
+                .or_else(|| discharge.per_hour())
+                .map(|rate| Duration::from_secs_f64(latest.percentage as f64 / rate * 3600.0))
+        };
+
+        let time_to_full = if latest.charging {
+            charge.per_hour().map(|rate| {
+                Duration::from_secs_f64((100 - latest.percentage.min(100)) as f64 / rate * 3600.0)
+            })
+        } else {
+            None
+        };
+
+        Some(Self {
+            samples: samples.len(),
+            first: samples[0].timestamp,
+            latest,
+            discharge,
+            charge,
+            time_to_empty,
+            time_to_full,
+            sessions,
+            cycles,
+            usage,
+        })
+    }
+}
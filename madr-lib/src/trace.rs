@@ -0,0 +1,258 @@
+// Packet tracing
+// A trace is a JSONL file with one entry per transport call, e.g.
+//   {"time_ms":1700000000000,"op":"send_feature_report","data":"08 07 00 ..."}
+//   {"time_ms":1700000000020,"op":"read_timeout","timeout_ms":20,"data":"08 08 00 ..."}
+// The first entry is always an "open" entry with the vendor and product ID of the device,
+// and whether it was connected by cable.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::Transport;
+use crate::{MadRError, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Event {
+    Open {
+        vid: u16,
+        pid: u16,
+        /// Missing in traces recorded before it was added
+        #[serde(default)]
+        wired: bool,
+    },
+    SendFeatureReport {
+        data: String,
+    },
+    Write {
+        data: String,
+    },
+    ReadTimeout {
+        timeout_ms: i32,
+        data: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// Milliseconds since the Unix epoch
+    time_ms: u64,
+    #[serde(flatten)]
+    event: Event,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    hex.split_whitespace()
+        .map(|b| {
+            u8::from_str_radix(b, 16)
+                .map_err(|_| MadRError::Trace(format!("Invalid hex byte in trace: {}", b)))
+        })
+        .collect()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Transport wrapper that logs every call to a trace file
+#[derive(Debug)]
+pub(crate) struct Tracer {
+    inner: Box<dyn Transport>,
+    out: RefCell<File>,
+}
+
+impl Tracer {
+    pub(crate) fn create(
+        path: impl AsRef<Path>,
+        inner: Box<dyn Transport>,
+        vid: u16,
+        pid: u16,
+        wired: bool,
+    ) -> Result<Self> {
+        let tracer = Self {
+            inner,
+            out: RefCell::new(File::create(path)?),
+        };
+
+        tracer.log(Event::Open { vid, pid, wired }, None)?;
+        Ok(tracer)
+    }
+
+    fn log(&self, event: Event, error: Option<&MadRError>) -> Result<()> {
+        let entry = Entry {
+            time_ms: now_ms(),
+            event,
+            error: error.map(|e| e.to_string()),
+        };
+
+        let mut line = serde_json::to_string(&entry)
+            .map_err(|e| MadRError::Trace(format!("Failed to serialize trace entry: {}", e)))?;
+        line.push('\n');
+
+        self.out.borrow_mut().write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Transport for Tracer {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        let result = self.inner.send_feature_report(report);
+        let event = Event::SendFeatureReport {
+            data: to_hex(report),
+        };
+
+        self.log(event, result.as_ref().err())?;
+        result
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        let result = self.inner.write(data);
+        let event = Event::Write { data: to_hex(data) };
+
+        self.log(event, result.as_ref().err())?;
+        result
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        let result = self.inner.read_timeout(buf, timeout_ms);
+        let size = *result.as_ref().unwrap_or(&0);
+        let event = Event::ReadTimeout {
+            timeout_ms,
+            data: to_hex(&buf[..size]),
+        };
+
+        self.log(event, result.as_ref().err())?;
+        result
+    }
+
+    fn reconnect(&self) -> Result<()> {
+        self.inner.reconnect()
+    }
+}
+
+/// Fake transport that plays back a trace, failing as soon as the
+/// requests sent to it differ from the ones that were recorded
+#[derive(Debug)]
+pub(crate) struct Replay {
+    entries: RefCell<VecDeque<Entry>>,
+}
+
+impl Replay {
+    /// Load a trace, returning the transport, the vendor and product ID of the traced device
+    /// and whether it was wired
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<(Self, u16, u16, bool)> {
+        let contents = fs::read_to_string(path)?;
+
+        let mut entries = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str::<Entry>(line)
+                    .map_err(|e| MadRError::Trace(format!("Line {}: {}", i + 1, e)))
+            })
+            .collect::<Result<VecDeque<_>>>()?;
+
+        let (vid, pid, wired) = match entries.pop_front() {
+            Some(Entry {
+                event: Event::Open { vid, pid, wired },
+                ..
+            }) => (vid, pid, wired),
+            _ => {
+                return Err(MadRError::Trace(
+                    "Trace does not start with an open entry".into(),
+                ));
+            }
+        };
+
+        let replay = Self {
+            entries: RefCell::new(entries),
+        };
+
+        Ok((replay, vid, pid, wired))
+    }
+
+    fn next(&self, op: &str) -> Result<Entry> {
+        self.entries
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| MadRError::Trace(format!("Trace ended, but got {}", op)))
+    }
+
+    /// Recorded errors are replayed as I/O errors, so they are retried like the original
+    fn recorded_error(error: String) -> MadRError {
+        MadRError::Io(io::Error::other(format!("Recorded error: {}", error)))
+    }
+
+    /// Check that a request matches the recorded one, and replay its error if it had one
+    fn expect_request(&self, op: &str, expected: Event, entry: Entry) -> Result<()> {
+        if entry.event != expected {
+            return Err(MadRError::Trace(format!(
+                "Replay diverged: expected {:?}, got {} {:?}",
+                entry.event, op, expected
+            )));
+        }
+
+        match entry.error {
+            Some(error) => Err(Self::recorded_error(error)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Transport for Replay {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        let entry = self.next("send_feature_report")?;
+        let expected = Event::SendFeatureReport {
+            data: to_hex(report),
+        };
+
+        self.expect_request("send_feature_report", expected, entry)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        let entry = self.next("write")?;
+        let expected = Event::Write { data: to_hex(data) };
+
+        self.expect_request("write", expected, entry)?;
+        Ok(data.len())
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize> {
+        let entry = self.next("read_timeout")?;
+
+        let Event::ReadTimeout { data, .. } = &entry.event else {
+            return Err(MadRError::Trace(format!(
+                "Replay diverged: expected {:?}, got read_timeout",
+                entry.event
+            )));
+        };
+
+        if let Some(error) = entry.error {
+            return Err(Self::recorded_error(error));
+        }
+
+        let data = from_hex(data)?;
+        let size = data.len().min(buf.len());
+        buf[..size].copy_from_slice(&data[..size]);
+
+        Ok(size)
+    }
+}
@@ -0,0 +1,78 @@
+// Every read and write of AsyncDevice reaches the device on its worker thread, and the worker
+// keeps serving other handles after one of them is dropped.
+#![cfg(feature = "tokio")]
+
+use std::time::Duration;
+
+use madr_lib::debounce::Debounce;
+use madr_lib::dpi::{DpiStage, Rgb};
+use madr_lib::emulator::Emulator;
+use madr_lib::sensor::Mode;
+use madr_lib::{AsyncDevice, Performance, PollingRate};
+
+#[tokio::test]
+async fn settings_round_trip() {
+    let emulator = Emulator::new();
+    let device = AsyncDevice::spawn({
+        let emulator = emulator.clone();
+        move || Ok(emulator.device(false))
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(device.model().name, "VXE MAD R");
+    assert!(!device.is_wired());
+
+    // Factory settings
+    assert_eq!(
+        device.performance().await.unwrap(),
+        Performance::new(1, PollingRate::Hz1000)
+    );
+    assert_eq!(
+        device.dpi_stage(3).await.unwrap(),
+        DpiStage::new(1600, 1600)
+    );
+    assert_eq!(device.stage_color(3).await.unwrap(), Rgb::new(0, 0, 255));
+    assert_eq!(device.debounce().await.unwrap(), Debounce::Ms8);
+    assert_eq!(device.sleep().await.unwrap(), Duration::from_secs(60));
+    assert_eq!(device.battery().await.unwrap().percentage(), 100);
+
+    let settings = Performance::new(2, PollingRate::Hz500);
+    device.set_performance(settings).await.unwrap();
+    device
+        .set_dpi(4, Some(2000), Some(1000), Some("1,2,3".into()))
+        .await
+        .unwrap();
+    device.set_debounce(Debounce::Ms2).await.unwrap();
+    device.set_sleep(Duration::from_secs(300)).await.unwrap();
+    device.set_sensor_mode(Mode::Max).await.unwrap();
+
+    // Read back through a clone, after the original handle is gone
+    let clone = device.clone();
+    drop(device);
+
+    assert_eq!(clone.performance().await.unwrap(), settings);
+    assert_eq!(clone.dpi_stage(4).await.unwrap(), DpiStage::new(2000, 1000));
+    assert_eq!(clone.dpi_stage(3).await.unwrap(), DpiStage::new(1600, 1600));
+    assert_eq!(clone.stage_color(4).await.unwrap(), Rgb::new(1, 2, 3));
+    assert_eq!(clone.debounce().await.unwrap(), Debounce::Ms2);
+    assert_eq!(clone.sleep().await.unwrap(), Duration::from_secs(300));
+    assert_eq!(clone.sensor().await.unwrap().mode(), Mode::Max);
+
+    // The emulator saw the same writes
+    let direct = emulator.device(false);
+    assert_eq!(Performance::read(&direct).unwrap(), settings);
+}
+
+#[tokio::test]
+async fn errors_come_back() {
+    let device = AsyncDevice::spawn(|| Ok(Emulator::new().device(false)))
+        .await
+        .unwrap();
+
+    assert!(device.dpi_stage(9).await.is_err());
+    assert!(device.set_dpi(1, Some(50000), None, None).await.is_err());
+
+    let failed = AsyncDevice::spawn(|| Err(madr_lib::MadRError::DeviceNotFound)).await;
+    assert!(matches!(failed, Err(madr_lib::MadRError::DeviceNotFound)));
+}
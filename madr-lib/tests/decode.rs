@@ -0,0 +1,196 @@
+// Reports of every known register decode to the setting they hold, and dissecting them names
+// each byte and checks every check byte and the trailing checksum.
+
+use std::time::Duration;
+
+use madr_lib::decode::{self, Field, Message, Setting};
+use madr_lib::dpi::{DpiStage, Rgb};
+use madr_lib::register::{self, Command};
+use madr_lib::sensor::Mode;
+
+fn report(command: Command, address: u8, payload: &[u8]) -> Vec<u8> {
+    register::build_report(command, address, payload).unwrap()
+}
+
+fn field(fields: &[Field], offset: usize) -> &Field {
+    fields.iter().find(|f| f.offset == offset).unwrap()
+}
+
+/// Offset and verdict of every check byte and the checksum
+fn verdicts(fields: &[Field]) -> Vec<(usize, bool)> {
+    fields
+        .iter()
+        .filter_map(|f| f.valid.map(|valid| (f.offset, valid)))
+        .collect()
+}
+
+#[test]
+fn dpi_pair() {
+    // Stage 1 at 800, stage 2 at 26000x1600 which needs the high bits of X
+    let payload = [0x0F, 0x0F, 0x00, 0x37, 0x07, 0x1F, 0x08, 0x27];
+    let write = report(Command::Write, 0x0C, &payload);
+
+    assert_eq!(
+        decode::decode(&write).unwrap(),
+        Message::Write {
+            address: 0x0C,
+            setting: Setting::DpiPair {
+                stage: 1,
+                stages: (DpiStage::new(800, 800), DpiStage::new(26000, 1600)),
+            },
+        }
+    );
+
+    let fields = decode::dissect(&write).unwrap();
+    assert_eq!(field(&fields, 4).meaning, "DPI pair 1/2");
+    assert_eq!(field(&fields, 6).name, "stage 1 DPI");
+    assert_eq!(field(&fields, 6).len, 3);
+    assert_eq!(field(&fields, 10).name, "stage 2 DPI");
+    assert_eq!(
+        field(&fields, 10).meaning,
+        "26000x1600 (low X, low Y, high bits)"
+    );
+    assert_eq!(verdicts(&fields), [(9, true), (13, true), (16, true)]);
+
+    // A wrong check byte for stage 2, with the checksum still matching the report
+    let mut corrupt = payload;
+    corrupt[7] = 0x28;
+    let fields = decode::dissect(&report(Command::Write, 0x0C, &corrupt)).unwrap();
+    assert_eq!(verdicts(&fields), [(9, true), (13, false), (16, true)]);
+}
+
+#[test]
+fn rgb_pair() {
+    let payload = [0xFF, 0x00, 0x00, 0x56, 0x00, 0x80, 0xFF, 0xD6];
+    let read = report(Command::Read, 0x34, &payload);
+
+    assert_eq!(
+        decode::decode(&read).unwrap(),
+        Message::Read {
+            address: 0x34,
+            setting: Setting::RgbPair {
+                stage: 3,
+                colors: (Rgb::new(255, 0, 0), Rgb::new(0, 128, 255)),
+            },
+        }
+    );
+
+    let fields = decode::dissect(&read).unwrap();
+    assert_eq!(field(&fields, 1).meaning, "read");
+    assert_eq!(field(&fields, 4).meaning, "color pair 3/4");
+    assert_eq!(field(&fields, 10).name, "stage 4 color");
+    assert_eq!(field(&fields, 10).meaning, "R,G,B = 0,128,255");
+    assert_eq!(field(&fields, 14).name, "padding");
+    assert_eq!(verdicts(&fields), [(9, true), (13, true), (16, true)]);
+
+    // A wrong checksum is reported without failing the decode
+    let mut corrupt = read;
+    corrupt[16] = corrupt[16].wrapping_add(1);
+    assert!(decode::decode(&corrupt).unwrap().is_known());
+    let fields = decode::dissect(&corrupt).unwrap();
+    assert_eq!(verdicts(&fields), [(9, true), (13, true), (16, false)]);
+}
+
+#[test]
+fn power() {
+    // Debounce 4 ms, sleep 2 minutes
+    let payload = [0x04, 0x51, 0x01, 0x54, 0x0C, 0x49, 0x00, 0x55, 0x00, 0x55];
+    let write = report(Command::Write, 0xA9, &payload);
+
+    assert_eq!(
+        decode::decode(&write).unwrap(),
+        Message::Write {
+            address: 0xA9,
+            setting: Setting::Power {
+                debounce_ms: 4,
+                sleep: Duration::from_secs(120),
+            },
+        }
+    );
+    assert_eq!(
+        decode::decode(&write).unwrap().to_string(),
+        "write debounce = 4 ms, sleep = 120 s"
+    );
+
+    let fields = decode::dissect(&write).unwrap();
+    assert_eq!(field(&fields, 4).meaning, "debounce/sleep");
+    assert_eq!(field(&fields, 6).meaning, "4 ms");
+    assert_eq!(field(&fields, 10).meaning, "120 s (tens of seconds)");
+    assert_eq!(
+        verdicts(&fields),
+        [(7, true), (9, true), (11, true), (16, true)]
+    );
+
+    let mut corrupt = payload;
+    corrupt[1] = 0x50;
+    let fields = decode::dissect(&report(Command::Write, 0xA9, &corrupt)).unwrap();
+    assert_eq!(
+        verdicts(&fields),
+        [(7, false), (9, true), (11, true), (16, true)]
+    );
+}
+
+#[test]
+fn sensor() {
+    // Competitive mode, sleep 60 seconds
+    let payload = [0x00, 0x55, 0x06, 0x4F, 0x01, 0x54];
+    let read = report(Command::Read, 0xB5, &payload);
+
+    assert_eq!(
+        decode::decode(&read).unwrap(),
+        Message::Read {
+            address: 0xB5,
+            setting: Setting::Sensor {
+                mode: Mode::Competitive,
+                sleep: Duration::from_secs(60),
+            },
+        }
+    );
+
+    let fields = decode::dissect(&read).unwrap();
+    assert_eq!(field(&fields, 4).meaning, "sensor/sleep");
+    assert_eq!(field(&fields, 8).meaning, "60 s (tens of seconds)");
+    assert_eq!(field(&fields, 10).name, "sensor mode");
+    assert_eq!(field(&fields, 10).meaning, "competitive");
+    assert_eq!(
+        verdicts(&fields),
+        [(7, true), (9, true), (11, true), (16, true)]
+    );
+
+    // An unknown mode leaves the setting undecoded
+    let unknown = report(Command::Read, 0xB5, &[0x00, 0x55, 0x06, 0x4F, 0x07, 0x4E]);
+    let message = decode::decode(&unknown).unwrap();
+    assert!(!message.is_known());
+    assert_eq!(
+        message.to_string(),
+        "read register 0xb5 unknown = 00 55 06 4f 07 4e"
+    );
+}
+
+#[test]
+fn battery() {
+    let request = report(Command::Battery, 0x00, &[]);
+    assert_eq!(decode::decode(&request).unwrap(), Message::BatteryRequest);
+
+    // 80%, charging, 4000 mV
+    let status = report(Command::Battery, 0x00, &[0x50, 0x01, 0x0F, 0xA0]);
+    let message = decode::decode(&status).unwrap();
+    assert_eq!(message.to_string(), "battery = 80%, 4000 mV, charging");
+
+    let fields = decode::dissect(&status).unwrap();
+    assert_eq!(field(&fields, 1).meaning, "battery");
+    assert_eq!(field(&fields, 6).meaning, "80%");
+    assert_eq!(field(&fields, 7).meaning, "yes");
+    assert_eq!(field(&fields, 8).len, 2);
+    assert_eq!(field(&fields, 8).meaning, "4000 mV");
+    assert_eq!(verdicts(&fields), [(16, true)]);
+}
+
+#[test]
+fn not_a_report() {
+    assert!(decode::decode(&[0x08, 0x07]).is_err());
+
+    let mut wrong_id = report(Command::Write, 0x00, &[]);
+    wrong_id[0] = 0x09;
+    assert!(decode::dissect(&wrong_id).is_err());
+}
@@ -0,0 +1,38 @@
+// DPI and color settings are validated as a whole before anything is written, so a bad
+// color doesn't leave the DPI half applied.
+
+use madr_lib::dpi;
+use madr_lib::emulator::Emulator;
+use madr_lib::{Device, Feature, MadRError};
+
+#[test]
+fn invalid_input_writes_nothing() {
+    let emulator = Emulator::new();
+    let device = emulator.device(false);
+    let factory = emulator.registers();
+
+    let result = dpi::apply_dpi_setting(&device, 1, Some(800), None, Some("not a color"));
+    assert!(result.is_err());
+    assert_eq!(emulator.registers(), factory);
+
+    let result = dpi::apply_dpi_setting(&device, 1, Some(800), Some(50000), Some("0,0,255"));
+    assert!(matches!(result, Err(MadRError::InvalidDpiSetting(_))));
+    assert_eq!(emulator.registers(), factory);
+
+    dpi::apply_dpi_setting(&device, 1, Some(800), None, Some("0,0,255")).unwrap();
+    assert_ne!(emulator.registers(), factory);
+}
+
+#[test]
+fn unsupported_color_writes_nothing() {
+    let emulator = Emulator::new();
+    let mad_r = emulator.device(false);
+    let mut model = mad_r.model().clone();
+    model.capabilities.features = vec![Feature::Battery];
+    let device = Device::with_transport(Box::new(emulator.clone()), model, mad_r.product_id());
+    let factory = emulator.registers();
+
+    let result = dpi::apply_dpi_setting(&device, 2, Some(1600), None, Some("0,0,255"));
+    assert!(matches!(result, Err(MadRError::Unsupported(_))));
+    assert_eq!(emulator.registers(), factory);
+}
@@ -0,0 +1,150 @@
+// Multi-report operations must never leave corrupted data on the mouse, whatever the link does.
+// Every fault, and every pair of faults, is injected at every transport call of an operation,
+// and afterwards each value must hold, together with its check byte, either its old value or
+// the one a clean run writes.
+// This must hold with and without retries.
+
+use std::ops::Range;
+use std::time::Duration;
+
+use madr_lib::emulator::{self, Emulator};
+use madr_lib::fault::{Fault, FaultyTransport};
+use madr_lib::{Device, Performance, Result, RetryPolicy, dpi, sleep};
+
+/// More transport calls than any operation below makes
+const MAX_CALLS: usize = 12;
+
+/// Default retries, without waiting between attempts
+fn fast_retries() -> RetryPolicy {
+    RetryPolicy {
+        backoff: Duration::ZERO,
+        ..RetryPolicy::default()
+    }
+}
+
+/// Register blocks made of value/check groups, as (start, length, group length). DPI and color
+/// stages are three values and a check byte, the other blocks a value and its check byte.
+const BLOCKS: [(usize, usize, usize); 5] = [
+    (0x00, 6, 2),  // performance
+    (0x0C, 32, 4), // DPI pairs
+    (0x2C, 32, 4), // color pairs
+    (0xA9, 10, 2), // debounce/sleep
+    (0xB5, 6, 2),  // sensor/sleep
+];
+
+/// Every value/check group in the register space, and every other register on its own
+fn groups() -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let mut address = 0;
+
+    while address < 0x100 {
+        let len = BLOCKS
+            .iter()
+            .find(|(start, len, _)| (*start..start + len).contains(&address))
+            .map_or(1, |(_, _, group)| *group);
+
+        groups.push(address..address + len);
+        address += len;
+    }
+
+    groups
+}
+
+type Operation = fn(&Device) -> Result<()>;
+
+/// Like `madrctl dpi modify-stage`, preceded by a read so that stale responses are possible
+fn set_dpi(device: &Device) -> Result<()> {
+    Performance::read(device)?;
+    dpi::apply_dpi_setting(device, 3, Some(1250), None, Some("1,2,3"))
+}
+
+fn set_sleep(device: &Device) -> Result<()> {
+    Performance::read(device)?;
+    sleep::apply_setting(device, Duration::from_secs(300))
+}
+
+/// Run `operation` against a fresh emulator with `faults` injected, returning the registers
+fn run(
+    operation: Operation,
+    policy: RetryPolicy,
+    faults: &[(usize, Fault)],
+) -> (Result<()>, Vec<u8>) {
+    let emulator = Emulator::new();
+    let mut transport = FaultyTransport::new(Box::new(emulator.clone()));
+    for (call, fault) in faults {
+        transport = transport.inject(*call, *fault);
+    }
+
+    let device = emulator::device(Box::new(transport), false).with_retry_policy(policy);
+
+    let result = operation(&device);
+    (result, emulator.registers())
+}
+
+fn check(name: &str, operation: Operation, policy: RetryPolicy) {
+    let before = Emulator::new().registers();
+    let (result, after) = run(operation, policy, &[]);
+    result.unwrap_or_else(|e| panic!("{}: clean run failed: {}", name, e));
+    assert_ne!(before, after, "{}: clean run changed nothing", name);
+
+    let single = (0..MAX_CALLS).flat_map(|call| Fault::ALL.map(|fault| (call, fault)));
+    let schedules = single.clone().flat_map(|first| {
+        single
+            .clone()
+            .filter(move |second| second.0 > first.0)
+            .map(move |second| vec![first, second])
+            .chain([vec![first]])
+    });
+
+    for faults in schedules {
+        let (_, registers) = run(operation, policy, &faults);
+
+        for group in groups() {
+            let value = &registers[group.clone()];
+            let (old, new) = (&before[group.clone()], &after[group.clone()]);
+            assert!(
+                value == old || value == new,
+                "{}: {:?} left {:02x?} at {:#04x}, expected {:02x?} or {:02x?}",
+                name,
+                faults,
+                value,
+                group.start,
+                old,
+                new
+            );
+        }
+    }
+}
+
+#[test]
+fn dpi_setting_survives_faults() {
+    check("apply_dpi_setting", set_dpi, RetryPolicy::none());
+}
+
+#[test]
+fn dpi_setting_survives_faults_with_retries() {
+    check("apply_dpi_setting", set_dpi, fast_retries());
+}
+
+#[test]
+fn sleep_setting_survives_faults() {
+    check("sleep::apply_setting", set_sleep, RetryPolicy::none());
+}
+
+#[test]
+fn sleep_setting_survives_faults_with_retries() {
+    check("sleep::apply_setting", set_sleep, fast_retries());
+}
+
+#[test]
+fn retries_recover_from_single_faults() {
+    let (_, expected) = run(set_dpi, fast_retries(), &[]);
+
+    for call in 0..MAX_CALLS {
+        for fault in Fault::ALL {
+            let (result, registers) = run(set_dpi, fast_retries(), &[(call, fault)]);
+            assert!(result.is_ok(), "{:?} at call {}: {:?}", fault, call, result);
+            assert_eq!(registers, expected, "{:?} at call {}", fault, call);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// The parts of hotplug monitoring that don't need a netlink socket: sysfs names and paths of
+// HID devices, and the udev messages announcing their hidraw nodes.
+
+#![cfg(all(feature = "hotplug", target_os = "linux"))]
+
+use madr_lib::hotplug;
+
+const RECEIVER: &str = "/devices/pci0000:00/0000:00:14.0/usb1/1-2";
+
+/// A udev netlink message with the header laid out like udev_monitor_netlink_header
+fn message(properties: &[&str]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for property in properties {
+        body.extend_from_slice(property.as_bytes());
+        body.push(0);
+    }
+
+    let mut message = b"libudev\0".to_vec();
+    message.extend_from_slice(&0xfeedcafeu32.to_be_bytes());
+    message.extend_from_slice(&40u32.to_ne_bytes());
+    message.extend_from_slice(&40u32.to_ne_bytes());
+    message.extend_from_slice(&(body.len() as u32).to_ne_bytes());
+    message.resize(40, 0);
+    message.extend_from_slice(&body);
+    message
+}
+
+#[test]
+fn hid_names() {
+    assert_eq!(
+        hotplug::parse_hid_name("0003:373B:1040.0005"),
+        Some((0x373B, 0x1040))
+    );
+    assert_eq!(
+        hotplug::parse_hid_name("0005:046d:b023.000A"),
+        Some((0x046D, 0xB023))
+    );
+
+    // PCI addresses, USB interfaces and anything not quite a HID name
+    assert_eq!(hotplug::parse_hid_name("0000:00:14.0"), None);
+    assert_eq!(hotplug::parse_hid_name("1-2:1.0"), None);
+    assert_eq!(hotplug::parse_hid_name("0003:373B:1040"), None);
+    assert_eq!(hotplug::parse_hid_name("0003:373B:1040.05"), None);
+    assert_eq!(hotplug::parse_hid_name("0003:373G:1040.0005"), None);
+    assert_eq!(hotplug::parse_hid_name("0003:0373B:1040.0005"), None);
+}
+
+#[test]
+fn interfaces_belong_to_their_usb_device() {
+    let config = format!("{RECEIVER}/1-2:1.1/0003:373B:1040.0006/hidraw/hidraw3");
+    let keyboard = format!("{RECEIVER}/1-2:1.0/0003:373B:1040.0005/hidraw/hidraw2");
+
+    let device = Some((RECEIVER.to_string(), 0x373B, 0x1040));
+    assert_eq!(hotplug::device_of(&config), device);
+    assert_eq!(hotplug::device_of(&keyboard), device);
+}
+
+#[test]
+fn other_hid_devices_are_their_own_device() {
+    let bluetooth = "/devices/virtual/misc/uhid/0005:373B:1040.0007/hidraw/hidraw4";
+    assert_eq!(
+        hotplug::device_of(bluetooth),
+        Some((
+            "/devices/virtual/misc/uhid/0005:373B:1040.0007".to_string(),
+            0x373B,
+            0x1040
+        ))
+    );
+
+    assert_eq!(hotplug::device_of(&format!("{RECEIVER}/1-2:1.0")), None);
+}
+
+#[test]
+fn udev_messages() {
+    let devpath = format!("{RECEIVER}/1-2:1.1/0003:373B:1040.0006/hidraw/hidraw3");
+    let action = "ACTION=add".to_string();
+    let path = format!("DEVPATH={devpath}");
+    let message = message(&[&action, &path, "SUBSYSTEM=hidraw", "SEQNUM=4711"]);
+
+    let properties = hotplug::parse_message(&message).unwrap();
+    assert_eq!(properties.len(), 4);
+    assert_eq!(properties["ACTION"], "add");
+    assert_eq!(properties["DEVPATH"], devpath);
+    assert_eq!(properties["SUBSYSTEM"], "hidraw");
+}
+
+#[test]
+fn not_udev_messages() {
+    let valid = message(&["ACTION=remove", "SUBSYSTEM=hidraw"]);
+    assert!(hotplug::parse_message(&valid).is_some());
+
+    // Kernel uevents start with "ACTION@DEVPATH" instead of the udev header
+    assert_eq!(
+        hotplug::parse_message(b"add@/devices/virtual\0ACTION=add\0"),
+        None
+    );
+
+    let mut wrong_magic = valid.clone();
+    wrong_magic[8] ^= 0xFF;
+    assert_eq!(hotplug::parse_message(&wrong_magic), None);
+
+    // Properties running past the end of the message
+    let mut too_long = valid.clone();
+    too_long[20..24].copy_from_slice(&1000u32.to_ne_bytes());
+    assert_eq!(hotplug::parse_message(&too_long), None);
+
+    assert_eq!(hotplug::parse_message(&valid[..39]), None);
+}
@@ -0,0 +1,45 @@
+// Two handles to the same device, as two processes would open it, exclude each other for the
+// duration of a transaction. The second one gives up with DeviceBusy after its lock timeout,
+// which is what `madrctl --lock-timeout` sets.
+
+use std::process;
+use std::time::{Duration, Instant};
+
+use madr_lib::emulator::Emulator;
+use madr_lib::{Device, MadRError, Performance, model};
+
+/// A handle to `emulator` locked like a device named `key`
+fn open(emulator: &Emulator, key: &str) -> Device {
+    let pid = emulator.device(false).product_id();
+    let model = model::builtin_models().remove(0);
+    Device::with_locked_transport(Box::new(emulator.clone()), model, pid, key).unwrap()
+}
+
+#[test]
+fn busy_device() {
+    let emulator = Emulator::new();
+    let key = format!("madr-lock-test-{}", process::id());
+
+    let first = open(&emulator, &key);
+    let second = open(&emulator, &key).with_lock_timeout(Duration::from_millis(100));
+
+    let guard = first.lock().unwrap();
+    // The handle holding the lock goes on, as do handles without a process lock
+    assert!(Performance::read(&first).is_ok());
+    assert!(Performance::read(&emulator.device(false)).is_ok());
+
+    let start = Instant::now();
+    match Performance::read(&second) {
+        Err(MadRError::DeviceBusy(e)) => assert!(e.contains("more than 100 ms"), "{e}"),
+        other => panic!("{other:?}"),
+    }
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    drop(guard);
+    assert!(Performance::read(&second).is_ok());
+
+    // Other devices are not affected
+    let other = open(&emulator, &format!("{key}-other"));
+    let _guard = first.lock().unwrap();
+    assert!(Performance::read(&other).is_ok());
+}
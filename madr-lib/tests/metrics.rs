@@ -0,0 +1,104 @@
+// A scrape of an emulated mouse renders as Prometheus text with the device labels on every
+// sample, and failed reads leave their metrics out and count towards the error counters.
+
+use std::time::Duration;
+
+use madr_lib::emulator::{self, Emulator};
+use madr_lib::fault::{Fault, FaultyTransport};
+use madr_lib::metrics::{Metrics, Scrape, Target};
+use madr_lib::{Battery, Device, Performance, RetryPolicy, Sensor, dpi};
+
+const LABELS: &str = r#"model="VXE MAD R",vid="373b",pid="1040",serial="A\"1""#;
+
+fn target(device: &Device) -> Target {
+    Target {
+        model: device.model().name.clone(),
+        vid: device.model().vid,
+        pid: device.product_id(),
+        serial: Some("A\"1".into()),
+        wired: device.is_wired(),
+    }
+}
+
+fn scrape(metrics: &mut Metrics, device: &Device) -> Scrape {
+    let performance = metrics.record("performance", Performance::read(device));
+
+    Scrape {
+        target: Some(target(device)),
+        battery: metrics.record("battery", Battery::read(device)),
+        dpi: performance
+            .and_then(|p| metrics.record("dpi", dpi::read_stage(device, p.dpi_stage()))),
+        performance,
+        sensor: metrics.record("sensor", Sensor::read(device)),
+    }
+}
+
+fn lines(text: &str) -> Vec<&str> {
+    text.lines().filter(|l| !l.starts_with('#')).collect()
+}
+
+#[test]
+fn scrape_of_emulated_mouse() {
+    let emulator = Emulator::new();
+    emulator.set_battery(87, true, 4012);
+    let device = emulator.device(false);
+
+    let mut metrics = Metrics::new();
+    let scraped = scrape(&mut metrics, &device);
+    let text = metrics.render(&scraped);
+
+    let expected = [
+        "madr_up 1".to_string(),
+        format!("madr_connection{{{},type=\"wired\"}} 0", LABELS),
+        format!("madr_connection{{{},type=\"wireless\"}} 1", LABELS),
+        format!("madr_battery_percent{{{}}} 87", LABELS),
+        format!("madr_battery_voltage_millivolts{{{}}} 4012", LABELS),
+        format!("madr_battery_charging{{{}}} 1", LABELS),
+        format!("madr_polling_rate_hertz{{{}}} 1000", LABELS),
+        format!("madr_dpi_stage{{{}}} 1", LABELS),
+        format!("madr_dpi{{{},axis=\"x\"}} 400", LABELS),
+        format!("madr_dpi{{{},axis=\"y\"}} 400", LABELS),
+        format!("madr_sensor_mode{{{},mode=\"basic\"}} 1", LABELS),
+        format!("madr_sensor_mode{{{},mode=\"competitive\"}} 0", LABELS),
+        format!("madr_sensor_mode{{{},mode=\"max\"}} 0", LABELS),
+        "madr_read_errors_total{reader=\"open\"} 0".to_string(),
+        "madr_read_errors_total{reader=\"battery\"} 0".to_string(),
+        "madr_read_errors_total{reader=\"performance\"} 0".to_string(),
+        "madr_read_errors_total{reader=\"dpi\"} 0".to_string(),
+        "madr_read_errors_total{reader=\"sensor\"} 0".to_string(),
+    ];
+    assert_eq!(lines(&text), expected);
+    assert!(text.contains("# TYPE madr_read_errors_total counter\n"));
+    assert!(text.contains("# HELP madr_battery_percent "));
+}
+
+#[test]
+fn failed_reads_are_counted() {
+    // Every call fails, the link is gone
+    let faulty = (0..1000).fold(
+        FaultyTransport::new(Box::new(Emulator::new())),
+        |faulty, call| faulty.inject(call, Fault::Error),
+    );
+    let device = emulator::device(Box::new(faulty), false).with_retry_policy(RetryPolicy {
+        backoff: Duration::ZERO,
+        ..RetryPolicy::default()
+    });
+
+    let mut metrics = Metrics::new();
+    scrape(&mut metrics, &device);
+    let scraped = scrape(&mut metrics, &device);
+    let text = metrics.render(&scraped);
+
+    let samples = lines(&text);
+    assert!(samples.contains(&"madr_up 0"));
+    assert!(samples.contains(&"madr_read_errors_total{reader=\"battery\"} 2"));
+    assert!(samples.contains(&"madr_read_errors_total{reader=\"performance\"} 2"));
+    // Never tried without a stage to read
+    assert!(samples.contains(&"madr_read_errors_total{reader=\"dpi\"} 0"));
+    assert!(!text.contains("madr_battery_percent"));
+
+    // Without a device there is only madr_up and the counters
+    let text = Metrics::new().render(&Scrape::default());
+    assert_eq!(lines(&text)[0], "madr_up 0");
+    assert!(!text.contains("madr_connection"));
+}
@@ -0,0 +1,150 @@
+// Models for devices without a descriptor behave like the matching MAD R, and a broken
+// descriptor in devices.d doesn't keep the others from loading. Devices can be picked by a
+// VID:PID given on the command line.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use madr_lib::{MadRError, Model, device, model};
+
+const DESCRIPTOR: &str = r#"
+name = "VXE R1"
+vid = 0x373b
+wired_pid = 0x1041
+wireless_pid = 0x1042
+interface = 1
+
+[capabilities]
+min_dpi = 100
+max_dpi = 26000
+dpi_step = 50
+dpi_stages = 8
+wired_polling_rates = [125, 250, 500, 1000]
+wireless_polling_rates = [125, 250, 500, 1000, 2000, 4000]
+features = ["dpi-colors", "battery"]
+"#;
+
+#[test]
+fn generic_model_keeps_connection_type() {
+    let wired = Model::generic(0x1234, 0x5678, true);
+    assert_eq!(wired.wired_pid, Some(0x5678));
+    assert_eq!(wired.wireless_pid, None);
+    assert!(!wired.verified);
+
+    let wireless = Model::generic(0x1234, 0x5678, false);
+    assert_eq!(wireless.wired_pid, None);
+    assert_eq!(wireless.wireless_pid, Some(0x5678));
+}
+
+#[test]
+fn bad_descriptors_are_skipped() {
+    let config = env::temp_dir().join(format!("madr-models-{}", process::id()));
+    let dir = config.join("madrctl").join("devices.d");
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(dir.join("a-broken.toml"), "name = ").unwrap();
+    fs::write(dir.join("b-no-pid.toml"), DESCRIPTOR.replace("_pid", "_id")).unwrap();
+    fs::write(dir.join("c-r1.toml"), DESCRIPTOR).unwrap();
+
+    // The only test in this binary reading the environment
+    unsafe { env::set_var("XDG_CONFIG_HOME", &config) };
+
+    let (models, skipped) = model::load_models().unwrap();
+    let loaded: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(loaded, ["VXE MAD R", "VXE R1"]);
+
+    let skipped: Vec<String> = skipped.iter().map(|e| e.to_string()).collect();
+    assert_eq!(skipped.len(), 2);
+    assert!(skipped[0].contains("a-broken.toml"), "{skipped:?}");
+    assert!(skipped[1].contains("b-no-pid.toml"), "{skipped:?}");
+    assert_eq!(model::all_models().unwrap().len(), 2);
+
+    let r1 = model::find_model(0x373b, 0x1042).unwrap().unwrap();
+    assert_eq!(r1.source, Some(dir.join("c-r1.toml")));
+    assert_eq!(r1.capabilities.max_dpi, 26000);
+
+    assert!(model::load_descriptor(&dir.join("a-broken.toml")).is_err());
+    assert!(model::load_descriptor(&dir.join("missing.toml")).is_err());
+
+    fs::remove_dir_all(config).unwrap();
+}
+
+#[test]
+fn device_ids() {
+    assert_eq!(
+        device::parse_device_id("373b:1040").unwrap(),
+        (0x373B, 0x1040)
+    );
+    assert_eq!(
+        device::parse_device_id("0x373B:0x1041").unwrap(),
+        (0x373B, 0x1041)
+    );
+
+    for invalid in ["373b", "373b:", "373b:10400", "zz:1040", "373b:1040:1"] {
+        assert!(
+            matches!(
+                device::parse_device_id(invalid),
+                Err(MadRError::InvalidDeviceId(_))
+            ),
+            "{invalid} was parsed"
+        );
+    }
+}
+
+#[test]
+fn capabilities_must_fit_the_protocol() {
+    let dir = env::temp_dir().join(format!("madr-capabilities-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("r1.toml");
+    fs::write(&path, DESCRIPTOR).unwrap();
+    assert!(model::load_descriptor(&path).is_ok());
+
+    for (from, to, message) in [
+        (
+            "dpi_step = 50",
+            "dpi_step = 0",
+            "dpi_step must be a multiple of 50",
+        ),
+        (
+            "dpi_step = 50",
+            "dpi_step = 10",
+            "dpi_step must be a multiple of 50",
+        ),
+        (
+            "min_dpi = 100",
+            "min_dpi = 30000",
+            "min_dpi must not be above max_dpi",
+        ),
+        (
+            "min_dpi = 100",
+            "min_dpi = 0",
+            "DPI must be between 50 and 51200",
+        ),
+        (
+            "max_dpi = 26000",
+            "max_dpi = 60000",
+            "DPI must be between 50 and 51200",
+        ),
+        (
+            "dpi_stages = 8",
+            "dpi_stages = 0",
+            "dpi_stages must be between 1 and 8",
+        ),
+        (
+            "dpi_stages = 8",
+            "dpi_stages = 9",
+            "dpi_stages must be between 1 and 8",
+        ),
+    ] {
+        fs::write(&path, DESCRIPTOR.replace(from, to)).unwrap();
+
+        match model::load_descriptor(&path) {
+            Err(MadRError::InvalidDescriptor(e)) => assert!(e.ends_with(message), "{to}: {e}"),
+            other => panic!("{to}: {other:?}"),
+        }
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
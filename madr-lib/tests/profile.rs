@@ -0,0 +1,114 @@
+// Applying a profile writes what differs from the mouse and reports it, applying it again
+// writes nothing.
+
+use madr_lib::emulator::Emulator;
+use madr_lib::profile::Profile;
+use madr_lib::sensor::Mode;
+use madr_lib::{Device, MadRError, Performance, dpi, register};
+
+const PROFILE: &str = r#"
+polling_rate = 500
+dpi_stage = 2
+sensor = "competitive"
+debounce = 2
+sleep = 300
+
+[[dpi]]
+stage = 2
+x_dpi = 1250
+rgb = "1,2,3"
+
+[[dpi]]
+stage = 3
+x_dpi = 1600
+"#;
+
+fn emulated_device() -> Device {
+    Emulator::new().device(false)
+}
+
+#[test]
+fn apply_changes_only_what_differs() {
+    let device = emulated_device();
+    let profile = Profile::parse(PROFILE).unwrap();
+
+    let changes: Vec<String> = profile
+        .apply(&device)
+        .unwrap()
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+
+    // Stage 3 is already at 1600 DPI with factory settings
+    assert_eq!(
+        changes,
+        [
+            "DPI stage 2: 800 -> 1250",
+            "DPI stage 2 color: 0,255,0 -> 1,2,3",
+            "polling rate: 1000 Hz -> 500 Hz",
+            "active DPI stage: 1 -> 2",
+            "sensor: basic -> competitive",
+            "debounce: 8 ms -> 2 ms",
+            "sleep timeout: 60 s -> 300 s",
+        ]
+    );
+
+    let performance = Performance::read(&device).unwrap();
+    assert_eq!(performance.dpi_stage(), 2);
+    assert_eq!(dpi::read_stage(&device, 2).unwrap().x_dpi(), 1250);
+
+    // Debounce and sleep share a register, and the sensor mode shares one with a copy of
+    // the sleep timeout; none of them may undo another
+    let power = register::read(&device, 0xA9, 0x0A).unwrap();
+    assert_eq!((power[0], power[4]), (2, 30));
+    let sensor = register::read(&device, 0xB5, 0x06).unwrap();
+    assert_eq!((sensor[2], sensor[4]), (30, Mode::Competitive as u8));
+
+    assert!(profile.apply(&device).unwrap().is_empty());
+
+    // Sleep is kept in tens of seconds, 305 s is the 300 s the mouse already has
+    let rounded = Profile::parse("sleep = 305").unwrap();
+    assert!(rounded.apply(&device).unwrap().is_empty());
+}
+
+#[test]
+fn invalid_profiles_are_rejected() {
+    for contents in [
+        "polling_rate = 300",
+        "debounce = 3",
+        "sensor = \"fast\"",
+        "unknown = 1",
+        "[[dpi]]\nstage = 1\ny_dpi = 800",
+        "[[dpi]]\nstage = 1\nrgb = \"1,2\"",
+    ] {
+        assert!(
+            matches!(Profile::parse(contents), Err(MadRError::InvalidProfile(_))),
+            "accepted {:?}",
+            contents
+        );
+    }
+}
+
+#[test]
+fn unsupported_settings_write_nothing() {
+    let emulator = Emulator::new();
+    // 8000 Hz only works wirelessly
+    let device = emulator.device(true);
+    let factory = emulator.registers();
+
+    // Every one of them comes after a DPI change that would be written first
+    for setting in [
+        "sleep = 5",
+        "sleep = 3000",
+        "dpi_stage = 9",
+        "polling_rate = 8000",
+        "[[dpi]]\nstage = 2\nx_dpi = 1250\n[[dpi]]\nstage = 3\nx_dpi = 40000",
+        "[[dpi]]\nstage = 2\nx_dpi = 1250\n[[dpi]]\nstage = 9\nrgb = \"1,2,3\"",
+    ] {
+        let contents = format!("{setting}\n[[dpi]]\nstage = 1\nx_dpi = 1250");
+        let profile = Profile::parse(&contents).unwrap();
+
+        assert!(profile.apply(&device).is_err(), "applied {setting:?}");
+        assert_eq!(emulator.registers(), factory, "{setting:?}");
+    }
+}
@@ -0,0 +1,81 @@
+// Snapshots of the register space list the bytes a setting changed, and find the check byte
+// protecting each of them, the way `madrctl probe` reports them.
+
+use madr_lib::debounce::{self, Debounce};
+use madr_lib::emulator::Emulator;
+use madr_lib::register::{Change, ChecksumPattern, Snapshot};
+
+/// A register space holding only `bytes` at `address`
+fn snapshot(address: u8, bytes: &[u8]) -> Snapshot {
+    let mut registers = vec![0; 256];
+    registers[address as usize..address as usize + bytes.len()].copy_from_slice(bytes);
+    Snapshot::from_bytes(registers).unwrap()
+}
+
+#[test]
+fn diff_lists_changed_bytes() {
+    let emulator = Emulator::new();
+    let device = emulator.device(false);
+
+    let before = Snapshot::capture(&device).unwrap();
+    assert_eq!(before.as_bytes(), emulator.registers());
+    assert!(before.diff(&before).is_empty());
+
+    debounce::apply_setting(&device, Debounce::Ms4).unwrap();
+    let after = Snapshot::capture(&device).unwrap();
+
+    // The debounce value and its check byte
+    assert_eq!(
+        before.diff(&after),
+        [
+            Change {
+                address: 0xA9,
+                before: 8,
+                after: 4,
+            },
+            Change {
+                address: 0xAA,
+                before: 0x4D,
+                after: 0x51,
+            },
+        ]
+    );
+
+    let pattern = ChecksumPattern {
+        start: 0xA9,
+        len: 1,
+        check: 0xAA,
+    };
+    assert_eq!(after.checksum_pattern(0xA9), Some(pattern));
+    assert_eq!(after.checksum_pattern(0xAA), Some(pattern));
+}
+
+#[test]
+fn checksum_patterns() {
+    // A DPI stage: low X, low Y and high bits, checked by the byte after them
+    let dpi = snapshot(0x0C, &[0x0F, 0x0F, 0x00, 0x37]);
+    let pattern = Some(ChecksumPattern {
+        start: 0x0C,
+        len: 3,
+        check: 0x0F,
+    });
+    for address in 0x0C..=0x0F {
+        assert_eq!(dpi.checksum_pattern(address), pattern, "{address:#04x}");
+    }
+
+    // Smaller groups are tried first
+    let pair = snapshot(0x20, &[0x01, 0x54, 0x00, 0x54]);
+    assert_eq!(
+        pair.checksum_pattern(0x20),
+        Some(ChecksumPattern {
+            start: 0x20,
+            len: 1,
+            check: 0x21,
+        })
+    );
+
+    // Nothing in a blank register space sums up
+    assert_eq!(dpi.checksum_pattern(0x80), None);
+
+    assert!(Snapshot::from_bytes(vec![0; 255]).is_err());
+}
@@ -0,0 +1,211 @@
+// The configuration interface is picked by its report descriptor: a feature report 0x08 with
+// 16 bytes of data in a vendor defined usage page. The mouse's other interfaces have to be
+// told apart from it without a model descriptor naming the interface.
+
+use madr_lib::MadRError;
+use madr_lib::report_descriptor::{Collection, Report, ReportDescriptor, ReportKind};
+
+/// Interface 0 of the MAD R: a boot mouse with 5 buttons, 16 bit X/Y and a wheel
+const MOUSE: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xA1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xA1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Button)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x05, //     Usage Maximum (5)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x75, 0x01, //     Report Size (1)
+    0x95, 0x05, //     Report Count (5)
+    0x81, 0x02, //     Input (Data, Var, Abs)
+    0x75, 0x03, //     Report Size (3)
+    0x95, 0x01, //     Report Count (1)
+    0x81, 0x01, //     Input (Const)
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x16, 0x01, 0x80, //     Logical Minimum (-32767)
+    0x26, 0xFF, 0x7F, //     Logical Maximum (32767)
+    0x75, 0x10, //     Report Size (16)
+    0x95, 0x02, //     Report Count (2)
+    0x81, 0x06, //     Input (Data, Var, Rel)
+    0x09, 0x38, //     Usage (Wheel)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7F, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x01, //     Report Count (1)
+    0x81, 0x06, //     Input (Data, Var, Rel)
+    0xC0, //   End Collection
+    0xC0, // End Collection
+];
+
+/// Interface 1 of the MAD R: keyboard and consumer keys for the side buttons, and the
+/// configuration reports in a vendor collection
+const CONFIG: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xA1, 0x01, // Collection (Application)
+    0x85, 0x01, //   Report ID (1)
+    0x05, 0x07, //   Usage Page (Keyboard)
+    0x19, 0xE0, //   Usage Minimum (Left Control)
+    0x29, 0xE7, //   Usage Maximum (Right GUI)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Var, Abs)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x06, //   Report Count (6)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x2A, 0xFF, 0x00, //   Usage Maximum (255)
+    0x81, 0x00, //   Input (Data, Array)
+    0xC0, // End Collection
+    0x05, 0x0C, // Usage Page (Consumer)
+    0x09, 0x01, // Usage (Consumer Control)
+    0xA1, 0x01, // Collection (Application)
+    0x85, 0x03, //   Report ID (3)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x03, //   Logical Maximum (1023)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x2A, 0xFF, 0x03, //   Usage Maximum (1023)
+    0x75, 0x10, //   Report Size (16)
+    0x95, 0x01, //   Report Count (1)
+    0x81, 0x00, //   Input (Data, Array)
+    0xC0, // End Collection
+    0x06, 0x00, 0xFF, // Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01, // Usage (0x01)
+    0xA1, 0x01, // Collection (Application)
+    0x85, 0x08, //   Report ID (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x10, //   Report Count (16)
+    0x09, 0x01, //   Usage (0x01)
+    0x81, 0x02, //   Input (Data, Var, Abs)
+    0x09, 0x01, //   Usage (0x01)
+    0xB1, 0x02, //   Feature (Data, Var, Abs)
+    0xC0, // End Collection
+];
+
+#[test]
+fn mouse_interface() {
+    let descriptor = ReportDescriptor::parse(MOUSE).unwrap();
+
+    assert_eq!(
+        descriptor.collections,
+        [Collection {
+            usage_page: 0x01,
+            usage: 0x02,
+        }]
+    );
+    // 5 buttons padded to a byte, X, Y and the wheel, in the usage page of the first field
+    assert_eq!(
+        descriptor.reports,
+        [Report {
+            kind: ReportKind::Input,
+            id: 0,
+            usage_page: 0x09,
+            len: 6,
+        }]
+    );
+    assert!(!descriptor.is_config_interface());
+}
+
+#[test]
+fn config_interface() {
+    let descriptor = ReportDescriptor::parse(CONFIG).unwrap();
+
+    let collections: Vec<(u16, u16)> = descriptor
+        .collections
+        .iter()
+        .map(|c| (c.usage_page, c.usage))
+        .collect();
+    assert_eq!(collections, [(0x01, 0x06), (0x0C, 0x01), (0xFF00, 0x01)]);
+
+    let reports: Vec<(ReportKind, u8, usize)> = descriptor
+        .reports
+        .iter()
+        .map(|r| (r.kind, r.id, r.len))
+        .collect();
+    assert_eq!(
+        reports,
+        [
+            (ReportKind::Input, 1, 7),
+            (ReportKind::Input, 3, 2),
+            (ReportKind::Input, 8, 16),
+            (ReportKind::Feature, 8, 16),
+        ]
+    );
+    assert!(descriptor.is_config_interface());
+}
+
+/// Offset of the last occurrence of `items` in the configuration interface's descriptor
+fn find(items: &[u8]) -> usize {
+    CONFIG
+        .windows(items.len())
+        .rposition(|w| w == items)
+        .unwrap()
+}
+
+#[test]
+fn config_report_must_match() {
+    // Report 0x08 with 8 bytes, as output instead of feature, or outside the vendor usage pages
+    for (from, to) in [
+        (&[0x95, 0x10], &[0x95, 0x08]),
+        (&[0xB1, 0x02], &[0x91, 0x02]),
+        (&[0x00, 0xFF], &[0x00, 0xFE]),
+    ] {
+        let at = find(from);
+        let mut data = CONFIG.to_vec();
+        data[at..at + 2].copy_from_slice(to);
+
+        let descriptor = ReportDescriptor::parse(&data).unwrap();
+        assert!(!descriptor.is_config_interface(), "{to:02x?}");
+    }
+}
+
+#[test]
+fn long_items_are_skipped() {
+    // A long item with 3 bytes of data that would read as Report ID (2) and Feature
+    let at = find(&[0x06, 0x00, 0xFF]);
+    let mut data = CONFIG[..at].to_vec();
+    data.extend_from_slice(&[0xFE, 0x03, 0xF0, 0x85, 0x02, 0xB1]);
+    data.extend_from_slice(&CONFIG[at..]);
+
+    let descriptor = ReportDescriptor::parse(&data).unwrap();
+    assert_eq!(descriptor, ReportDescriptor::parse(CONFIG).unwrap());
+    assert!(descriptor.is_config_interface());
+}
+
+#[test]
+fn truncated_descriptors() {
+    // Cut inside the data of a short item
+    let at = find(&[0x26, 0xFF, 0x00]);
+    assert!(matches!(
+        ReportDescriptor::parse(&CONFIG[..at + 2]),
+        Err(MadRError::InvalidReport(_))
+    ));
+    assert!(matches!(
+        ReportDescriptor::parse(&[0x27, 0xFF, 0xFF, 0xFF]),
+        Err(MadRError::InvalidReport(_))
+    ));
+
+    // Long items without their size, or with less data than it says
+    for long in [&[0xFE][..], &[0xFE, 0x03, 0xF0, 0x01]] {
+        assert!(
+            matches!(
+                ReportDescriptor::parse(long),
+                Err(MadRError::InvalidReport(_))
+            ),
+            "{long:02x?}"
+        );
+    }
+
+    assert_eq!(
+        ReportDescriptor::parse(&[]).unwrap(),
+        ReportDescriptor::default()
+    );
+}
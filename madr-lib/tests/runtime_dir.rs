@@ -0,0 +1,55 @@
+// Without $XDG_RUNTIME_DIR, lock files and the madrd socket go to a madr-<uid> directory in the
+// temp directory. Anyone can create that name first, so it is only used while it is a directory
+// of this user that nobody else can get into.
+
+#![cfg(unix)]
+
+use std::env;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::{MetadataExt, PermissionsExt, symlink};
+use std::process;
+
+use madr_lib::ipc;
+
+#[test]
+fn private_fallback() {
+    let tmp = env::temp_dir().join(format!("madr-runtime-{}", process::id()));
+    fs::create_dir_all(&tmp).unwrap();
+    let uid = fs::metadata(&tmp).unwrap().uid();
+    let dir = tmp.join(format!("madr-{uid}"));
+
+    // The only test in this binary reading the environment
+    unsafe {
+        env::remove_var("XDG_RUNTIME_DIR");
+        env::set_var("TMPDIR", &tmp);
+    }
+
+    assert_eq!(ipc::socket_path().unwrap(), dir.join("madrd.sock"));
+    assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+    assert!(ipc::socket_path().is_ok());
+
+    fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+    assert!(ipc::socket_path().is_err());
+    fs::set_permissions(&dir, Permissions::from_mode(0o700)).unwrap();
+
+    // Only root can hand the directory to another user
+    if uid == 0 {
+        std::os::unix::fs::chown(&dir, Some(65534), None).unwrap();
+        assert!(ipc::socket_path().is_err());
+    }
+
+    // Nor is a link to a private directory good enough
+    let elsewhere = tmp.join("elsewhere");
+    fs::rename(&dir, &elsewhere).unwrap();
+    symlink(&elsewhere, &dir).unwrap();
+    assert!(ipc::socket_path().is_err());
+    fs::remove_file(&dir).unwrap();
+
+    unsafe { env::set_var("XDG_RUNTIME_DIR", &elsewhere) };
+    assert_eq!(
+        ipc::socket_path().unwrap(),
+        elsewhere.join("madr").join("madrd.sock")
+    );
+
+    fs::remove_dir_all(tmp).unwrap();
+}
@@ -0,0 +1,85 @@
+// A device handle shared between threads must behave as if every thread had the mouse to
+// itself: each request gets its own response, and read-modify-writes of the same register
+// pair don't undo each other. Retries are off, so a single mixed up response fails the test.
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use madr_lib::dpi;
+use madr_lib::emulator::Emulator;
+use madr_lib::{Battery, Device, Performance, RetryPolicy};
+
+const ROUNDS: u16 = 500;
+
+/// Number of threads sharing the device
+const THREADS: usize = 4;
+
+fn shared_device() -> (Device, Emulator) {
+    let emulator = Emulator::new();
+    let device = emulator
+        .device(false)
+        .with_retry_policy(RetryPolicy::none());
+
+    (device, emulator)
+}
+
+#[test]
+fn device_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    assert_send_sync::<Device>();
+}
+
+#[test]
+fn concurrent_requests_get_their_own_responses() {
+    let (device, emulator) = shared_device();
+    let start = Arc::new(Barrier::new(THREADS));
+
+    let battery = {
+        let device = device.clone();
+        let start = start.clone();
+        thread::spawn(move || {
+            start.wait();
+            for _ in 0..ROUNDS {
+                Battery::read(&device).expect("battery read");
+            }
+        })
+    };
+
+    let performance = {
+        let device = device.clone();
+        let start = start.clone();
+        thread::spawn(move || {
+            start.wait();
+            for _ in 0..ROUNDS {
+                Performance::read(&device).expect("performance read");
+            }
+        })
+    };
+
+    // Stages 1 and 2 share a register pair, both threads read it and write it back
+    let dpi = |stage: u8, base: u16| {
+        let device = device.clone();
+        let start = start.clone();
+        thread::spawn(move || {
+            start.wait();
+            for round in 0..ROUNDS {
+                let dpi = base + round * 50;
+                dpi::apply_dpi_setting(&device, stage, Some(dpi), None, None).expect("dpi write");
+            }
+        })
+    };
+    let first = dpi(1, 1000);
+    let second = dpi(2, 5000);
+
+    for worker in [battery, performance, first, second] {
+        worker.join().unwrap();
+    }
+
+    // Both last writes must have landed, as if they had been made one after the other
+    let last = (ROUNDS - 1) * 50;
+    let (expected_device, expected) = shared_device();
+    dpi::apply_dpi_setting(&expected_device, 1, Some(1000 + last), None, None).unwrap();
+    dpi::apply_dpi_setting(&expected_device, 2, Some(5000 + last), None, None).unwrap();
+
+    assert_eq!(emulator.registers(), expected.registers());
+}
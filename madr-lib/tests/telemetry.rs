@@ -0,0 +1,120 @@
+// A battery log survives a round trip through its file, and the report attributes discharge
+// to the settings in use, leaves out gaps in the log and counts every charge.
+
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+use madr_lib::PollingRate;
+use madr_lib::sensor::Mode;
+use madr_lib::telemetry::{self, ChargeSession, Report, Sample};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+
+fn sample(timestamp: u64, percentage: u8, charging: bool, rate: PollingRate) -> Sample {
+    Sample {
+        timestamp,
+        percentage,
+        voltage_mv: 3700 + percentage as u16 * 5,
+        charging,
+        polling_rate: Some(rate),
+        sensor_mode: Some(Mode::Competitive),
+    }
+}
+
+/// Discharge at `per_hour` for `hours`, one sample an hour starting at `start`
+fn discharge(start: u64, from: u8, per_hour: u8, hours: u8, rate: PollingRate) -> Vec<Sample> {
+    (0..=hours)
+        .map(|i| sample(start + i as u64 * HOUR, from - i * per_hour, false, rate))
+        .collect()
+}
+
+fn seconds(duration: Option<Duration>) -> Option<u64> {
+    duration.map(|d| d.as_secs_f64().round() as u64)
+}
+
+#[test]
+fn log_round_trip() {
+    let path = env::temp_dir().join(format!("madr-battery-{}.csv", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut unknown = sample(2 * HOUR, 50, true, PollingRate::Hz125);
+    unknown.polling_rate = None;
+    unknown.sensor_mode = None;
+
+    // Appended out of order, loaded sorted
+    let samples = [sample(HOUR, 90, false, PollingRate::Hz8000), unknown];
+    for sample in samples.iter().rev() {
+        telemetry::append(&path, sample).unwrap();
+    }
+
+    let contents = fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.lines().next(), Some(telemetry::CSV_HEADER));
+    assert_eq!(telemetry::load(&path).unwrap(), samples);
+
+    fs::write(&path, format!("{}\n1,2,3\n", telemetry::CSV_HEADER)).unwrap();
+    let error = telemetry::load(&path).unwrap_err().to_string();
+    assert!(error.contains(":2: expected 6 fields"), "{}", error);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn report() {
+    // 4 hours at 1000 Hz losing 3 %/h, 2 hours at 8000 Hz losing 6 %/h, then a charge
+    // from 76% to 100% in 2 hours and a last hour at 8000 Hz after a gap of over 4 hours.
+    // The time until the next sample counts for the settings of the previous one.
+    let mut samples = discharge(0, 100, 3, 4, PollingRate::Hz1000);
+    samples.pop();
+    samples.extend(discharge(4 * HOUR, 88, 6, 2, PollingRate::Hz8000));
+    let charge_start = 6 * HOUR + 20 * MINUTE;
+    samples.extend((0..=4).map(|i| {
+        sample(
+            charge_start + i * 30 * MINUTE,
+            76 + i as u8 * 6,
+            true,
+            PollingRate::Hz8000,
+        )
+    }));
+    samples.extend(discharge(13 * HOUR, 100, 6, 1, PollingRate::Hz8000));
+
+    let report = Report::new(&samples).unwrap();
+    assert_eq!(report.samples, samples.len());
+
+    let rates: Vec<(Option<PollingRate>, f64)> = report
+        .usage
+        .iter()
+        .map(|u| (u.polling_rate, u.discharge.per_hour().unwrap()))
+        .collect();
+    assert_eq!(
+        rates,
+        [
+            (Some(PollingRate::Hz1000), 3.0),
+            (Some(PollingRate::Hz8000), 6.0)
+        ]
+    );
+    assert_eq!(
+        seconds(report.usage[1].discharge.full_charge()),
+        Some(100 * HOUR / 6)
+    );
+
+    assert_eq!(
+        report.sessions,
+        [ChargeSession {
+            start: charge_start,
+            end: charge_start + 2 * HOUR,
+            from: 76,
+            to: 100,
+        }]
+    );
+    assert_eq!(report.cycles, 0.24);
+    assert_eq!(report.charge.per_hour(), Some(12.0));
+
+    // 94% left at the 8000 Hz rate
+    assert_eq!(seconds(report.time_to_empty), Some(94 * HOUR / 6));
+    assert_eq!(report.time_to_full, None);
+
+    assert!(Report::new(&[]).is_none());
+}
@@ -0,0 +1,86 @@
+// A session traced against the emulator plays back to the same results when the same calls are
+// made against the trace, and fails as soon as the calls differ from the recorded ones.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use madr_lib::dpi::{self, DpiStage, Rgb};
+use madr_lib::emulator::Emulator;
+use madr_lib::{Battery, Device, MadRError, Performance, PollingRate, Result};
+
+fn trace_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("madr-trace-{}-{}.jsonl", name, process::id()))
+}
+
+fn record(path: &PathBuf) -> Device {
+    Emulator::new().device(false).trace(path).unwrap()
+}
+
+#[derive(Debug, PartialEq)]
+struct Session {
+    before: Performance,
+    after: Performance,
+    stage: DpiStage,
+    color: Rgb,
+    battery: Battery,
+}
+
+/// Reads, a write and the reads that see it
+fn session(device: &Device) -> Result<Session> {
+    let before = Performance::read(device)?;
+    madr_lib::performance::apply_setting(device, &Performance::new(3, PollingRate::Hz500))?;
+
+    Ok(Session {
+        before,
+        after: Performance::read(device)?,
+        stage: dpi::read_stage(device, 3)?,
+        color: dpi::read_stage_color(device, 2)?,
+        battery: Battery::read(device)?,
+    })
+}
+
+#[test]
+fn replay_matches_recording() {
+    let path = trace_path("session");
+    let device = record(&path);
+    let pid = device.product_id();
+    let recorded = session(&device).unwrap();
+    assert_eq!(recorded.after, Performance::new(3, PollingRate::Hz500));
+
+    let replay = Device::replay(&path).unwrap();
+    assert_eq!(replay.product_id(), pid);
+    assert_eq!(session(&replay).unwrap(), recorded);
+
+    // Nothing is left to replay
+    assert!(matches!(
+        Performance::read(&replay),
+        Err(MadRError::Trace(e)) if e.starts_with("Trace ended")
+    ));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn replay_diverges() {
+    let path = trace_path("diverge");
+    session(&record(&path)).unwrap();
+
+    // The trace starts with a performance read, not a battery request
+    let replay = Device::replay(&path).unwrap();
+    assert!(matches!(
+        Battery::read(&replay),
+        Err(MadRError::Trace(e)) if e.starts_with("Replay diverged")
+    ));
+
+    // Same request as recorded, then a different write
+    let replay = Device::replay(&path).unwrap();
+    Performance::read(&replay).unwrap();
+    assert!(matches!(
+        madr_lib::performance::apply_setting(&replay, &Performance::new(2, PollingRate::Hz500)),
+        Err(MadRError::Trace(e)) if e.starts_with("Replay diverged")
+    ));
+
+    fs::remove_file(path).unwrap();
+}
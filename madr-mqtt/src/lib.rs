@@ -0,0 +1,430 @@
+// A bridge between the mouse and an MQTT broker, announced to Home Assistant through MQTT
+// discovery. Every setting gets a retained state topic under <prefix>/madr_<vid>_<pid>, and the
+// settings that can be changed a command topic next to it:
+//   madr/madr_373b_1040/availability       online or offline, also the will of the connection
+//   madr/madr_373b_1040/battery            percentage, a sensor
+//   madr/madr_373b_1040/charging           ON or OFF, a binary sensor
+//   madr/madr_373b_1040/polling_rate[/set] "1000 Hz", a select
+//   madr/madr_373b_1040/sensor_mode[/set]  "basic", a select
+//   madr/madr_373b_1040/dpi_stage[/set]    "1", a select
+// The discovery configs are retained under homeassistant/<component>/madr_<vid>_<pid>/<object>.
+// The mouse is read every interval and after each command, and only what changed is published
+// again. While a wireless mouse is asleep it is reported as offline rather than as stale values.
+
+pub mod packet;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+use madr_lib::model::Feature;
+use madr_lib::sensor::{self, Mode};
+use madr_lib::{Battery, Device, MadRError, Performance, PollingRate, Sensor, performance};
+
+use crate::packet::{Packet, Will};
+
+pub const DEFAULT_PORT: u16 = 1883;
+
+/// How long the broker gets to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+const MODES: [Mode; 3] = [Mode::Basic, Mode::Competitive, Mode::Max];
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// First level of the state and command topics
+    pub prefix: String,
+    /// Where Home Assistant looks for discovery configs
+    pub discovery_prefix: String,
+    /// How often the mouse is read
+    pub interval: Duration,
+    /// Longest time without a packet to the broker before it drops the connection
+    pub keep_alive: Duration,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            prefix: "madr".into(),
+            discovery_prefix: "homeassistant".into(),
+            interval: Duration::from_secs(30),
+            keep_alive: Duration::from_secs(60),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+/// Identifies the mouse in topics and to Home Assistant
+pub fn node_id(device: &Device) -> String {
+    format!(
+        "madr_{:04x}_{:04x}",
+        device.model().vid,
+        device.product_id()
+    )
+}
+
+/// Parse a polling rate as the select shows it, "1000 Hz", or as a plain number
+fn parse_polling_rate(value: &str) -> madr_lib::Result<PollingRate> {
+    let number = value.trim().trim_end_matches("Hz").trim();
+    let rate = number.parse::<u16>().map_err(|_| {
+        MadRError::InvalidPerformanceSetting(format!("Unsupported polling rate: {}", value))
+    })?;
+
+    PollingRate::try_from(rate)
+}
+
+pub struct Bridge {
+    device: Device,
+    options: Options,
+    node_id: String,
+    /// Payloads last published by topic, cleared for every connection
+    published: HashMap<String, String>,
+}
+
+/// Sends packets to the broker, remembering when it last did for the keep alive
+struct Sender {
+    stream: TcpStream,
+    last: Instant,
+}
+
+impl Sender {
+    fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        self.stream.write_all(&packet.encode())?;
+        self.last = Instant::now();
+        Ok(())
+    }
+}
+
+impl Bridge {
+    pub fn new(device: Device, options: Options) -> Self {
+        let node_id = node_id(&device);
+
+        Self {
+            device,
+            options,
+            node_id,
+            published: HashMap::new(),
+        }
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// State topic of `object`, commands go to the same topic with /set appended
+    pub fn topic(&self, object: &str) -> String {
+        format!("{}/{}/{}", self.options.prefix, self.node_id, object)
+    }
+
+    /// Discovery config topic of `object`
+    pub fn config_topic(&self, component: &str, object: &str) -> String {
+        format!(
+            "{}/{}/{}/{}/config",
+            self.options.discovery_prefix, component, self.node_id, object
+        )
+    }
+
+    /// The objects that can be changed through commands
+    fn selects(&self) -> Vec<(&'static str, &'static str, Vec<String>)> {
+        let capabilities = self.device.capabilities();
+
+        let rates = capabilities
+            .polling_rates(self.device.is_wired())
+            .iter()
+            .map(|rate| rate.to_string())
+            .collect();
+        let stages = (1..=capabilities.dpi_stages)
+            .map(|stage| stage.to_string())
+            .collect();
+
+        let mut selects = vec![
+            ("polling_rate", "Polling rate", rates),
+            ("dpi_stage", "DPI stage", stages),
+        ];
+        if capabilities.supports(Feature::SensorMode) {
+            let modes = MODES.iter().map(|mode| mode.to_string()).collect();
+            selects.push(("sensor_mode", "Sensor mode", modes));
+        }
+
+        selects
+    }
+
+    /// Discovery configs by topic, for every entity this mouse has
+    pub fn discovery(&self) -> Vec<(String, Value)> {
+        let model = &self.device.model().name;
+        let entity = |object: &str, name: &str| {
+            json!({
+                "name": name,
+                "unique_id": format!("{}_{}", self.node_id, object),
+                "state_topic": self.topic(object),
+                "availability_topic": self.topic("availability"),
+                "device": {
+                    "identifiers": [self.node_id],
+                    "name": model,
+                    "manufacturer": "VXE",
+                    "model": model,
+                },
+            })
+        };
+
+        let mut configs = Vec::new();
+
+        if self.device.capabilities().supports(Feature::Battery) {
+            let mut battery = entity("battery", "Battery");
+            battery["device_class"] = "battery".into();
+            battery["unit_of_measurement"] = "%".into();
+            battery["state_class"] = "measurement".into();
+            configs.push((self.config_topic("sensor", "battery"), battery));
+
+            let mut charging = entity("charging", "Charging");
+            charging["device_class"] = "battery_charging".into();
+            configs.push((self.config_topic("binary_sensor", "charging"), charging));
+        }
+
+        for (object, name, options) in self.selects() {
+            let mut select = entity(object, name);
+            select["command_topic"] = format!("{}/set", self.topic(object)).into();
+            select["options"] = options.into();
+            configs.push((self.config_topic("select", object), select));
+        }
+
+        configs
+    }
+
+    /// Payloads by state topic, `None` if the mouse didn't answer
+    fn read(&self) -> Option<Vec<(String, String)>> {
+        let capabilities = self.device.capabilities();
+        let performance = Performance::read(&self.device).ok()?;
+
+        let mut state = vec![
+            (
+                self.topic("polling_rate"),
+                performance.polling_rate().to_string(),
+            ),
+            (self.topic("dpi_stage"), performance.dpi_stage().to_string()),
+        ];
+
+        if capabilities.supports(Feature::Battery) {
+            let battery = Battery::read(&self.device).ok()?;
+            let charging = if battery.is_charging() { "ON" } else { "OFF" };
+            state.push((self.topic("battery"), battery.percentage().to_string()));
+            state.push((self.topic("charging"), charging.into()));
+        }
+
+        if capabilities.supports(Feature::SensorMode) {
+            let sensor = Sensor::read(&self.device).ok()?;
+            state.push((self.topic("sensor_mode"), sensor.mode().to_string()));
+        }
+
+        Some(state)
+    }
+
+    /// Apply a command payload to `object`
+    pub fn apply(&self, object: &str, value: &str) -> madr_lib::Result<()> {
+        match object {
+            "polling_rate" => {
+                let rate = parse_polling_rate(value)?;
+                let current = Performance::read(&self.device)?;
+                let settings = Performance::new(current.dpi_stage(), rate);
+                performance::apply_setting(&self.device, &settings)
+            }
+            "dpi_stage" => {
+                let stage = value.trim().parse::<u8>().map_err(|_| {
+                    MadRError::InvalidDpiSetting(format!("Not a DPI stage: {}", value))
+                })?;
+                let current = Performance::read(&self.device)?;
+                let settings = Performance::new(stage, current.polling_rate());
+                performance::apply_setting(&self.device, &settings)
+            }
+            "sensor_mode" => sensor::apply_setting(&self.device, value.trim().parse()?),
+            _ => Err(MadRError::Unsupported(object.into())),
+        }
+    }
+
+    /// Publish `payload` retained, unless it was already
+    fn publish(&mut self, sender: &mut Sender, topic: String, payload: String) -> io::Result<()> {
+        if self.published.get(&topic) == Some(&payload) {
+            return Ok(());
+        }
+
+        sender.send(&Packet::Publish {
+            topic: topic.clone(),
+            payload: payload.clone().into_bytes(),
+            retain: true,
+        })?;
+        self.published.insert(topic, payload);
+
+        Ok(())
+    }
+
+    /// Read the mouse and publish what changed
+    fn update(&mut self, sender: &mut Sender) -> io::Result<()> {
+        let availability = self.topic("availability");
+
+        match self.read() {
+            Some(state) => {
+                self.publish(sender, availability, ONLINE.into())?;
+                for (topic, payload) in state {
+                    self.publish(sender, topic, payload)?;
+                }
+            }
+            None => self.publish(sender, availability, OFFLINE.into())?,
+        }
+
+        Ok(())
+    }
+
+    fn command(&mut self, sender: &mut Sender, topic: &str, payload: &[u8]) -> io::Result<()> {
+        let prefix = format!("{}/{}/", self.options.prefix, self.node_id);
+        let Some(object) = topic
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix("/set"))
+        else {
+            return Ok(());
+        };
+
+        let value = String::from_utf8_lossy(payload);
+        if let Err(e) = self.apply(object, &value) {
+            eprintln!("Failed to set {} to {:?}: {}", object, value, e);
+        }
+
+        // Also after a failure, so a select that was changed in the UI shows the real value again
+        self.published.remove(&self.topic(object));
+        self.update(sender)
+    }
+
+    /// Bridge the mouse over a connection to the broker until the connection is lost
+    pub fn run(&mut self, stream: TcpStream) -> io::Result<()> {
+        let result = self.session(&stream);
+
+        // The reader thread has a clone of the stream, which keeps the connection open and
+        // the thread blocked in a read until it is shut down
+        let _ = stream.shutdown(Shutdown::Both);
+        result
+    }
+
+    fn session(&mut self, stream: &TcpStream) -> io::Result<()> {
+        self.published.clear();
+
+        let mut sender = Sender {
+            stream: stream.try_clone()?,
+            last: Instant::now(),
+        };
+        let keep_alive = self.options.keep_alive.as_secs().min(u16::MAX as u64) as u16;
+        sender.send(&Packet::Connect {
+            client_id: format!("madr-mqtt-{}", self.node_id),
+            keep_alive,
+            username: self.options.username.clone(),
+            password: self.options.password.clone(),
+            will: Some(Will {
+                topic: self.topic("availability"),
+                payload: OFFLINE.as_bytes().to_vec(),
+                retain: true,
+            }),
+        })?;
+
+        let (tx, packets) = mpsc::channel();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            loop {
+                let packet = Packet::read(&mut reader);
+                let failed = packet.is_err();
+                if tx.send(packet).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let closed = || {
+            io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "broker closed the connection",
+            )
+        };
+
+        match packets.recv_timeout(CONNECT_TIMEOUT) {
+            Ok(Ok(Packet::ConnAck { code: 0 })) => {}
+            Ok(Ok(Packet::ConnAck { code })) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("broker refused the connection, return code {}", code),
+                ));
+            }
+            Ok(Ok(packet)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected CONNACK, got {:?}", packet),
+                ));
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "broker didn't accept the connection",
+                ));
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err(closed()),
+        }
+
+        for (topic, config) in self.discovery() {
+            sender.send(&Packet::Publish {
+                topic,
+                payload: config.to_string().into_bytes(),
+                retain: true,
+            })?;
+        }
+
+        let topics = self
+            .selects()
+            .into_iter()
+            .map(|(object, _, _)| format!("{}/set", self.topic(object)))
+            .collect();
+        sender.send(&Packet::Subscribe { id: 1, topics })?;
+
+        self.update(&mut sender)?;
+        let mut next_update = Instant::now() + self.options.interval;
+
+        // Ping well before the broker gives up on the connection
+        let ping_after = self.options.keep_alive / 2;
+
+        loop {
+            let mut deadline = next_update;
+            if ping_after > Duration::ZERO {
+                deadline = deadline.min(sender.last + ping_after);
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+
+            match packets.recv_timeout(timeout) {
+                Ok(Ok(Packet::Publish { topic, payload, .. })) => {
+                    self.command(&mut sender, &topic, &payload)?;
+                }
+                Ok(Ok(Packet::Disconnect)) => return Err(closed()),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(closed()),
+                Ok(Err(e)) => return Err(e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(closed()),
+            }
+
+            let now = Instant::now();
+            if now >= next_update {
+                self.update(&mut sender)?;
+                next_update = now + self.options.interval;
+            }
+            if ping_after > Duration::ZERO && now >= sender.last + ping_after {
+                sender.send(&Packet::PingReq)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+
+use madr_lib::{Device, device};
+use madr_mqtt::{Bridge, Options};
+
+/// How long to wait before connecting to the broker again
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Parser)]
+#[command(name = "madr-mqtt")]
+#[command(version, long_about = None)]
+#[command(about = "Bridge a VXE MAD R series mouse to MQTT, with Home Assistant discovery")]
+struct Cli {
+    /// MQTT broker to connect to, the port defaults to 1883
+    #[arg(long, value_name = "HOST[:PORT]", default_value = "localhost")]
+    broker: String,
+
+    /// User name to log in to the broker with, the password is read from $MADR_MQTT_PASSWORD
+    #[arg(long)]
+    username: Option<String>,
+
+    /// First level of the state and command topics
+    #[arg(long, default_value = "madr")]
+    prefix: String,
+
+    /// Prefix Home Assistant looks for discovery configs under
+    #[arg(long, default_value = "homeassistant")]
+    discovery_prefix: String,
+
+    /// How often the mouse is read, in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    interval: u64,
+
+    /// Open this device even if no known model matches it
+    #[arg(long, value_name = "VID:PID", value_parser = device::parse_device_id)]
+    force_device: Option<(u16, u16)>,
+
+    /// Treat the device given with --force-device as connected by cable
+    #[arg(long, requires = "force_device")]
+    wired: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let device = match cli.force_device {
+        Some((vid, pid)) => Device::open_forced(vid, pid, cli.wired)?,
+        None => Device::open()?,
+    };
+
+    let broker = if cli.broker.contains(':') {
+        cli.broker
+    } else {
+        format!("{}:{}", cli.broker, madr_mqtt::DEFAULT_PORT)
+    };
+
+    let options = Options {
+        prefix: cli.prefix,
+        discovery_prefix: cli.discovery_prefix,
+        interval: Duration::from_secs(cli.interval),
+        username: cli.username,
+        password: std::env::var("MADR_MQTT_PASSWORD").ok(),
+        ..Options::default()
+    };
+    let mut bridge = Bridge::new(device, options);
+
+    loop {
+        let result = TcpStream::connect(&broker).and_then(|stream| {
+            println!("Connected to {} as {}", broker, bridge.node_id());
+            bridge.run(stream)
+        });
+
+        if let Err(e) = result {
+            eprintln!("{}: {}, reconnecting", broker, e);
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
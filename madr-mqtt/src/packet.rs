@@ -0,0 +1,316 @@
+// The MQTT 3.1.1 packets a client publishing at QoS 0 needs, and the ones a broker answers with
+// Encoding and decoding are both here, so a test broker can be built from the same types.
+// Packets received at QoS 1 or 2 are decoded, but never acknowledged: the bridge only
+// subscribes at QoS 0, so brokers never send those.
+
+use std::io::{self, Read};
+
+/// Packet types, the high nibble of the first byte
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// Protocol level of MQTT 3.1.1
+const PROTOCOL_LEVEL: u8 = 4;
+
+/// Largest remaining length the 4 byte encoding allows
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+/// Message the broker publishes when the client goes away without disconnecting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Connect {
+        client_id: String,
+        keep_alive: u16,
+        username: Option<String>,
+        password: Option<String>,
+        will: Option<Will>,
+    },
+    /// Return code 0 means the connection was accepted
+    ConnAck {
+        code: u8,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+    },
+    /// Topic filters, all at QoS 0
+    Subscribe {
+        id: u16,
+        topics: Vec<String>,
+    },
+    SubAck {
+        id: u16,
+        codes: Vec<u8>,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_bytes(out, s.as_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Reads the fields of a packet body
+struct Body<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Body<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(invalid("packet is too short"));
+        }
+
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.u16()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid("string is not UTF-8"))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+
+        let first = match self {
+            Packet::Connect {
+                client_id,
+                keep_alive,
+                username,
+                password,
+                will,
+            } => {
+                put_str(&mut body, "MQTT");
+                body.push(PROTOCOL_LEVEL);
+
+                // Always a clean session, nothing is kept between connections
+                let mut flags = 0b10;
+                if let Some(will) = will {
+                    flags |= 0b100;
+                    if will.retain {
+                        flags |= 0b10_0000;
+                    }
+                }
+                if password.is_some() {
+                    flags |= 0b100_0000;
+                }
+                if username.is_some() {
+                    flags |= 0b1000_0000;
+                }
+                body.push(flags);
+                body.extend_from_slice(&keep_alive.to_be_bytes());
+
+                put_str(&mut body, client_id);
+                if let Some(will) = will {
+                    put_str(&mut body, &will.topic);
+                    put_bytes(&mut body, &will.payload);
+                }
+                if let Some(username) = username {
+                    put_str(&mut body, username);
+                }
+                if let Some(password) = password {
+                    put_str(&mut body, password);
+                }
+
+                CONNECT << 4
+            }
+            Packet::ConnAck { code } => {
+                body.extend_from_slice(&[0, *code]);
+                CONNACK << 4
+            }
+            Packet::Publish {
+                topic,
+                payload,
+                retain,
+            } => {
+                put_str(&mut body, topic);
+                body.extend_from_slice(payload);
+                PUBLISH << 4 | *retain as u8
+            }
+            Packet::Subscribe { id, topics } => {
+                body.extend_from_slice(&id.to_be_bytes());
+                for topic in topics {
+                    put_str(&mut body, topic);
+                    body.push(0);
+                }
+                SUBSCRIBE << 4 | 0b10
+            }
+            Packet::SubAck { id, codes } => {
+                body.extend_from_slice(&id.to_be_bytes());
+                body.extend_from_slice(codes);
+                SUBACK << 4
+            }
+            Packet::PingReq => PINGREQ << 4,
+            Packet::PingResp => PINGRESP << 4,
+            Packet::Disconnect => DISCONNECT << 4,
+        };
+
+        let mut packet = vec![first];
+
+        // Remaining length, 7 bits at a time with the high bit set on all but the last byte
+        let mut len = body.len();
+        loop {
+            let mut byte = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                byte |= 0x80;
+            }
+            packet.push(byte);
+
+            if len == 0 {
+                break;
+            }
+        }
+
+        packet.extend_from_slice(&body);
+        packet
+    }
+
+    /// Read the next packet, blocking until it has arrived in full
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        let first = byte[0];
+
+        let mut len = 0usize;
+        let mut multiplier = 1usize;
+        loop {
+            reader.read_exact(&mut byte)?;
+            len += (byte[0] & 0x7f) as usize * multiplier;
+
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+
+            multiplier *= 128;
+            if multiplier > 128 * 128 * 128 {
+                return Err(invalid("remaining length is too long"));
+            }
+        }
+        if len > MAX_REMAINING_LENGTH {
+            return Err(invalid("remaining length is too long"));
+        }
+
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data)?;
+        let mut body = Body { data: &data };
+
+        let packet = match first >> 4 {
+            CONNECT => {
+                if body.string()? != "MQTT" || body.u8()? != PROTOCOL_LEVEL {
+                    return Err(invalid("not MQTT 3.1.1"));
+                }
+
+                let flags = body.u8()?;
+                let keep_alive = body.u16()?;
+                let client_id = body.string()?;
+
+                let will = if flags & 0b100 != 0 {
+                    Some(Will {
+                        topic: body.string()?,
+                        payload: body.bytes()?,
+                        retain: flags & 0b10_0000 != 0,
+                    })
+                } else {
+                    None
+                };
+                let username = (flags & 0b1000_0000 != 0)
+                    .then(|| body.string())
+                    .transpose()?;
+                let password = (flags & 0b100_0000 != 0)
+                    .then(|| body.string())
+                    .transpose()?;
+
+                Packet::Connect {
+                    client_id,
+                    keep_alive,
+                    username,
+                    password,
+                    will,
+                }
+            }
+            CONNACK => {
+                body.u8()?;
+                Packet::ConnAck { code: body.u8()? }
+            }
+            PUBLISH => {
+                let topic = body.string()?;
+                if (first >> 1) & 0b11 != 0 {
+                    // Packet identifier, only there above QoS 0
+                    body.u16()?;
+                }
+
+                Packet::Publish {
+                    topic,
+                    payload: body.rest().to_vec(),
+                    retain: first & 1 != 0,
+                }
+            }
+            SUBSCRIBE => {
+                let id = body.u16()?;
+                let mut topics = Vec::new();
+                while !body.data.is_empty() {
+                    topics.push(body.string()?);
+                    body.u8()?;
+                }
+
+                Packet::Subscribe { id, topics }
+            }
+            SUBACK => Packet::SubAck {
+                id: body.u16()?,
+                codes: body.rest().to_vec(),
+            },
+            PINGREQ => Packet::PingReq,
+            PINGRESP => Packet::PingResp,
+            DISCONNECT => Packet::Disconnect,
+            kind => return Err(invalid(&format!("unsupported packet type {}", kind))),
+        };
+
+        Ok(packet)
+    }
+}
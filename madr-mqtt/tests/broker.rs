@@ -0,0 +1,259 @@
+// An emulated MAD R bridged to a minimal broker running in the test, built on the same packet
+// codec: the bridge announces itself to Home Assistant, publishes the mouse's state and applies
+// commands sent to the select topics.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use madr_lib::emulator::Emulator;
+use madr_lib::sensor::Mode;
+use madr_lib::{Performance, PollingRate, Sensor};
+use madr_mqtt::packet::{Packet, Will};
+use madr_mqtt::{Bridge, Options};
+
+const NODE: &str = "madr_373b_1040";
+
+/// The broker side of a single client connection, keeping what was published retained
+struct Broker {
+    stream: TcpStream,
+    retained: HashMap<String, Vec<u8>>,
+    subscriptions: Vec<String>,
+}
+
+impl Broker {
+    fn send(&mut self, packet: Packet) {
+        self.stream.write_all(&packet.encode()).unwrap();
+    }
+
+    /// Handle packets from the bridge until `done` holds
+    fn until(&mut self, done: impl Fn(&Self) -> bool) {
+        while !done(self) {
+            match Packet::read(&mut self.stream).unwrap() {
+                Packet::Publish {
+                    topic,
+                    payload,
+                    retain,
+                } => {
+                    assert!(retain, "{} was not retained", topic);
+                    self.retained.insert(topic, payload);
+                }
+                Packet::Subscribe { id, topics } => {
+                    let codes = vec![0; topics.len()];
+                    self.subscriptions.extend(topics);
+                    self.send(Packet::SubAck { id, codes });
+                }
+                Packet::PingReq => self.send(Packet::PingResp),
+                packet => panic!("unexpected {:?}", packet),
+            }
+        }
+    }
+
+    fn state(&self, object: &str) -> Option<&str> {
+        let payload = self.retained.get(&format!("madr/{}/{}", NODE, object))?;
+        std::str::from_utf8(payload).ok()
+    }
+
+    fn config(&self, component: &str, object: &str) -> Value {
+        let topic = format!("homeassistant/{}/{}/{}/config", component, NODE, object);
+        serde_json::from_slice(&self.retained[&topic]).unwrap()
+    }
+
+    /// Send a command and wait until the state of `object` is `expected`
+    fn command(&mut self, object: &str, payload: &str, expected: &str) {
+        let topic = format!("madr/{}/{}/set", NODE, object);
+        self.send(Packet::Publish {
+            topic,
+            payload: payload.as_bytes().to_vec(),
+            retain: false,
+        });
+        self.until(|broker| broker.state(object) == Some(expected));
+    }
+}
+
+#[test]
+fn bridge_with_discovery() {
+    let emulator = Emulator::new();
+    emulator.set_battery(87, true, 4012);
+    let device = emulator.device(false);
+    let model = device.model().clone();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let bridge = thread::spawn(move || {
+        let options = Options {
+            interval: Duration::from_secs(60),
+            ..Options::default()
+        };
+        let mut bridge = Bridge::new(device, options);
+        bridge.run(TcpStream::connect(address).unwrap())
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut broker = Broker {
+        stream,
+        retained: HashMap::new(),
+        subscriptions: Vec::new(),
+    };
+
+    let Packet::Connect {
+        client_id, will, ..
+    } = Packet::read(&mut broker.stream).unwrap()
+    else {
+        panic!("expected CONNECT");
+    };
+    assert_eq!(client_id, format!("madr-mqtt-{}", NODE));
+    assert_eq!(
+        will,
+        Some(Will {
+            topic: format!("madr/{}/availability", NODE),
+            payload: b"offline".to_vec(),
+            retain: true,
+        })
+    );
+    broker.send(Packet::ConnAck { code: 0 });
+
+    broker.until(|broker| broker.state("sensor_mode").is_some());
+    assert_eq!(broker.state("availability"), Some("online"));
+    assert_eq!(broker.state("battery"), Some("87"));
+    assert_eq!(broker.state("charging"), Some("ON"));
+    assert_eq!(broker.state("polling_rate"), Some("1000 Hz"));
+    assert_eq!(broker.state("dpi_stage"), Some("1"));
+    assert_eq!(broker.state("sensor_mode"), Some("basic"));
+
+    // Home Assistant discovery
+    let battery = broker.config("sensor", "battery");
+    assert_eq!(battery["device_class"], "battery");
+    assert_eq!(battery["unit_of_measurement"], "%");
+    assert_eq!(battery["state_topic"], format!("madr/{}/battery", NODE));
+    assert_eq!(battery["device"]["identifiers"][0], NODE);
+    assert_eq!(battery["device"]["model"], model.name.as_str());
+
+    let charging = broker.config("binary_sensor", "charging");
+    assert_eq!(charging["device_class"], "battery_charging");
+
+    let rates = broker.config("select", "polling_rate");
+    let expected: Vec<String> = model
+        .capabilities
+        .wireless_polling_rates
+        .iter()
+        .map(|rate| rate.to_string())
+        .collect();
+    assert_eq!(rates["options"], serde_json::json!(expected));
+    let command_topic = format!("madr/{}/polling_rate/set", NODE);
+    assert_eq!(rates["command_topic"], command_topic.as_str());
+    assert!(broker.subscriptions.contains(&command_topic));
+
+    let stages = broker.config("select", "dpi_stage");
+    assert_eq!(
+        stages["options"].as_array().unwrap().len(),
+        model.capabilities.dpi_stages as usize
+    );
+    let modes = broker.config("select", "sensor_mode");
+    assert_eq!(
+        modes["options"],
+        serde_json::json!(["basic", "competitive", "max"])
+    );
+
+    // Commands are applied to the mouse and its state published again
+    let check = emulator.device(false);
+
+    broker.command("polling_rate", "500 Hz", "500 Hz");
+    broker.command("dpi_stage", "3", "3");
+    let performance = Performance::read(&check).unwrap();
+    assert_eq!(performance.polling_rate(), PollingRate::Hz500);
+    assert_eq!(performance.dpi_stage(), 3);
+
+    // A stage the mouse doesn't have changes nothing, and the bridge carries on
+    broker.command("dpi_stage", "99", "3");
+    broker.command("sensor_mode", "competitive", "competitive");
+    assert_eq!(Sensor::read(&check).unwrap().mode(), Mode::Competitive);
+
+    // Losing the broker ends the run, for the caller to reconnect
+    drop(broker);
+    assert!(bridge.join().unwrap().is_err());
+}
+
+#[test]
+fn connection_is_closed_when_refused() {
+    let device = Emulator::new().device(false);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let bridge = thread::spawn(move || {
+        let mut bridge = Bridge::new(device, Options::default());
+        bridge.run(TcpStream::connect(address).unwrap())
+    });
+
+    let (mut stream, _) = listener.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    assert!(matches!(
+        Packet::read(&mut stream).unwrap(),
+        Packet::Connect { .. }
+    ));
+
+    // Not authorized
+    stream
+        .write_all(&Packet::ConnAck { code: 5 }.encode())
+        .unwrap();
+    let error = bridge.join().unwrap().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+
+    // Nothing holds the connection open after the run
+    let mut rest = Vec::new();
+    assert_eq!(stream.read_to_end(&mut rest).unwrap(), 0);
+}
+
+#[test]
+fn packets_round_trip() {
+    let packets = [
+        Packet::Connect {
+            client_id: "madr".into(),
+            keep_alive: 60,
+            username: Some("user".into()),
+            password: Some("secret".into()),
+            will: Some(Will {
+                topic: "madr/availability".into(),
+                payload: b"offline".to_vec(),
+                retain: true,
+            }),
+        },
+        Packet::ConnAck { code: 5 },
+        Packet::Publish {
+            topic: "madr/battery".into(),
+            // Long enough for a two byte remaining length
+            payload: vec![b'x'; 300],
+            retain: true,
+        },
+        Packet::Subscribe {
+            id: 7,
+            topics: vec!["a/set".into(), "b/set".into()],
+        },
+        Packet::SubAck {
+            id: 7,
+            codes: vec![0, 0x80],
+        },
+        Packet::PingReq,
+        Packet::PingResp,
+        Packet::Disconnect,
+    ];
+
+    for packet in packets {
+        let encoded = packet.encode();
+        assert_eq!(Packet::read(&mut encoded.as_slice()).unwrap(), packet);
+    }
+
+    // A truncated packet is an error rather than a short read
+    let encoded = Packet::PingReq.encode();
+    assert!(Packet::read(&mut &encoded[..1]).is_err());
+}
@@ -0,0 +1,108 @@
+// Packets against bytes laid out by hand from the MQTT 3.1.1 specification, so the codec
+// can't agree with itself on something a broker would read differently. Section numbers are
+// those of the OASIS standard.
+
+use madr_mqtt::packet::{Packet, Will};
+
+fn assert_codec(packet: Packet, bytes: &[u8]) {
+    assert_eq!(packet.encode(), bytes, "{packet:?}");
+    assert_eq!(Packet::read(&mut &bytes[..]).unwrap(), packet);
+}
+
+#[test]
+fn connect() {
+    // 3.1: the variable header of figure 3.6 without a will QoS, which the bridge never asks for
+    let bytes = [
+        0x10, 0x1E, // CONNECT, remaining length 30
+        0x00, 0x04, b'M', b'Q', b'T', b'T', // 3.1.2.1 protocol name
+        0x04, // 3.1.2.2 protocol level
+        0xE6, // 3.1.2.3 user name, password, will retain, will flag, clean session
+        0x00, 0x0A, // 3.1.2.10 keep alive of 10 s
+        0x00, 0x04, b'm', b'a', b'd', b'r', // 3.1.3.1 client identifier
+        0x00, 0x01, b't', // 3.1.3.2 will topic
+        0x00, 0x03, b'o', b'f', b'f', // 3.1.3.3 will message
+        0x00, 0x01, b'u', // 3.1.3.4 user name
+        0x00, 0x01, b'p', // 3.1.3.5 password
+    ];
+    assert_codec(
+        Packet::Connect {
+            client_id: "madr".into(),
+            keep_alive: 10,
+            username: Some("u".into()),
+            password: Some("p".into()),
+            will: Some(Will {
+                topic: "t".into(),
+                payload: b"off".to_vec(),
+                retain: true,
+            }),
+        },
+        &bytes,
+    );
+
+    // Only the clean session flag
+    let bytes = [
+        0x10, 0x10, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x3C, 0x00, 0x04, b'm',
+        b'a', b'd', b'r',
+    ];
+    assert_codec(
+        Packet::Connect {
+            client_id: "madr".into(),
+            keep_alive: 60,
+            username: None,
+            password: None,
+            will: None,
+        },
+        &bytes,
+    );
+}
+
+#[test]
+fn subscribe() {
+    // 3.8: packet identifier 10 as in figure 3.21, the topics of figure 3.22 at QoS 0
+    let bytes = [
+        0x82, 0x0E, // SUBSCRIBE with the reserved bits 0010 of 3.8.1, remaining length 14
+        0x00, 0x0A, // 3.8.2 packet identifier
+        0x00, 0x03, b'a', b'/', b'b', 0x00, // 3.8.3 topic filter and requested QoS
+        0x00, 0x03, b'c', b'/', b'd', 0x00,
+    ];
+    assert_codec(
+        Packet::Subscribe {
+            id: 10,
+            topics: vec!["a/b".into(), "c/d".into()],
+        },
+        &bytes,
+    );
+
+    // 3.9: the SUBACK of figure 3.26, granting QoS 0 and refusing the other
+    assert_codec(
+        Packet::SubAck {
+            id: 10,
+            codes: vec![0x00, 0x80],
+        },
+        &[0x90, 0x04, 0x00, 0x0A, 0x00, 0x80],
+    );
+}
+
+#[test]
+fn remaining_length() {
+    // 2.2.3, table 2.4: the boundaries of one, two and three byte lengths
+    for (len, encoded) in [
+        (127, &[0x7F][..]),
+        (128, &[0x80, 0x01]),
+        (16_383, &[0xFF, 0x7F]),
+        (16_384, &[0x80, 0x80, 0x01]),
+    ] {
+        // A topic of one byte takes 3 of the remaining length
+        let packet = Packet::Publish {
+            topic: "t".into(),
+            payload: vec![b'x'; len - 3],
+            retain: false,
+        };
+
+        let bytes = packet.encode();
+        assert_eq!(bytes[0], 0x30);
+        assert_eq!(&bytes[1..1 + encoded.len()], encoded, "{len}");
+        assert_eq!(bytes.len(), 1 + encoded.len() + len);
+        assert_eq!(Packet::read(&mut &bytes[..]).unwrap(), packet);
+    }
+}
@@ -0,0 +1,275 @@
+// Desktop notifications through org.freedesktop.Notifications, for when the battery runs low,
+// charging starts or completes, and a toast when the active DPI stage changes.
+// The mouse is polled, through madrd if it is running. `Monitor` turns what it reads into
+// events, `Notifier` shows them. Battery notifications replace each other, as do DPI toasts,
+// so there is never more than one of each on screen.
+
+use std::collections::HashMap;
+
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{Structure, Value};
+
+use madr_lib::backend::Backend;
+use madr_lib::dpi::{DpiStage, Rgb};
+use madr_lib::{Battery, Feature, MadRError, Result};
+
+pub const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+pub const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+pub const APP_NAME: &str = "madr-notify";
+
+/// How long a DPI toast stays up, in milliseconds
+const TOAST_TIMEOUT: i32 = 1500;
+
+/// Side of the square color swatch shown in DPI toasts, in pixels
+const SWATCH_SIZE: i32 = 16;
+
+// Urgency levels from the notification spec
+const URGENCY_LOW: u8 = 0;
+const URGENCY_NORMAL: u8 = 1;
+const URGENCY_CRITICAL: u8 = 2;
+
+/// Errors after which the mouse should be opened again, it or madrd went away
+pub fn is_disconnect(error: &MadRError) -> bool {
+    match error {
+        MadRError::Io(_) | MadRError::DeviceNotFound | MadRError::Daemon(_) => true,
+        #[cfg(feature = "hidapi")]
+        MadRError::HidApiInit(_) => true,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The percentage dropped to or below `threshold`
+    BatteryLow {
+        percentage: u8,
+        threshold: u8,
+        /// `threshold` is the lowest one
+        critical: bool,
+    },
+    ChargingStarted {
+        percentage: u8,
+    },
+    ChargingComplete,
+    DpiStage {
+        stage: u8,
+        dpi: DpiStage,
+        /// `None` if the model has no DPI colors
+        color: Option<Rgb>,
+    },
+}
+
+impl Event {
+    pub fn summary(&self) -> String {
+        match self {
+            Event::BatteryLow { .. } => "Mouse battery low".into(),
+            Event::ChargingStarted { .. } => "Mouse charging".into(),
+            Event::ChargingComplete => "Mouse charged".into(),
+            Event::DpiStage { stage, .. } => format!("DPI stage {}", stage),
+        }
+    }
+
+    pub fn body(&self) -> String {
+        match self {
+            Event::BatteryLow { percentage, .. } => format!("{}% left", percentage),
+            Event::ChargingStarted { percentage } => format!("Battery at {}%", percentage),
+            Event::ChargingComplete => "Battery at 100%".into(),
+            Event::DpiStage {
+                dpi,
+                color: Some(color),
+                ..
+            } => format!("{} DPI, color {}", dpi, color.hex()),
+            Event::DpiStage { dpi, .. } => format!("{} DPI", dpi),
+        }
+    }
+
+    /// Name from the freedesktop icon naming spec
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Event::BatteryLow { critical: true, .. } => "battery-caution",
+            Event::BatteryLow { .. } => "battery-low",
+            Event::ChargingStarted { .. } => "battery-good-charging",
+            Event::ChargingComplete => "battery-full-charged",
+            Event::DpiStage { .. } => "input-mouse",
+        }
+    }
+
+    fn urgency(&self) -> u8 {
+        match self {
+            Event::BatteryLow { critical: true, .. } => URGENCY_CRITICAL,
+            Event::BatteryLow { .. } | Event::ChargingComplete => URGENCY_NORMAL,
+            Event::ChargingStarted { .. } | Event::DpiStage { .. } => URGENCY_LOW,
+        }
+    }
+
+    fn is_toast(&self) -> bool {
+        matches!(self, Event::DpiStage { .. })
+    }
+}
+
+/// Turns readings of the mouse into events
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    /// Highest first
+    thresholds: Vec<u8>,
+    battery: Option<Battery>,
+    stage: Option<u8>,
+}
+
+impl Monitor {
+    /// Warn when the battery drops to each of `thresholds` percent
+    pub fn new(thresholds: &[u8]) -> Self {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_unstable_by(|a, b| b.cmp(a));
+        thresholds.dedup();
+
+        Self {
+            thresholds,
+            battery: None,
+            stage: None,
+        }
+    }
+
+    /// Event for a new battery reading. The first one only warns if the battery is low already.
+    pub fn battery(&mut self, battery: &Battery) -> Option<Event> {
+        let previous = self.battery.replace(battery.clone());
+        let percentage = battery.percentage();
+
+        if battery.is_charging() {
+            return match previous {
+                Some(p) if !p.is_charging() => Some(Event::ChargingStarted { percentage }),
+                Some(p) if p.percentage() < 100 && percentage >= 100 => {
+                    Some(Event::ChargingComplete)
+                }
+                _ => None,
+            };
+        }
+
+        // Only the lowest threshold crossed, when several are at once
+        let above = previous.map_or(u8::MAX, |p| p.percentage());
+        let threshold = self
+            .thresholds
+            .iter()
+            .copied()
+            .rfind(|&t| percentage <= t && above > t)?;
+
+        Some(Event::BatteryLow {
+            percentage,
+            threshold,
+            critical: self.thresholds.last() == Some(&threshold),
+        })
+    }
+
+    /// Whether a new active stage differs from the last one read
+    pub fn stage(&mut self, stage: u8) -> bool {
+        self.stage.replace(stage).is_some_and(|s| s != stage)
+    }
+
+    pub fn poll_battery(&mut self, mouse: &Backend) -> Result<Option<Event>> {
+        let battery = mouse.battery()?;
+        Ok(self.battery(&battery))
+    }
+
+    pub fn poll_stage(&mut self, mouse: &Backend) -> Result<Option<Event>> {
+        let stage = mouse.performance()?.dpi_stage();
+        if !self.stage(stage) {
+            return Ok(None);
+        }
+
+        let color = if mouse.capabilities().supports(Feature::DpiColors) {
+            Some(mouse.read_stage_color(stage)?)
+        } else {
+            None
+        };
+
+        Ok(Some(Event::DpiStage {
+            stage,
+            dpi: mouse.read_stage(stage)?,
+            color,
+        }))
+    }
+}
+
+/// Shows events as desktop notifications
+pub struct Notifier<'a> {
+    proxy: Proxy<'a>,
+    /// Notification to replace with the next battery event, 0 for none
+    battery: u32,
+    /// Same for DPI toasts
+    toast: u32,
+}
+
+impl Notifier<'_> {
+    pub fn new(connection: &Connection) -> zbus::Result<Self> {
+        let proxy = Proxy::new(
+            connection,
+            NOTIFICATIONS_NAME,
+            NOTIFICATIONS_PATH,
+            NOTIFICATIONS_NAME,
+        )?;
+
+        Ok(Self {
+            proxy,
+            battery: 0,
+            toast: 0,
+        })
+    }
+
+    /// Show an event, returning the ID of its notification
+    pub fn notify(&mut self, event: &Event) -> zbus::Result<u32> {
+        let mut hints: HashMap<&str, Value> = HashMap::new();
+        hints.insert("urgency", Value::from(event.urgency()));
+        hints.insert("category", Value::from("device"));
+
+        if event.is_toast() {
+            hints.insert("transient", Value::from(true));
+        }
+        if let Event::DpiStage {
+            color: Some(color), ..
+        } = event
+        {
+            hints.insert("image-data", swatch(color));
+        }
+
+        let replaces = if event.is_toast() {
+            &mut self.toast
+        } else {
+            &mut self.battery
+        };
+        let timeout = if event.is_toast() { TOAST_TIMEOUT } else { -1 };
+
+        let id: u32 = self.proxy.call(
+            "Notify",
+            &(
+                APP_NAME,
+                *replaces,
+                event.icon(),
+                event.summary(),
+                event.body(),
+                Vec::<&str>::new(),
+                hints,
+                timeout,
+            ),
+        )?;
+
+        *replaces = id;
+        Ok(id)
+    }
+}
+
+/// Square of a solid color, as the "image-data" hint: width, height, rowstride, has alpha,
+/// bits per sample, channels and RGB bytes
+fn swatch(color: &Rgb) -> Value<'static> {
+    let pixels = (SWATCH_SIZE * SWATCH_SIZE) as usize;
+    let data: Vec<u8> = [color.r(), color.g(), color.b()].repeat(pixels);
+
+    Value::from(Structure::from((
+        SWATCH_SIZE,
+        SWATCH_SIZE,
+        SWATCH_SIZE * 3,
+        false,
+        8i32,
+        3i32,
+        data,
+    )))
+}
@@ -0,0 +1,126 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use zbus::blocking::Connection;
+
+use madr_lib::backend::Backend;
+use madr_lib::{Device, device};
+use madr_notify::{Event, Monitor, Notifier};
+
+#[derive(Parser)]
+#[command(name = "madr-notify")]
+#[command(version, long_about = None)]
+#[command(
+    about = "Desktop notifications for the battery and DPI stage of a VXE MAD R series mouse"
+)]
+struct Cli {
+    /// Battery percentages to warn at
+    #[arg(long, value_name = "PERCENT", value_delimiter = ',', default_values_t = [20, 10, 5])]
+    thresholds: Vec<u8>,
+
+    /// How often the battery is read, in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    battery_interval: u64,
+
+    /// How often the active DPI stage is read, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 500, value_parser = clap::value_parser!(u64).range(1..))]
+    dpi_interval: u64,
+
+    /// Don't show a toast when the DPI stage changes
+    #[arg(long)]
+    no_dpi: bool,
+
+    /// Open the device directly even if madrd is running
+    #[arg(long)]
+    no_daemon: bool,
+
+    /// Open this device even if no known model matches it, implies --no-daemon
+    #[arg(long, value_name = "VID:PID", value_parser = device::parse_device_id)]
+    force_device: Option<(u16, u16)>,
+
+    /// Treat the device given with --force-device as connected by cable
+    #[arg(long, requires = "force_device")]
+    wired: bool,
+}
+
+fn open(cli: &Cli) -> madr_lib::Result<Backend> {
+    if let Some((vid, pid)) = cli.force_device {
+        return Device::open_forced(vid, pid, cli.wired).map(Backend::Direct);
+    }
+
+    if !cli.no_daemon
+        && let Some(mouse) = Backend::daemon()?
+    {
+        return Ok(mouse);
+    }
+
+    Device::open().map(Backend::Direct)
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let connection = Connection::session()?;
+    let mut notifier = Notifier::new(&connection)?;
+    let mut monitor = Monitor::new(&cli.thresholds);
+
+    let battery_interval = Duration::from_secs(cli.battery_interval);
+    let tick = if cli.no_dpi {
+        battery_interval
+    } else {
+        Duration::from_millis(cli.dpi_interval)
+    };
+
+    let mut mouse: Option<Backend> = None;
+    let mut battery_read: Option<Instant> = None;
+    // Only the first of a run of failures is reported, the mouse may be asleep for hours
+    let mut failing = false;
+
+    loop {
+        if mouse.is_none() {
+            match open(&cli) {
+                Ok(m) => mouse = Some(m),
+                Err(e) if !failing => {
+                    eprintln!("madr-notify: {}", e);
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+
+        if let Some(m) = &mouse {
+            let mut events: Vec<madr_lib::Result<Option<Event>>> = Vec::new();
+
+            if battery_read.is_none_or(|read| read.elapsed() >= battery_interval) {
+                battery_read = Some(Instant::now());
+                events.push(monitor.poll_battery(m));
+            }
+            if !cli.no_dpi {
+                events.push(monitor.poll_stage(m));
+            }
+
+            for event in events {
+                match event {
+                    Ok(event) => {
+                        failing = false;
+                        if let Some(event) = event {
+                            notifier.notify(&event)?;
+                        }
+                    }
+                    Err(e) => {
+                        if !failing {
+                            eprintln!("madr-notify: {}", e);
+                            failing = true;
+                        }
+                        if madr_notify::is_disconnect(&e) {
+                            mouse = None;
+                        }
+                    }
+                }
+            }
+        }
+
+        thread::sleep(tick);
+    }
+}
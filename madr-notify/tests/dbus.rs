@@ -0,0 +1,164 @@
+// Notifications for an emulated MAD R, sent to a stub notification server on a private
+// dbus-daemon session bus. Skipped if dbus-daemon is not installed.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use zbus::blocking::connection::Builder;
+use zbus::interface;
+use zbus::zvariant::OwnedValue;
+
+use madr_lib::backend::Backend;
+use madr_lib::emulator::Emulator;
+use madr_lib::{Performance, PollingRate, performance};
+use madr_notify::{Event, Monitor, NOTIFICATIONS_NAME, NOTIFICATIONS_PATH, Notifier};
+use madr_test_support::private_bus;
+
+#[derive(Debug)]
+struct Notification {
+    replaces_id: u32,
+    icon: String,
+    summary: String,
+    body: String,
+    hints: HashMap<String, OwnedValue>,
+    expire_timeout: i32,
+}
+
+#[derive(Default)]
+struct Server {
+    received: Arc<Mutex<Vec<Notification>>>,
+}
+
+#[interface(name = "org.freedesktop.Notifications")]
+impl Server {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        _app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        _actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let mut received = self.received.lock().unwrap();
+        received.push(Notification {
+            replaces_id,
+            icon: app_icon,
+            summary,
+            body,
+            hints,
+            expire_timeout,
+        });
+        received.len() as u32
+    }
+}
+
+#[test]
+fn battery_and_dpi_notifications() {
+    let Some(bus) = private_bus() else {
+        eprintln!("dbus-daemon not found, skipping");
+        return;
+    };
+
+    let server = Server::default();
+    let received = server.received.clone();
+    let _server = Builder::address(bus.address.as_str())
+        .unwrap()
+        .name(NOTIFICATIONS_NAME)
+        .unwrap()
+        .serve_at(NOTIFICATIONS_PATH, server)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let client = Builder::address(bus.address.as_str())
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut notifier = Notifier::new(&client).unwrap();
+
+    let emulator = Emulator::new();
+    let device = emulator.device(false);
+    let mouse = Backend::Direct(device.clone());
+    let mut monitor = Monitor::new(&[5, 20, 10]);
+
+    let poll = |monitor: &mut Monitor| {
+        let events = [monitor.poll_battery(&mouse), monitor.poll_stage(&mouse)];
+        events
+            .into_iter()
+            .filter_map(|event| event.unwrap())
+            .collect::<Vec<Event>>()
+    };
+
+    // Nothing to say about a full battery and the stage the mouse started in
+    assert_eq!(poll(&mut monitor), []);
+
+    // Dropping past two thresholds at once only warns about the lower one
+    emulator.set_battery(9, false, 3600);
+    let events = poll(&mut monitor);
+    assert_eq!(
+        events,
+        [Event::BatteryLow {
+            percentage: 9,
+            threshold: 10,
+            critical: false
+        }]
+    );
+    assert_eq!(events[0].icon(), "battery-low");
+    notifier.notify(&events[0]).unwrap();
+
+    emulator.set_battery(8, false, 3590);
+    assert_eq!(poll(&mut monitor), []);
+
+    emulator.set_battery(5, false, 3500);
+    let events = poll(&mut monitor);
+    assert!(matches!(
+        events[..],
+        [Event::BatteryLow { critical: true, .. }]
+    ));
+    notifier.notify(&events[0]).unwrap();
+
+    emulator.set_battery(5, true, 3900);
+    assert_eq!(
+        poll(&mut monitor),
+        [Event::ChargingStarted { percentage: 5 }]
+    );
+    emulator.set_battery(100, true, 4200);
+    let events = poll(&mut monitor);
+    assert_eq!(events, [Event::ChargingComplete]);
+    notifier.notify(&events[0]).unwrap();
+
+    performance::apply_setting(&device, &Performance::new(3, PollingRate::Hz1000)).unwrap();
+    let events = poll(&mut monitor);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].summary(), "DPI stage 3");
+    notifier.notify(&events[0]).unwrap();
+
+    let received = received.lock().unwrap();
+    let [low, critical, charged, toast] = &received[..] else {
+        panic!("expected 4 notifications, got {:?}", received);
+    };
+
+    assert_eq!(
+        (low.summary.as_str(), low.body.as_str()),
+        ("Mouse battery low", "9% left")
+    );
+    assert_eq!(low.replaces_id, 0);
+    assert_eq!(u8::try_from(&low.hints["urgency"]).unwrap(), 1);
+
+    // Battery notifications replace the previous one
+    assert_eq!(critical.replaces_id, 1);
+    assert_eq!(critical.icon, "battery-caution");
+    assert_eq!(u8::try_from(&critical.hints["urgency"]).unwrap(), 2);
+    assert_eq!(charged.replaces_id, 2);
+    assert_eq!(charged.summary, "Mouse charged");
+
+    assert_eq!(toast.replaces_id, 0);
+    assert_eq!(toast.body, "1600 DPI, color #0000ff");
+    assert!(bool::try_from(&toast.hints["transient"]).unwrap());
+    assert!(toast.hints.contains_key("image-data"));
+    assert!(toast.expire_timeout > 0);
+}
@@ -0,0 +1,681 @@
+// ratbagd compatible D-Bus service, so Piper and other libratbag clients can configure the mouse
+// Implements the org.freedesktop.ratbag1 object model (API version 2) on top of madr-lib:
+//   /org/freedesktop/ratbag1                              Manager
+//   /org/freedesktop/ratbag1/device/<id>                  Device
+//   /org/freedesktop/ratbag1/profile/<id>/p0              Profile, the mouse has only one
+//   /org/freedesktop/ratbag1/resolution/<id>/p0/r<n>      Resolution, DPI stage n + 1
+//   /org/freedesktop/ratbag1/button/<id>/p0/b<n>          Button, can't be remapped yet
+//   /org/freedesktop/ratbag1/led/<id>/p0/l<n>             LED, the color of DPI stage n + 1
+// Like with ratbagd, changes are kept until Device.Commit() writes them to the mouse, which
+// applies them as a madr-lib profile so only what differs is written.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use zbus::blocking::connection::{Builder, Connection};
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Structure, Value};
+use zbus::{ObjectServer, fdo, interface};
+
+use madr_lib::dpi::{self, DpiStage, Rgb};
+use madr_lib::profile::{Profile, StageSettings};
+use madr_lib::{Debounce, Device, Feature, Performance, PollingRate, debounce};
+
+pub const BUS_NAME: &str = "org.freedesktop.ratbag1";
+pub const API_VERSION: i32 = 2;
+pub const MANAGER_PATH: &str = "/org/freedesktop/ratbag1";
+
+/// Left, right, middle and two side buttons
+const BUTTONS: u32 = 5;
+
+// Values from the libratbag D-Bus API
+const RESOLUTION_CAP_SEPARATE_XY: u32 = 1;
+const ACTION_TYPE_BUTTON: u32 = 1;
+const LED_MODE_ON: u32 = 1;
+const LED_COLOR_DEPTH_RGB_888: u32 = 1;
+
+/// Settings as clients see them, the mouse only has them after a commit
+#[derive(Debug, Clone)]
+struct Settings {
+    stages: Vec<DpiStage>,
+    /// Empty if the model has no DPI colors
+    colors: Vec<Rgb>,
+    /// 1-based, like the stages everywhere in madr-lib
+    active_stage: u8,
+    polling_rate: PollingRate,
+    /// `None` if the model has no debounce setting
+    debounce: Option<Debounce>,
+}
+
+impl Settings {
+    fn read(device: &Device) -> madr_lib::Result<Self> {
+        let _guard = device.lock()?;
+        let capabilities = device.capabilities();
+        let count = capabilities.dpi_stages;
+
+        let stages = (1..=count)
+            .map(|stage| dpi::read_stage(device, stage))
+            .collect::<madr_lib::Result<_>>()?;
+
+        let colors = if capabilities.supports(Feature::DpiColors) {
+            (1..=count)
+                .map(|stage| dpi::read_stage_color(device, stage))
+                .collect::<madr_lib::Result<_>>()?
+        } else {
+            Vec::new()
+        };
+
+        let performance = Performance::read(device)?;
+
+        let debounce = if capabilities.supports(Feature::Debounce) {
+            Some(debounce::read(device)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            stages,
+            colors,
+            active_stage: performance.dpi_stage(),
+            polling_rate: performance.polling_rate(),
+            debounce,
+        })
+    }
+
+    fn profile(&self) -> Profile {
+        let dpi = self
+            .stages
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| StageSettings {
+                stage: i as u8 + 1,
+                x_dpi: Some(stage.x_dpi()),
+                y_dpi: Some(stage.y_dpi()),
+                rgb: self.colors.get(i).copied(),
+            })
+            .collect();
+
+        Profile {
+            polling_rate: Some(self.polling_rate),
+            dpi_stage: Some(self.active_stage),
+            dpi,
+            debounce: self.debounce,
+            ..Profile::default()
+        }
+    }
+}
+
+fn object_path(path: String) -> OwnedObjectPath {
+    OwnedObjectPath::try_from(path).expect("object paths are built from [a-z0-9_/]")
+}
+
+#[derive(Debug, Clone)]
+struct Paths {
+    device: OwnedObjectPath,
+    profile: OwnedObjectPath,
+    resolutions: Vec<OwnedObjectPath>,
+    buttons: Vec<OwnedObjectPath>,
+    leds: Vec<OwnedObjectPath>,
+}
+
+impl Paths {
+    fn new(id: &str, resolutions: usize, leds: usize) -> Self {
+        let child = |kind: &str, prefix: &str, count: usize| {
+            (0..count)
+                .map(|i| object_path(format!("{MANAGER_PATH}/{kind}/{id}/p0/{prefix}{i}")))
+                .collect()
+        };
+
+        Self {
+            device: object_path(format!("{MANAGER_PATH}/device/{id}")),
+            profile: object_path(format!("{MANAGER_PATH}/profile/{id}/p0")),
+            resolutions: child("resolution", "r", resolutions),
+            buttons: child("button", "b", BUTTONS as usize),
+            leds: child("led", "l", leds),
+        }
+    }
+}
+
+struct State {
+    settings: Settings,
+    /// Whether the settings changed since the last commit
+    dirty: bool,
+}
+
+struct Shared {
+    device: Device,
+    paths: Paths,
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn settings(&self) -> Settings {
+        self.state().settings.clone()
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Settings)) {
+        let mut state = self.state();
+        change(&mut state.settings);
+        state.dirty = true;
+    }
+
+    /// Write the settings to the mouse. If that fails, they are read back from it instead.
+    fn commit(&self) -> madr_lib::Result<()> {
+        let mut state = self.state();
+        let result = state.settings.profile().apply(&self.device).map(|_| ());
+
+        if result.is_err()
+            && let Ok(settings) = Settings::read(&self.device)
+        {
+            state.settings = settings;
+        }
+
+        state.dirty = false;
+        result
+    }
+
+    fn check_dpi(&self, dpi: u32) -> fdo::Result<u16> {
+        u16::try_from(dpi)
+            .ok()
+            .filter(|dpi| self.device.capabilities().check_dpi(*dpi).is_ok())
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unsupported resolution {}", dpi)))
+    }
+
+    /// Tell clients that IsDirty of the profile may have changed
+    async fn dirty_changed(&self, server: &ObjectServer) -> zbus::Result<()> {
+        let profile = server
+            .interface::<_, RatbagProfile>(&self.paths.profile)
+            .await?;
+        profile
+            .get()
+            .await
+            .is_dirty_changed(profile.signal_emitter())
+            .await
+    }
+}
+
+struct Manager {
+    devices: Vec<OwnedObjectPath>,
+}
+
+#[interface(name = "org.freedesktop.ratbag1.Manager")]
+impl Manager {
+    #[zbus(property(emits_changed_signal = "const"), name = "APIVersion")]
+    fn api_version(&self) -> i32 {
+        API_VERSION
+    }
+
+    #[zbus(property)]
+    fn devices(&self) -> Vec<OwnedObjectPath> {
+        self.devices.clone()
+    }
+}
+
+struct RatbagDevice {
+    shared: Arc<Shared>,
+}
+
+#[interface(name = "org.freedesktop.ratbag1.Device")]
+impl RatbagDevice {
+    /// "usb:VID:PID:0", like libratbag's device IDs
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn model(&self) -> String {
+        let device = &self.shared.device;
+        format!(
+            "usb:{:04x}:{:04x}:0",
+            device.model().vid,
+            device.product_id()
+        )
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn name(&self) -> String {
+        self.shared.device.model().name.clone()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn firmware_version(&self) -> String {
+        String::new()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn profiles(&self) -> Vec<OwnedObjectPath> {
+        vec![self.shared.paths.profile.clone()]
+    }
+
+    /// Write the changes to the mouse, returning 0 on success. On failure the settings are
+    /// read back from the mouse and Resync is emitted.
+    async fn commit(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> fdo::Result<u32> {
+        // Writing takes several reports, which must not block the executor serving the bus
+        let shared = self.shared.clone();
+        let result = blocking::unblock(move || shared.commit()).await;
+        self.shared.dirty_changed(server).await?;
+
+        match result {
+            Ok(()) => Ok(0),
+            Err(e) => {
+                eprintln!("madr-ratbagd: commit failed: {}", e);
+                Self::resync(&emitter).await?;
+                Ok(1)
+            }
+        }
+    }
+
+    /// The settings changed on their own, clients should read them again
+    #[zbus(signal)]
+    async fn resync(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
+
+struct RatbagProfile {
+    shared: Arc<Shared>,
+}
+
+#[interface(name = "org.freedesktop.ratbag1.Profile")]
+impl RatbagProfile {
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn index(&self) -> u32 {
+        0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn name(&self) -> String {
+        String::new()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn disabled(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn is_dirty(&self) -> bool {
+        self.shared.state().dirty
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn capabilities(&self) -> Vec<u32> {
+        Vec::new()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn resolutions(&self) -> Vec<OwnedObjectPath> {
+        self.shared.paths.resolutions.clone()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn buttons(&self) -> Vec<OwnedObjectPath> {
+        self.shared.paths.buttons.clone()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn leds(&self) -> Vec<OwnedObjectPath> {
+        self.shared.paths.leds.clone()
+    }
+
+    #[zbus(property)]
+    fn report_rate(&self) -> u32 {
+        self.shared.settings().polling_rate as u32
+    }
+
+    #[zbus(property)]
+    async fn set_report_rate(
+        &mut self,
+        rate: u32,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let device = &self.shared.device;
+        let rate = u16::try_from(rate)
+            .ok()
+            .and_then(|r| PollingRate::try_from(r).ok())
+            .filter(|r| {
+                device
+                    .capabilities()
+                    .polling_rates(device.is_wired())
+                    .contains(r)
+            })
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unsupported report rate {}", rate)))?;
+
+        self.shared.update(|s| s.polling_rate = rate);
+        Ok(self.is_dirty_changed(&emitter).await?)
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn report_rates(&self) -> Vec<u32> {
+        let device = &self.shared.device;
+        device
+            .capabilities()
+            .polling_rates(device.is_wired())
+            .iter()
+            .map(|r| *r as u32)
+            .collect()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn angle_snapping(&self) -> i32 {
+        -1
+    }
+
+    /// In ms, -1 if the model has no debounce setting
+    #[zbus(property)]
+    fn debounce(&self) -> i32 {
+        self.shared.settings().debounce.map_or(-1, |d| d as i32)
+    }
+
+    #[zbus(property)]
+    async fn set_debounce(
+        &mut self,
+        ms: i32,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let debounce = u8::try_from(ms)
+            .ok()
+            .and_then(|ms| Debounce::try_from(ms).ok())
+            .filter(|_| {
+                self.shared
+                    .device
+                    .capabilities()
+                    .supports(Feature::Debounce)
+            })
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unsupported debounce {}", ms)))?;
+
+        self.shared.update(|s| s.debounce = Some(debounce));
+        Ok(self.is_dirty_changed(&emitter).await?)
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn debounces(&self) -> Vec<u32> {
+        if !self
+            .shared
+            .device
+            .capabilities()
+            .supports(Feature::Debounce)
+        {
+            return Vec::new();
+        }
+
+        (0..=u8::MAX)
+            .filter_map(|ms| Debounce::try_from(ms).ok())
+            .map(|d| d as u32)
+            .collect()
+    }
+
+    /// The only profile is always active
+    fn set_active(&self) {}
+}
+
+/// Resolution from a Resolution property value, (uu) or u for both axes
+fn parse_resolution(value: &Value<'_>) -> Option<(u32, u32)> {
+    match value {
+        Value::U32(dpi) => Some((*dpi, *dpi)),
+        Value::Structure(fields) => match fields.fields() {
+            [Value::U32(x), Value::U32(y)] => Some((*x, *y)),
+            _ => None,
+        },
+        Value::Value(inner) => parse_resolution(inner),
+        _ => None,
+    }
+}
+
+struct RatbagResolution {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+impl RatbagResolution {
+    /// Make this the active stage and tell clients about every resolution that changed with it
+    async fn activate(&self, server: &ObjectServer) -> fdo::Result<()> {
+        self.shared
+            .update(|s| s.active_stage = self.index as u8 + 1);
+
+        for path in &self.shared.paths.resolutions {
+            let resolution = server.interface::<_, RatbagResolution>(path).await?;
+            let emitter = resolution.signal_emitter();
+            let resolution = resolution.get().await;
+            resolution.is_active_changed(emitter).await?;
+            resolution.is_default_changed(emitter).await?;
+        }
+
+        Ok(self.shared.dirty_changed(server).await?)
+    }
+}
+
+#[interface(name = "org.freedesktop.ratbag1.Resolution")]
+impl RatbagResolution {
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn index(&self) -> u32 {
+        self.index as u32
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn capabilities(&self) -> Vec<u32> {
+        vec![RESOLUTION_CAP_SEPARATE_XY]
+    }
+
+    #[zbus(property)]
+    fn is_active(&self) -> bool {
+        self.shared.settings().active_stage as usize == self.index + 1
+    }
+
+    /// The mouse starts in the stage it was in, so the active stage is also the default
+    #[zbus(property)]
+    fn is_default(&self) -> bool {
+        self.is_active()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn is_disabled(&self) -> bool {
+        false
+    }
+
+    /// (uu), X and Y DPI
+    #[zbus(property)]
+    fn resolution(&self) -> fdo::Result<OwnedValue> {
+        let stage = self.shared.settings().stages[self.index];
+        let value = Value::from(Structure::from((
+            stage.x_dpi() as u32,
+            stage.y_dpi() as u32,
+        )));
+
+        Ok(OwnedValue::try_from(value).map_err(zbus::Error::from)?)
+    }
+
+    #[zbus(property)]
+    async fn set_resolution(
+        &mut self,
+        value: OwnedValue,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> fdo::Result<()> {
+        let (x_dpi, y_dpi) = parse_resolution(&value)
+            .ok_or_else(|| fdo::Error::InvalidArgs("Resolution must be (uu) or u".into()))?;
+        let stage = DpiStage::new(self.shared.check_dpi(x_dpi)?, self.shared.check_dpi(y_dpi)?);
+
+        self.shared.update(|s| s.stages[self.index] = stage);
+        Ok(self.shared.dirty_changed(server).await?)
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn resolutions(&self) -> Vec<u32> {
+        let capabilities = self.shared.device.capabilities();
+        (capabilities.min_dpi..=capabilities.max_dpi)
+            .filter(|dpi| capabilities.check_dpi(*dpi).is_ok())
+            .map(u32::from)
+            .collect()
+    }
+
+    async fn set_active(&self, #[zbus(object_server)] server: &ObjectServer) -> fdo::Result<()> {
+        self.activate(server).await
+    }
+
+    async fn set_default(&self, #[zbus(object_server)] server: &ObjectServer) -> fdo::Result<()> {
+        self.activate(server).await
+    }
+}
+
+struct RatbagButton {
+    index: u32,
+}
+
+#[interface(name = "org.freedesktop.ratbag1.Button")]
+impl RatbagButton {
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Every button does what it does by default
+    #[zbus(property)]
+    fn mapping(&self) -> (u32, OwnedValue) {
+        (ACTION_TYPE_BUTTON, OwnedValue::from(self.index + 1))
+    }
+
+    #[zbus(property)]
+    fn set_mapping(&mut self, _mapping: (u32, OwnedValue)) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "Button remapping is not supported".into(),
+        ))
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn action_types(&self) -> Vec<u32> {
+        vec![ACTION_TYPE_BUTTON]
+    }
+}
+
+struct RatbagLed {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+#[interface(name = "org.freedesktop.ratbag1.Led")]
+impl RatbagLed {
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn index(&self) -> u32 {
+        self.index as u32
+    }
+
+    #[zbus(property)]
+    fn mode(&self) -> u32 {
+        LED_MODE_ON
+    }
+
+    #[zbus(property)]
+    fn set_mode(&mut self, mode: u32) -> fdo::Result<()> {
+        if mode == LED_MODE_ON {
+            Ok(())
+        } else {
+            Err(fdo::Error::NotSupported(format!(
+                "Unsupported LED mode {}",
+                mode
+            )))
+        }
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn modes(&self) -> Vec<u32> {
+        vec![LED_MODE_ON]
+    }
+
+    #[zbus(property)]
+    fn color(&self) -> (u32, u32, u32) {
+        let rgb = self.shared.settings().colors[self.index];
+        (rgb.r() as u32, rgb.g() as u32, rgb.b() as u32)
+    }
+
+    #[zbus(property)]
+    async fn set_color(
+        &mut self,
+        color: (u32, u32, u32),
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> fdo::Result<()> {
+        let channel = |c: u32| {
+            u8::try_from(c)
+                .map_err(|_| fdo::Error::InvalidArgs(format!("Color value {} is over 255", c)))
+        };
+        let rgb = Rgb::new(channel(color.0)?, channel(color.1)?, channel(color.2)?);
+
+        self.shared.update(|s| s.colors[self.index] = rgb);
+        Ok(self.shared.dirty_changed(server).await?)
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn color_depth(&self) -> u32 {
+        LED_COLOR_DEPTH_RGB_888
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn effect_duration(&self) -> u32 {
+        0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn brightness(&self) -> u32 {
+        u8::MAX as u32
+    }
+}
+
+/// Serve `device` on the bus `builder` connects to, under ratbagd's well-known name.
+/// The service runs for as long as the returned connection is kept.
+pub fn serve(builder: Builder<'static>, device: Device) -> anyhow::Result<Connection> {
+    let settings = Settings::read(&device)?;
+    let id = format!("{:04x}_{:04x}", device.model().vid, device.product_id());
+    let paths = Paths::new(&id, settings.stages.len(), settings.colors.len());
+
+    let shared = Arc::new(Shared {
+        device,
+        paths: paths.clone(),
+        state: Mutex::new(State {
+            settings,
+            dirty: false,
+        }),
+    });
+
+    let mut builder = builder
+        .name(BUS_NAME)?
+        .serve_at(
+            MANAGER_PATH,
+            Manager {
+                devices: vec![paths.device.clone()],
+            },
+        )?
+        .serve_at(
+            paths.device.clone(),
+            RatbagDevice {
+                shared: shared.clone(),
+            },
+        )?
+        .serve_at(
+            paths.profile.clone(),
+            RatbagProfile {
+                shared: shared.clone(),
+            },
+        )?;
+
+    for (index, path) in paths.resolutions.iter().enumerate() {
+        let shared = shared.clone();
+        builder = builder.serve_at(path.clone(), RatbagResolution { shared, index })?;
+    }
+
+    for (index, path) in paths.buttons.iter().enumerate() {
+        let index = index as u32;
+        builder = builder.serve_at(path.clone(), RatbagButton { index })?;
+    }
+
+    for (index, path) in paths.leds.iter().enumerate() {
+        let shared = shared.clone();
+        builder = builder.serve_at(path.clone(), RatbagLed { shared, index })?;
+    }
+
+    Ok(builder.build()?)
+}
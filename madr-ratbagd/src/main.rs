@@ -0,0 +1,47 @@
+use std::thread;
+
+use clap::Parser;
+use zbus::blocking::connection::Builder;
+
+use madr_lib::{Device, device};
+
+#[derive(Parser)]
+#[command(name = "madr-ratbagd")]
+#[command(version, long_about = None)]
+#[command(about = "Offer a VXE MAD R series mouse to Piper through the ratbagd D-Bus API")]
+struct Cli {
+    /// Use the session bus instead of the system bus, which needs no D-Bus policy
+    #[arg(long)]
+    session: bool,
+
+    /// Open this device even if no known model matches it
+    #[arg(long, value_name = "VID:PID", value_parser = device::parse_device_id)]
+    force_device: Option<(u16, u16)>,
+
+    /// Treat the device given with --force-device as connected by cable
+    #[arg(long, requires = "force_device")]
+    wired: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let device = match cli.force_device {
+        Some((vid, pid)) => Device::open_forced(vid, pid, cli.wired)?,
+        None => Device::open()?,
+    };
+
+    let builder = if cli.session {
+        Builder::session()?
+    } else {
+        Builder::system()?
+    };
+
+    let name = device.model().name.clone();
+    let _connection = madr_ratbagd::serve(builder, device)?;
+    println!("Serving {} as {}", name, madr_ratbagd::BUS_NAME);
+
+    loop {
+        thread::park();
+    }
+}
@@ -0,0 +1,118 @@
+// Piper's view of the mouse: an emulated MAD R served on a private dbus-daemon session bus,
+// configured through the ratbagd API. Nothing reaches the mouse before Commit().
+// Skipped if dbus-daemon is not installed.
+
+use zbus::blocking::Proxy;
+use zbus::blocking::connection::{Builder, Connection};
+use zbus::blocking::proxy::Builder as ProxyBuilder;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Structure, Value};
+
+use madr_lib::emulator::Emulator;
+use madr_lib::{Debounce, Performance, PollingRate, debounce, dpi};
+use madr_ratbagd::{API_VERSION, BUS_NAME, MANAGER_PATH};
+use madr_test_support::private_bus;
+
+/// Reads every property from the service, a cached one may not have seen PropertiesChanged yet
+fn proxy<'a>(client: &Connection, path: &'a str, interface: &'a str) -> Proxy<'a> {
+    ProxyBuilder::new(client)
+        .destination(BUS_NAME)
+        .unwrap()
+        .path(path)
+        .unwrap()
+        .interface(format!("org.freedesktop.ratbag1.{}", interface))
+        .unwrap()
+        .cache_properties(CacheProperties::No)
+        .build()
+        .unwrap()
+}
+
+fn paths(proxy: &Proxy<'_>, property: &str) -> Vec<OwnedObjectPath> {
+    proxy.get_property(property).unwrap()
+}
+
+#[test]
+fn piper_configures_the_mouse() {
+    let Some(bus) = private_bus() else {
+        eprintln!("dbus-daemon not found, skipping");
+        return;
+    };
+
+    let device = Emulator::new().device(false);
+
+    let builder = Builder::address(bus.address.as_str()).unwrap();
+    let _service = madr_ratbagd::serve(builder, device.clone()).unwrap();
+    let client = Builder::address(bus.address.as_str())
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let manager = proxy(&client, MANAGER_PATH, "Manager");
+    assert_eq!(
+        manager.get_property::<i32>("APIVersion").unwrap(),
+        API_VERSION
+    );
+    let devices = paths(&manager, "Devices");
+    assert_eq!(devices.len(), 1);
+
+    let ratbag_device = proxy(&client, devices[0].as_str(), "Device");
+    assert_eq!(
+        ratbag_device.get_property::<String>("Name").unwrap(),
+        "VXE MAD R"
+    );
+    let profile_path = paths(&ratbag_device, "Profiles").remove(0);
+    let profile = proxy(&client, profile_path.as_str(), "Profile");
+
+    let resolution_paths = paths(&profile, "Resolutions");
+    let led_paths = paths(&profile, "Leds");
+    assert_eq!((resolution_paths.len(), led_paths.len()), (8, 8));
+    assert!(!profile.get_property::<bool>("IsDirty").unwrap());
+    assert_eq!(profile.get_property::<i32>("Debounce").unwrap(), 8);
+
+    let stage_2 = proxy(&client, resolution_paths[1].as_str(), "Resolution");
+    stage_2
+        .set_property(
+            "Resolution",
+            Value::from(Structure::from((1250u32, 1250u32))),
+        )
+        .unwrap();
+    let resolution: OwnedValue = stage_2.get_property("Resolution").unwrap();
+    assert_eq!(<(u32, u32)>::try_from(resolution).unwrap(), (1250, 1250));
+    assert!(profile.get_property::<bool>("IsDirty").unwrap());
+
+    let stage_3 = proxy(&client, resolution_paths[2].as_str(), "Resolution");
+    stage_3.call_method("SetActive", &()).unwrap();
+    assert!(stage_3.get_property::<bool>("IsActive").unwrap());
+    let stage_1 = proxy(&client, resolution_paths[0].as_str(), "Resolution");
+    assert!(!stage_1.get_property::<bool>("IsActive").unwrap());
+
+    let led_2 = proxy(&client, led_paths[1].as_str(), "Led");
+    led_2.set_property("Color", (1u32, 2u32, 3u32)).unwrap();
+    profile.set_property("ReportRate", 500u32).unwrap();
+    profile.set_property("Debounce", 4i32).unwrap();
+
+    // Invalid values are refused right away
+    assert!(stage_2.set_property("Resolution", 123u32).is_err());
+    assert!(profile.set_property("ReportRate", 300u32).is_err());
+    let button_paths = paths(&profile, "Buttons");
+    let button = proxy(&client, button_paths[0].as_str(), "Button");
+    let mapping = (2u32, Value::from(0u32));
+    assert!(button.set_property("Mapping", mapping).is_err());
+
+    // Nothing was written yet
+    assert_eq!(dpi::read_stage(&device, 2).unwrap().x_dpi(), 800);
+
+    let status: u32 = ratbag_device.call("Commit", &()).unwrap();
+    assert_eq!(status, 0);
+    assert!(!profile.get_property::<bool>("IsDirty").unwrap());
+
+    assert_eq!(dpi::read_stage(&device, 2).unwrap().x_dpi(), 1250);
+    assert_eq!(
+        dpi::read_stage_color(&device, 2).unwrap().to_string(),
+        "1,2,3"
+    );
+    let performance = Performance::read(&device).unwrap();
+    assert_eq!(performance.dpi_stage(), 3);
+    assert_eq!(performance.polling_rate(), PollingRate::Hz500);
+    assert_eq!(debounce::read(&device).unwrap(), Debounce::Ms4);
+}
@@ -0,0 +1,40 @@
+// Helpers shared by the integration tests of the D-Bus services
+// Each test gets its own dbus-daemon, so tests don't see each other's names or the user's
+// session bus. Tests are skipped where dbus-daemon is not installed.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+/// A private session bus, stopped when dropped
+pub struct Bus {
+    daemon: Child,
+    /// Address to connect to, e.g. with `zbus::blocking::connection::Builder::address`
+    pub address: String,
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// Start a dbus-daemon for a private session bus, `None` if it can't be started
+pub fn private_bus() -> Option<Bus> {
+    let mut daemon = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address=1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    let mut address = String::new();
+    BufReader::new(daemon.stdout.take()?)
+        .read_line(&mut address)
+        .ok()?;
+
+    Some(Bus {
+        daemon,
+        address: address.trim().to_string(),
+    })
+}
@@ -0,0 +1,292 @@
+// Virtual MAD R on top of /dev/uhid
+// Creates a HID device with the vendor configuration interface of a MAD R and serves it from
+// a `madr_lib::emulator::Emulator`, so that the real hidraw/hidapi code path can be exercised
+// without a mouse. The kernel removes the device when it is dropped.
+// Needs write access to /dev/uhid, see Documentation/hid/uhid.rst in the kernel tree.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use madr_lib::emulator::Emulator;
+
+const UHID_PATH: &str = "/dev/uhid";
+
+// Event types from linux/uhid.h
+const UHID_DESTROY: u32 = 1;
+const UHID_START: u32 = 2;
+const UHID_OUTPUT: u32 = 6;
+const UHID_GET_REPORT: u32 = 9;
+const UHID_GET_REPORT_REPLY: u32 = 10;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_SET_REPORT: u32 = 13;
+const UHID_SET_REPORT_REPLY: u32 = 14;
+
+/// sizeof(struct uhid_event), the create2 request is the largest member of its union
+const UHID_EVENT_SIZE: usize = 4376;
+const UHID_DATA_MAX: usize = 4096;
+
+const BUS_USB: u16 = 0x03;
+
+/// How long to wait for the hidraw node to show up after creating the device
+const CREATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the worker checks whether it should stop
+const POLL_INTERVAL_MS: i32 = 100;
+
+/// One vendor defined collection with report 0x08 as 16 byte input, output and feature report,
+/// which is what madr-lib looks for when picking the configuration interface
+pub const REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x00, 0xFF, // Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01, // Usage (0x01)
+    0xA1, 0x01, // Collection (Application)
+    0x85, 0x08, //   Report ID (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x10, //   Report Count (16)
+    0x09, 0x01, //   Usage (0x01)
+    0x81, 0x02, //   Input (Data, Var, Abs)
+    0x09, 0x01, //   Usage (0x01)
+    0x91, 0x02, //   Output (Data, Var, Abs)
+    0x09, 0x01, //   Usage (0x01)
+    0xB1, 0x02, //   Feature (Data, Var, Abs)
+    0xC0, // End Collection
+];
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_ne_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+}
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn put_str(buf: &mut [u8], offset: usize, max: usize, value: &str) {
+    let len = value.len().min(max - 1);
+    buf[offset..offset + len].copy_from_slice(&value.as_bytes()[..len]);
+}
+
+fn event(kind: u32) -> Vec<u8> {
+    let mut ev = vec![0u8; UHID_EVENT_SIZE];
+    put_u32(&mut ev, 0, kind);
+    ev
+}
+
+/// struct uhid_create2_req
+fn create2_event(name: &str, serial: &str, vid: u16, pid: u16) -> Vec<u8> {
+    let mut ev = event(UHID_CREATE2);
+    put_str(&mut ev, 4, 128, name);
+    put_str(&mut ev, 132, 64, "madr-uhid");
+    put_str(&mut ev, 196, 64, serial);
+    put_u16(&mut ev, 260, REPORT_DESCRIPTOR.len() as u16);
+    put_u16(&mut ev, 262, BUS_USB);
+    put_u32(&mut ev, 264, vid as u32);
+    put_u32(&mut ev, 268, pid as u32);
+    ev[280..280 + REPORT_DESCRIPTOR.len()].copy_from_slice(REPORT_DESCRIPTOR);
+    ev
+}
+
+/// struct uhid_input2_req
+fn input2_event(data: &[u8]) -> Vec<u8> {
+    let mut ev = event(UHID_INPUT2);
+    let len = data.len().min(UHID_DATA_MAX);
+    put_u16(&mut ev, 4, len as u16);
+    ev[6..6 + len].copy_from_slice(&data[..len]);
+    ev
+}
+
+/// struct uhid_set_report_reply_req
+fn set_report_reply_event(id: u32) -> Vec<u8> {
+    let mut ev = event(UHID_SET_REPORT_REPLY);
+    put_u32(&mut ev, 4, id);
+    ev
+}
+
+/// struct uhid_get_report_reply_req, GET_REPORT is not part of the protocol
+fn get_report_reply_event(id: u32) -> Vec<u8> {
+    let mut ev = event(UHID_GET_REPORT_REPLY);
+    put_u32(&mut ev, 4, id);
+    put_u16(&mut ev, 8, libc::EIO as u16);
+    ev
+}
+
+/// Wait up to `timeout_ms` for an event, `None` on timeout
+fn read_event(file: &mut File, timeout_ms: i32) -> io::Result<Option<Vec<u8>>> {
+    let mut pollfd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    // SAFETY: `pollfd` is a single valid pollfd
+    let ready = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+    if ready < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if ready == 0 {
+        return Ok(None);
+    }
+
+    let mut ev = vec![0u8; UHID_EVENT_SIZE];
+    let size = file.read(&mut ev)?;
+    ev.truncate(size);
+    Ok(Some(ev))
+}
+
+/// Find the hidraw node the kernel created for the device with this serial number
+fn find_hidraw(serial: &str) -> Option<PathBuf> {
+    fs::read_dir("/sys/class/hidraw")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| {
+            fs::read_to_string(entry.path().join("device").join("uevent"))
+                .is_ok_and(|uevent| uevent.lines().any(|l| l == format!("HID_UNIQ={}", serial)))
+        })
+        .map(|entry| Path::new("/dev").join(entry.file_name()))
+}
+
+/// Serve events until `stop` is set, then destroy the device
+fn serve(mut file: File, emulator: Emulator, stop: Arc<AtomicBool>, started: mpsc::Sender<()>) {
+    while !stop.load(Ordering::Relaxed) {
+        let ev = match read_event(&mut file, POLL_INTERVAL_MS) {
+            Ok(Some(ev)) if ev.len() >= 4 => ev,
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("madr-uhid: {}", e);
+                break;
+            }
+        };
+
+        let reply = match get_u32(&ev, 0) {
+            UHID_START => {
+                let _ = started.send(());
+                None
+            }
+            UHID_OUTPUT => {
+                let size = (get_u16(&ev, 4 + UHID_DATA_MAX) as usize).min(UHID_DATA_MAX);
+                emulator.handle(&ev[4..4 + size]);
+                None
+            }
+            UHID_SET_REPORT => {
+                let size = (get_u16(&ev, 10) as usize).min(UHID_DATA_MAX);
+                emulator.handle(&ev[12..12 + size]);
+                Some(set_report_reply_event(get_u32(&ev, 4)))
+            }
+            UHID_GET_REPORT => Some(get_report_reply_event(get_u32(&ev, 4))),
+            _ => None,
+        };
+
+        let mut events: Vec<Vec<u8>> = reply.into_iter().collect();
+        while let Some(input) = emulator.next_input() {
+            events.push(input2_event(&input));
+        }
+
+        for ev in events {
+            if let Err(e) = file.write_all(&ev) {
+                eprintln!("madr-uhid: {}", e);
+            }
+        }
+    }
+
+    let _ = file.write_all(&event(UHID_DESTROY));
+}
+
+/// A virtual MAD R, removed again when dropped
+#[derive(Debug)]
+pub struct VirtualMouse {
+    emulator: Emulator,
+    hidraw: Option<PathBuf>,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl VirtualMouse {
+    /// Create a virtual MAD R with the given product ID, served by a fresh `Emulator`
+    pub fn create(pid: u16) -> io::Result<Self> {
+        Self::with_emulator(Emulator::new(), pid)
+    }
+
+    /// Create a virtual MAD R backed by `emulator`, keep a clone of it to inspect its registers
+    pub fn with_emulator(emulator: Emulator, pid: u16) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(UHID_PATH)?;
+
+        let serial = format!("madr-uhid-{}-{:04x}", std::process::id(), pid);
+        file.write_all(&create2_event(
+            "VXE MAD R (virtual)",
+            &serial,
+            madr_lib::model::VXE_VID,
+            pid,
+        ))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (started, start) = mpsc::channel();
+        let worker = {
+            let emulator = emulator.clone();
+            let stop = stop.clone();
+            thread::spawn(move || serve(file, emulator, stop, started))
+        };
+
+        let mut mouse = Self {
+            emulator,
+            hidraw: None,
+            stop,
+            worker: Some(worker),
+        };
+
+        if start.recv_timeout(CREATE_TIMEOUT).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "kernel did not start the virtual device",
+            ));
+        }
+
+        // The hidraw node is created shortly after the device is started
+        let deadline = Instant::now() + CREATE_TIMEOUT;
+        while mouse.hidraw.is_none() && Instant::now() < deadline {
+            mouse.hidraw = find_hidraw(&serial);
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        Ok(mouse)
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    /// hidraw node of the virtual device, if it could be found
+    pub fn hidraw(&self) -> Option<&Path> {
+        self.hidraw.as_deref()
+    }
+}
+
+impl Drop for VirtualMouse {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
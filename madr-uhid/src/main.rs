@@ -0,0 +1,58 @@
+use std::process::ExitCode;
+use std::sync::mpsc;
+
+use clap::Parser;
+
+use madr_lib::emulator::Emulator;
+use madr_uhid::VirtualMouse;
+
+#[derive(Parser)]
+#[command(name = "madr-uhid")]
+#[command(version, long_about = None)]
+#[command(about = "Expose an emulated VXE MAD R as a real HID device until interrupted")]
+struct Cli {
+    /// Pretend to be connected by cable instead of through the wireless receiver
+    #[arg(long)]
+    wired: bool,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let emulator = Emulator::new();
+    let device = emulator.device(cli.wired);
+    let (model, pid) = (device.model(), device.product_id());
+
+    let mouse = match VirtualMouse::with_emulator(emulator, pid) {
+        Ok(mouse) => mouse,
+        Err(e) => {
+            eprintln!("failed to create virtual device: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match mouse.hidraw() {
+        Some(path) => println!(
+            "{} ({:04x}:{:04x}) at {}",
+            model.name,
+            model.vid,
+            pid,
+            path.display()
+        ),
+        None => println!("{} ({:04x}:{:04x}) created", model.name, model.vid, pid),
+    }
+    println!("press Ctrl-C to remove it");
+
+    let (tx, rx) = mpsc::channel();
+    if let Err(e) = ctrlc::set_handler(move || {
+        let _ = tx.send(());
+    }) {
+        eprintln!("failed to install signal handler: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    let _ = rx.recv();
+    drop(mouse);
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,49 @@
+// A virtual mouse goes through the kernel like a real one: it is enumerated as a MAD R with a
+// configuration interface, opens, and settings written through it land in its emulator.
+// Needs write access to /dev/uhid and no real MAD R attached, so it only runs when asked for:
+//   cargo test -p madr-uhid -- --ignored
+
+use madr_lib::emulator::Emulator;
+use madr_lib::{Device, Performance, PollingRate, device, dpi};
+use madr_uhid::VirtualMouse;
+
+#[test]
+#[ignore = "needs write access to /dev/uhid"]
+fn round_trip_through_the_kernel() {
+    let emulator = Emulator::new();
+    let pid = emulator.device(false).product_id();
+    let mouse = VirtualMouse::with_emulator(emulator.clone(), pid).unwrap();
+    let hidraw = mouse
+        .hidraw()
+        .expect("no hidraw node for the virtual mouse");
+
+    let entry = device::list()
+        .unwrap()
+        .into_iter()
+        .find(|e| e.path == hidraw.to_string_lossy())
+        .expect("virtual mouse was not enumerated");
+    assert_eq!(entry.pid, pid);
+    assert!(entry.is_config_interface());
+    assert_eq!(entry.model.unwrap().name, "VXE MAD R");
+
+    let device = Device::open().unwrap();
+    assert_eq!(device.product_id(), pid);
+    assert!(!device.is_wired());
+
+    let settings = Performance::new(4, PollingRate::Hz500);
+    madr_lib::performance::apply_setting(&device, &settings).unwrap();
+    assert_eq!(Performance::read(&device).unwrap(), settings);
+
+    dpi::apply_dpi_setting(&device, 2, Some(1200), None, Some("1,2,3")).unwrap();
+    assert_eq!(dpi::read_stage(&device, 2).unwrap().x_dpi(), 1200);
+    assert_eq!(
+        dpi::read_stage_color(&device, 2).unwrap().to_string(),
+        "1,2,3"
+    );
+
+    // The writes went through the kernel to the emulator behind the virtual mouse
+    assert_eq!(
+        Performance::read(&emulator.device(false)).unwrap(),
+        settings
+    );
+}
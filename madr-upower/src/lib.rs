@@ -0,0 +1,437 @@
+// The mouse battery on D-Bus in the shape of UPower, so battery indicators and scripts written
+// for UPower devices can show it. The kernel has no power supply for the mouse, so UPower itself
+// never lists it; this service offers the same interfaces under its own name instead:
+//   /org/freedesktop/UPower                         org.freedesktop.UPower, EnumerateDevices()
+//   /org/freedesktop/UPower/devices/mouse_madr_<id> org.freedesktop.UPower.Device
+// The battery is read again on Refresh() and whenever `Service::refresh` is called, and
+// PropertiesChanged is emitted for whatever changed. While the wireless mouse is asleep the
+// device stays listed with IsPresent false, like UPower does for a mouse that is switched off.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use zbus::blocking::connection::{Builder, Connection};
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedObjectPath, Value};
+use zbus::{fdo, interface};
+
+use madr_lib::{Battery, Device, Result};
+
+pub const BUS_NAME: &str = "org.madr.UPower";
+pub const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+pub const DEVICE_INTERFACE: &str = "org.freedesktop.UPower.Device";
+
+/// The UPower version whose interfaces are implemented
+const DAEMON_VERSION: &str = "1.90.0";
+
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+// Values from the UPower D-Bus API
+const TYPE_MOUSE: u32 = 5;
+const STATE_UNKNOWN: u32 = 0;
+const STATE_CHARGING: u32 = 1;
+const STATE_DISCHARGING: u32 = 2;
+const STATE_FULLY_CHARGED: u32 = 4;
+const TECHNOLOGY_UNKNOWN: u32 = 0;
+const WARNING_LEVEL_NONE: u32 = 1;
+const WARNING_LEVEL_LOW: u32 = 3;
+const WARNING_LEVEL_CRITICAL: u32 = 4;
+/// The battery reports a percentage rather than coarse levels
+const BATTERY_LEVEL_NONE: u32 = 1;
+
+// UPower's default PercentageLow and PercentageCritical
+const PERCENTAGE_LOW: u8 = 20;
+const PERCENTAGE_CRITICAL: u8 = 5;
+
+/// Device properties by name
+type Properties = HashMap<&'static str, Value<'static>>;
+
+/// Path of the UPower device for `device`
+pub fn device_path(device: &Device) -> OwnedObjectPath {
+    let path = format!(
+        "{UPOWER_PATH}/devices/mouse_madr_{:04x}_{:04x}",
+        device.model().vid,
+        device.product_id()
+    );
+    OwnedObjectPath::try_from(path).expect("object paths are built from [a-z0-9_/]")
+}
+
+/// What the last read found, `battery` is `None` if the mouse didn't answer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Status {
+    battery: Option<Battery>,
+    /// Seconds since the Unix epoch of the last successful read
+    update_time: u64,
+}
+
+impl Status {
+    /// Status after reading `battery`, which keeps the time of the last successful read
+    fn after(battery: Result<Battery>, previous: &Status) -> Self {
+        match battery {
+            Ok(battery) => Self {
+                battery: Some(battery),
+                update_time: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+            },
+            Err(_) => Self {
+                battery: None,
+                update_time: previous.update_time,
+            },
+        }
+    }
+
+    fn percentage(&self) -> f64 {
+        self.battery.as_ref().map_or(0.0, |b| b.percentage() as f64)
+    }
+
+    fn voltage(&self) -> f64 {
+        self.battery
+            .as_ref()
+            .map_or(0.0, |b| b.voltage() as f64 / 1000.0)
+    }
+
+    fn state(&self) -> u32 {
+        match &self.battery {
+            None => STATE_UNKNOWN,
+            Some(b) if b.is_charging() && b.percentage() >= 100 => STATE_FULLY_CHARGED,
+            Some(b) if b.is_charging() => STATE_CHARGING,
+            Some(_) => STATE_DISCHARGING,
+        }
+    }
+
+    fn warning_level(&self) -> u32 {
+        match &self.battery {
+            Some(b) if !b.is_charging() && b.percentage() <= PERCENTAGE_CRITICAL => {
+                WARNING_LEVEL_CRITICAL
+            }
+            Some(b) if !b.is_charging() && b.percentage() <= PERCENTAGE_LOW => WARNING_LEVEL_LOW,
+            _ => WARNING_LEVEL_NONE,
+        }
+    }
+
+    /// Picked like UPower picks icons for batteries
+    fn icon_name(&self) -> String {
+        let Some(battery) = &self.battery else {
+            return "battery-missing-symbolic".into();
+        };
+
+        if self.state() == STATE_FULLY_CHARGED {
+            return "battery-full-charged-symbolic".into();
+        }
+
+        let level = match battery.percentage() {
+            0..10 => "caution",
+            10..30 => "low",
+            30..60 => "good",
+            _ => "full",
+        };
+        let charging = if battery.is_charging() {
+            "-charging"
+        } else {
+            ""
+        };
+
+        format!("battery-{}{}-symbolic", level, charging)
+    }
+
+    /// The properties that can change between reads
+    fn properties(&self) -> Properties {
+        HashMap::from([
+            ("UpdateTime", Value::from(self.update_time)),
+            ("IsPresent", Value::from(self.battery.is_some())),
+            ("Percentage", Value::from(self.percentage())),
+            ("Voltage", Value::from(self.voltage())),
+            ("State", Value::from(self.state())),
+            ("WarningLevel", Value::from(self.warning_level())),
+            ("IconName", Value::from(self.icon_name())),
+        ])
+    }
+}
+
+struct Shared {
+    device: Device,
+    status: Mutex<Status>,
+}
+
+impl Shared {
+    fn status(&self) -> MutexGuard<'_, Status> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Read the battery again, returning the properties that changed
+    fn refresh(&self) -> Properties {
+        // Read before locking, properties are served from the last read meanwhile
+        let battery = Battery::read(&self.device);
+
+        let mut status = self.status();
+        let previous = status.properties();
+        *status = Status::after(battery, &status);
+
+        let mut changed = status.properties();
+        changed.retain(|name, value| previous.get(name) != Some(value));
+        changed
+    }
+}
+
+/// PropertiesChanged for the device interface
+fn properties_changed(changed: Properties) -> (&'static str, Properties, Vec<&'static str>) {
+    (DEVICE_INTERFACE, changed, Vec::new())
+}
+
+struct UPower {
+    devices: Vec<OwnedObjectPath>,
+}
+
+#[interface(name = "org.freedesktop.UPower")]
+impl UPower {
+    fn enumerate_devices(&self) -> Vec<OwnedObjectPath> {
+        self.devices.clone()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn daemon_version(&self) -> String {
+        DAEMON_VERSION.into()
+    }
+
+    /// About the computer, which the mouse battery has nothing to do with
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn on_battery(&self) -> bool {
+        false
+    }
+}
+
+struct UPowerDevice {
+    shared: Arc<Shared>,
+}
+
+#[interface(name = "org.freedesktop.UPower.Device")]
+impl UPowerDevice {
+    async fn refresh(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<()> {
+        // Reading the battery takes a round trip to the mouse, which must not block the
+        // executor serving the bus
+        let shared = self.shared.clone();
+        let changed = blocking::unblock(move || shared.refresh()).await;
+        if !changed.is_empty() {
+            emitter
+                .emit(
+                    PROPERTIES_INTERFACE,
+                    "PropertiesChanged",
+                    &properties_changed(changed),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn native_path(&self) -> String {
+        let device = &self.shared.device;
+        format!(
+            "madr_{:04x}_{:04x}",
+            device.model().vid,
+            device.product_id()
+        )
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn vendor(&self) -> String {
+        "VXE".into()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn model(&self) -> String {
+        self.shared.device.model().name.clone()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn serial(&self) -> String {
+        String::new()
+    }
+
+    #[zbus(property)]
+    fn update_time(&self) -> u64 {
+        self.shared.status().update_time
+    }
+
+    #[zbus(property(emits_changed_signal = "const"), name = "Type")]
+    fn kind(&self) -> u32 {
+        TYPE_MOUSE
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn power_supply(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn has_history(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn has_statistics(&self) -> bool {
+        false
+    }
+
+    /// Only for line power devices
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn online(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn is_present(&self) -> bool {
+        self.shared.status().battery.is_some()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn is_rechargeable(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn percentage(&self) -> f64 {
+        self.shared.status().percentage()
+    }
+
+    /// In volts
+    #[zbus(property)]
+    fn voltage(&self) -> f64 {
+        self.shared.status().voltage()
+    }
+
+    #[zbus(property)]
+    fn state(&self) -> u32 {
+        self.shared.status().state()
+    }
+
+    #[zbus(property)]
+    fn warning_level(&self) -> u32 {
+        self.shared.status().warning_level()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn battery_level(&self) -> u32 {
+        BATTERY_LEVEL_NONE
+    }
+
+    #[zbus(property)]
+    fn icon_name(&self) -> String {
+        self.shared.status().icon_name()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn technology(&self) -> u32 {
+        TECHNOLOGY_UNKNOWN
+    }
+
+    // The mouse reports neither energy nor time estimates, UPower uses 0 for unknown
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn energy(&self) -> f64 {
+        0.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn energy_empty(&self) -> f64 {
+        0.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn energy_full(&self) -> f64 {
+        0.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn energy_full_design(&self) -> f64 {
+        0.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn energy_rate(&self) -> f64 {
+        0.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn time_to_empty(&self) -> i64 {
+        0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn time_to_full(&self) -> i64 {
+        0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn capacity(&self) -> f64 {
+        100.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn charge_cycles(&self) -> i32 {
+        -1
+    }
+}
+
+/// A running service, for as long as it is kept
+pub struct Service {
+    connection: Connection,
+    path: OwnedObjectPath,
+    shared: Arc<Shared>,
+}
+
+impl Service {
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Read the battery again and tell clients what changed
+    pub fn refresh(&self) -> zbus::Result<()> {
+        let changed = self.shared.refresh();
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        self.connection.emit_signal(
+            None::<&str>,
+            &self.path,
+            PROPERTIES_INTERFACE,
+            "PropertiesChanged",
+            &properties_changed(changed),
+        )
+    }
+}
+
+/// Serve the battery of `device` on the bus `builder` connects to, under `BUS_NAME`
+pub fn serve(builder: Builder<'static>, device: Device) -> zbus::Result<Service> {
+    let path = device_path(&device);
+    let status = Status::after(Battery::read(&device), &Status::default());
+    let shared = Arc::new(Shared {
+        device,
+        status: Mutex::new(status),
+    });
+
+    let connection = builder
+        .name(BUS_NAME)?
+        .serve_at(
+            UPOWER_PATH,
+            UPower {
+                devices: vec![path.clone()],
+            },
+        )?
+        .serve_at(
+            path.clone(),
+            UPowerDevice {
+                shared: shared.clone(),
+            },
+        )?
+        .build()?;
+
+    Ok(Service {
+        connection,
+        path,
+        shared,
+    })
+}
@@ -0,0 +1,53 @@
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+use zbus::blocking::connection::Builder;
+
+use madr_lib::{Device, device};
+
+#[derive(Parser)]
+#[command(name = "madr-upower")]
+#[command(version, long_about = None)]
+#[command(about = "Publish the battery of a VXE MAD R series mouse as a UPower device on D-Bus")]
+struct Cli {
+    /// Use the session bus instead of the system bus, which needs no D-Bus policy
+    #[arg(long)]
+    session: bool,
+
+    /// How often the battery is read, in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    interval: u64,
+
+    /// Open this device even if no known model matches it
+    #[arg(long, value_name = "VID:PID", value_parser = device::parse_device_id)]
+    force_device: Option<(u16, u16)>,
+
+    /// Treat the device given with --force-device as connected by cable
+    #[arg(long, requires = "force_device")]
+    wired: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let device = match cli.force_device {
+        Some((vid, pid)) => Device::open_forced(vid, pid, cli.wired)?,
+        None => Device::open()?,
+    };
+
+    let builder = if cli.session {
+        Builder::session()?
+    } else {
+        Builder::system()?
+    };
+
+    let path = madr_upower::device_path(&device);
+    let service = madr_upower::serve(builder, device)?;
+    println!("Serving {} as {}", path.as_str(), madr_upower::BUS_NAME);
+
+    loop {
+        thread::sleep(Duration::from_secs(cli.interval));
+        service.refresh()?;
+    }
+}
@@ -0,0 +1,89 @@
+// The battery of an emulated MAD R as a UPower device on a private dbus-daemon session bus,
+// read the way UPower clients read it. Skipped if dbus-daemon is not installed.
+
+use zbus::blocking::Proxy;
+use zbus::blocking::connection::Builder;
+use zbus::blocking::proxy::Builder as ProxyBuilder;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::OwnedObjectPath;
+
+use madr_lib::emulator::Emulator;
+use madr_test_support::private_bus;
+use madr_upower::{BUS_NAME, DEVICE_INTERFACE, UPOWER_PATH};
+
+#[test]
+fn battery_as_upower_device() {
+    let Some(bus) = private_bus() else {
+        eprintln!("dbus-daemon not found, skipping");
+        return;
+    };
+
+    let emulator = Emulator::new();
+    emulator.set_battery(42, false, 3850);
+    let device = emulator.device(false);
+
+    let builder = Builder::address(bus.address.as_str()).unwrap();
+    let service = madr_upower::serve(builder, device).unwrap();
+    let client = Builder::address(bus.address.as_str())
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let upower = Proxy::new(&client, BUS_NAME, UPOWER_PATH, "org.freedesktop.UPower").unwrap();
+    let devices: Vec<OwnedObjectPath> = upower.call("EnumerateDevices", &()).unwrap();
+    assert_eq!(devices.len(), 1);
+    assert!(devices[0].as_str().contains("/devices/mouse_"));
+
+    // Read every property from the service, not from what PropertiesChanged said
+    let battery: Proxy = ProxyBuilder::new(&client)
+        .destination(BUS_NAME)
+        .unwrap()
+        .path(devices[0].as_str())
+        .unwrap()
+        .interface(DEVICE_INTERFACE)
+        .unwrap()
+        .cache_properties(CacheProperties::No)
+        .build()
+        .unwrap();
+    assert_eq!(battery.get_property::<u32>("Type").unwrap(), 5);
+    assert_eq!(
+        battery.get_property::<String>("Model").unwrap(),
+        "VXE MAD R"
+    );
+    assert!(battery.get_property::<bool>("IsPresent").unwrap());
+    assert_eq!(battery.get_property::<f64>("Percentage").unwrap(), 42.0);
+    assert_eq!(battery.get_property::<f64>("Voltage").unwrap(), 3.85);
+    assert_eq!(battery.get_property::<u32>("State").unwrap(), 2);
+    assert_eq!(battery.get_property::<u32>("WarningLevel").unwrap(), 1);
+    assert_eq!(
+        battery.get_property::<String>("IconName").unwrap(),
+        "battery-good-symbolic"
+    );
+    assert!(battery.get_property::<u64>("UpdateTime").unwrap() > 0);
+
+    // Refresh() reads the battery again
+    emulator.set_battery(100, true, 4200);
+    battery.call_method("Refresh", &()).unwrap();
+    assert_eq!(battery.get_property::<f64>("Percentage").unwrap(), 100.0);
+    assert_eq!(battery.get_property::<u32>("State").unwrap(), 4);
+    assert_eq!(
+        battery.get_property::<String>("IconName").unwrap(),
+        "battery-full-charged-symbolic"
+    );
+
+    // So does the service's own poll, which tells clients what changed
+    let cached = Proxy::new(&client, BUS_NAME, devices[0].as_str(), DEVICE_INTERFACE).unwrap();
+    let mut levels = cached.receive_property_changed::<u32>("WarningLevel");
+    emulator.set_battery(4, false, 3500);
+    service.refresh().unwrap();
+
+    let level = levels
+        .find_map(|change| change.get().ok().filter(|&level| level != 1))
+        .unwrap();
+    assert_eq!(level, 4);
+    assert_eq!(battery.get_property::<f64>("Percentage").unwrap(), 4.0);
+    assert_eq!(
+        battery.get_property::<String>("IconName").unwrap(),
+        "battery-caution-symbolic"
+    );
+}
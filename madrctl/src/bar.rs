@@ -0,0 +1,268 @@
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::json;
+
+use madr_lib::{
+    backend::Backend,
+    battery::Battery,
+    dpi::{DpiStage, Rgb},
+    model::Feature,
+    performance::PollingRate,
+    MadRError,
+};
+
+/// Text shown when no --template is given
+pub const DEFAULT_TEMPLATE: &str = "{percentage}% {dpi} DPI";
+
+// Same thresholds and colors as `info battery`
+const LOW_COLOR: &str = "#ff0000";
+const MEDIUM_COLOR: &str = "#ffff00";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// JSON for a custom module with "return-type": "json"
+    Waybar,
+    /// JSON for a block with format=json and interval=persist
+    I3blocks,
+    /// Text with color tags for a custom/script module with tail = true
+    Polybar,
+    /// Just the text, one line per update
+    Template,
+}
+
+/// What the mouse said on the last poll
+enum Status {
+    Connected(Reading),
+    /// The receiver is there, but the mouse doesn't answer
+    Asleep {
+        model: String,
+    },
+    /// No mouse or receiver found, shown as empty text so bars hide the module
+    Disconnected,
+}
+
+struct Reading {
+    model: String,
+    battery: Battery,
+    stage: u8,
+    dpi: DpiStage,
+    /// `None` if the model has no DPI colors
+    color: Option<Rgb>,
+    polling_rate: PollingRate,
+}
+
+impl Reading {
+    fn read(device: &Backend) -> madr_lib::Result<Self> {
+        // Checked here, madrd would report it as one of its own errors, which mean asleep
+        device.capabilities().require(Feature::Battery)?;
+
+        let battery = device.battery()?;
+        let performance = device.performance()?;
+        let stage = performance.dpi_stage();
+
+        let color = if device.capabilities().supports(Feature::DpiColors) {
+            Some(device.read_stage_color(stage)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            model: device.model().name.clone(),
+            battery,
+            stage,
+            dpi: device.read_stage(stage)?,
+            color,
+            polling_rate: performance.polling_rate(),
+        })
+    }
+
+    /// CSS class for waybar, like the colors of `info battery`
+    fn level(&self) -> &'static str {
+        match self.battery.percentage() {
+            0..=20 => "low",
+            21..=50 => "medium",
+            _ => "high",
+        }
+    }
+
+    fn color(&self) -> Option<&'static str> {
+        match self.battery.percentage() {
+            0..=20 => Some(LOW_COLOR),
+            21..=50 => Some(MEDIUM_COLOR),
+            _ => None,
+        }
+    }
+
+    fn fill(&self, template: &str) -> String {
+        let placeholders = [
+            ("{model}", self.model.clone()),
+            ("{percentage}", self.battery.percentage().to_string()),
+            (
+                "{voltage}",
+                format!("{:.2}", self.battery.voltage() as f32 / 1000.0),
+            ),
+            (
+                "{charging}",
+                if self.battery.is_charging() {
+                    "charging".into()
+                } else {
+                    String::new()
+                },
+            ),
+            ("{stage}", self.stage.to_string()),
+            ("{dpi}", self.dpi.to_string()),
+            ("{color}", self.color.map_or(String::new(), |c| c.hex())),
+            ("{polling_rate}", (self.polling_rate as u16).to_string()),
+        ];
+
+        placeholders
+            .iter()
+            .fold(template.to_string(), |text, (key, value)| {
+                text.replace(key, value)
+            })
+    }
+
+    fn tooltip(&self) -> String {
+        format!(
+            "{}\nBattery: {}% ({:.2}V){}\nDPI stage {}: {}\nPolling rate: {}",
+            self.model,
+            self.battery.percentage(),
+            self.battery.voltage() as f32 / 1000.0,
+            if self.battery.is_charging() {
+                ", charging"
+            } else {
+                ""
+            },
+            self.stage,
+            self.dpi,
+            self.polling_rate
+        )
+    }
+}
+
+/// One line of output for the bar
+fn render(status: &Status, format: Format, template: &str) -> String {
+    let text = match status {
+        Status::Connected(reading) => reading.fill(template),
+        Status::Asleep { .. } => "asleep".into(),
+        Status::Disconnected => String::new(),
+    };
+
+    match format {
+        Format::Template => text,
+        Format::Polybar => match status {
+            Status::Connected(reading) => match reading.color() {
+                Some(color) => format!("%{{F{}}}{}%{{F-}}", color, text),
+                None => text,
+            },
+            _ => text,
+        },
+        Format::I3blocks => {
+            let (short, color) = match status {
+                Status::Connected(reading) => (
+                    format!("{}%", reading.battery.percentage()),
+                    reading.color(),
+                ),
+                _ => (text.clone(), None),
+            };
+
+            let mut block = json!({ "full_text": text, "short_text": short });
+            if let Some(color) = color {
+                block["color"] = json!(color);
+            }
+            block.to_string()
+        }
+        Format::Waybar => {
+            let (tooltip, class, percentage) = match status {
+                Status::Connected(reading) => {
+                    let mut class = vec![reading.level()];
+                    if reading.battery.is_charging() {
+                        class.push("charging");
+                    }
+                    (reading.tooltip(), class, reading.battery.percentage())
+                }
+                Status::Asleep { model } => (format!("{} is asleep", model), vec!["asleep"], 0),
+                Status::Disconnected => (String::new(), vec!["disconnected"], 0),
+            };
+
+            json!({
+                "text": text,
+                "tooltip": tooltip,
+                "class": class,
+                "percentage": percentage,
+            })
+            .to_string()
+        }
+    }
+}
+
+/// Errors of a mouse that doesn't answer, or of a receiver that went away with it. madrd
+/// reports them as its own errors.
+fn is_asleep(error: &MadRError) -> bool {
+    match error {
+        MadRError::NoResponse(_) | MadRError::Io(_) | MadRError::Daemon(_) => true,
+        #[cfg(feature = "hidapi")]
+        MadRError::HidApiInit(_) => true,
+        _ => false,
+    }
+}
+
+/// Print the battery and DPI for a status bar every `interval`, only when it changed.
+/// `open` connects to the mouse, again after it stopped answering.
+pub fn run(
+    open: impl Fn() -> Result<Backend>,
+    format: Format,
+    template: &str,
+    interval: Duration,
+    once: bool,
+) -> Result<()> {
+    let mut device: Option<Backend> = None;
+    let mut last = None;
+
+    loop {
+        if device.is_none() {
+            device = match open() {
+                Ok(device) => Some(device),
+                // madrd reports a missing mouse as its own error
+                Err(e)
+                    if matches!(
+                        e.downcast_ref(),
+                        Some(MadRError::DeviceNotFound | MadRError::Daemon(_))
+                    ) =>
+                {
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+        }
+
+        let status = match &device {
+            Some(d) => match Reading::read(d) {
+                Ok(reading) => Status::Connected(reading),
+                Err(e) if is_asleep(&e) => {
+                    let model = d.model().name.clone();
+                    // Opened again next time, in case the receiver went away too
+                    device = None;
+                    Status::Asleep { model }
+                }
+                Err(e) => return Err(e.into()),
+            },
+            None => Status::Disconnected,
+        };
+
+        let line = render(&status, format, template);
+        if last.as_ref() != Some(&line) {
+            println!("{}", line);
+            last = Some(line);
+        }
+
+        if once {
+            return Ok(());
+        }
+
+        thread::sleep(interval);
+    }
+}
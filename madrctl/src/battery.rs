@@ -0,0 +1,190 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use colored::Colorize;
+
+use madr_lib::{
+    backend::Backend,
+    model::Feature,
+    telemetry::{self, Report, Sample},
+};
+
+/// Charge sessions listed in the report, most recent last
+const RECENT_SESSIONS: usize = 10;
+
+/// Append a sample to `file` every `interval`, `count` times or until interrupted.
+/// Failed reads, e.g. while the mouse is asleep, are reported and skipped.
+pub fn log(device: &Backend, file: &Path, interval: Duration, count: Option<u64>) -> Result<()> {
+    println!(
+        "Logging battery to {} every {} s, press Ctrl-C to stop",
+        file.display(),
+        interval.as_secs()
+    );
+
+    let mut taken = 0;
+    loop {
+        match sample(device) {
+            Ok(sample) => {
+                telemetry::append(file, &sample)?;
+                println!("{}", describe(&sample));
+            }
+            Err(e) => eprintln!("{}: {}", "warning".yellow(), e),
+        }
+
+        taken += 1;
+        if count.is_some_and(|count| taken >= count) {
+            return Ok(());
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+fn sample(device: &Backend) -> madr_lib::Result<Sample> {
+    let battery = device.battery()?;
+    let polling_rate = device.performance().ok().map(|p| p.polling_rate());
+    let mode = if device.capabilities().supports(Feature::SensorMode) {
+        device.sensor().ok().map(|s| s.mode())
+    } else {
+        None
+    };
+
+    Ok(Sample::new(&battery, polling_rate, mode))
+}
+
+fn describe(sample: &Sample) -> String {
+    let mut line = format!(
+        "{} {}% | {:.2}V | {}",
+        format_time(sample.timestamp),
+        sample.percentage,
+        sample.voltage_mv as f32 / 1000.0,
+        if sample.charging {
+            "Charging"
+        } else {
+            "Not Charging"
+        }
+    );
+
+    if let Some(rate) = sample.polling_rate {
+        line.push_str(&format!(" | {}", rate));
+    }
+    if let Some(mode) = sample.sensor_mode {
+        line.push_str(&format!(" | {}", mode));
+    }
+
+    line
+}
+
+/// Print what the log in `file` says about the battery
+pub fn report(file: &Path) -> Result<()> {
+    let samples = telemetry::load(file)?;
+    let Some(report) = Report::new(&samples) else {
+        println!("No samples in {}", file.display());
+        return Ok(());
+    };
+
+    println!(
+        "{} samples from {} to {} UTC",
+        report.samples,
+        format_time(report.first),
+        format_time(report.latest.timestamp)
+    );
+    println!("Latest: {}", describe(&report.latest));
+
+    match (report.time_to_empty, report.time_to_full) {
+        (Some(left), _) => println!("About {} to empty", format_duration(left).bold()),
+        (_, Some(left)) => println!("About {} to full", format_duration(left).bold()),
+        _ => {}
+    }
+
+    println!(
+        "Discharge: {}, charge: {}",
+        format_rate(report.discharge.per_hour()),
+        format_rate(report.charge.per_hour())
+    );
+
+    println!(
+        "Charge cycles: {:.1}, charged {} time(s)",
+        report.cycles,
+        report.sessions.len()
+    );
+    let skipped = report.sessions.len().saturating_sub(RECENT_SESSIONS);
+    for session in &report.sessions[skipped..] {
+        println!(
+            "  {}  {}% -> {}% in {}",
+            format_time(session.start),
+            session.from,
+            session.to,
+            format_duration(Duration::from_secs(session.end - session.start))
+        );
+    }
+
+    if report.usage.is_empty() {
+        return Ok(());
+    }
+
+    println!("Battery life by settings:");
+    println!(
+        "  {:<14} {:<13} {:>16} {:>18} {:>12}",
+        "polling rate", "sensor", "drain", "full charge lasts", "logged"
+    );
+    for usage in &report.usage {
+        println!(
+            "  {:<14} {:<13} {:>16} {:>18} {:>12}",
+            usage
+                .polling_rate
+                .map_or("unknown".into(), |r| r.to_string()),
+            usage
+                .sensor_mode
+                .map_or("unknown".into(), |m| m.to_string()),
+            format_rate(usage.discharge.per_hour()),
+            usage
+                .discharge
+                .full_charge()
+                .map_or("-".into(), format_duration),
+            format_duration(Duration::from_secs_f64(usage.discharge.hours * 3600.0)),
+        );
+    }
+
+    Ok(())
+}
+
+fn format_rate(rate: Option<f64>) -> String {
+    rate.map_or("not enough data".into(), |r| format!("{:.1} %/h", r))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    match minutes / 60 {
+        0 => format!("{} min", minutes),
+        hours => format!("{} h {} min", hours, minutes % 60),
+    }
+}
+
+/// "YYYY-MM-DD HH:MM" in UTC
+fn format_time(timestamp: u64) -> String {
+    // Days since the epoch to a civil date, from
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let seconds = timestamp % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
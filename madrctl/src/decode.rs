@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+
+use madr_lib::{decode, register};
+
+/// Parse hex bytes, separated by whitespace, colons or nothing at all
+fn parse_hex(input: &[String]) -> Result<Vec<u8>> {
+    let digits: String = input
+        .join(" ")
+        .replace("0x", "")
+        .replace("0X", "")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != ',')
+        .collect();
+
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(anyhow!("invalid hex digit: {}", c));
+    }
+
+    if !digits.len().is_multiple_of(2) {
+        return Err(anyhow!("odd number of hex digits"));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|e| anyhow!(e)))
+        .collect()
+}
+
+pub fn run(input: &[String]) -> Result<()> {
+    let report = parse_hex(input)?;
+
+    if report.len() != register::REPORT_LEN {
+        return Err(anyhow!(
+            "expected {} bytes, got {}",
+            register::REPORT_LEN,
+            report.len()
+        ));
+    }
+
+    match decode::decode(&report) {
+        Ok(message) if message.is_known() => println!("{}\n", message.to_string().bold()),
+        Ok(message) => println!("{} {}\n", message, "[investigate]".yellow()),
+        Err(e) => println!("{}\n", e.to_string().yellow()),
+    }
+
+    for field in decode::dissect(&report)? {
+        let range = if field.len == 1 {
+            format!("{}", field.offset)
+        } else {
+            format!("{}-{}", field.offset, field.offset + field.len - 1)
+        };
+
+        let bytes = report[field.offset..field.offset + field.len]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let status = match field.valid {
+            Some(true) => " ok".green(),
+            Some(false) => " invalid".red(),
+            None => "".normal(),
+        };
+
+        println!(
+            "{:>5}  {:<8}  {:<14}  {}{}",
+            range, bytes, field.name, field.meaning, status
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+use madr_lib::{
+    backend::Backend,
+    device,
+    metrics::{self, Metrics, Scrape, Target},
+    model::Feature,
+};
+
+use crate::http::{self, Request};
+
+struct Exporter<F> {
+    open: F,
+    /// Opened on the first scrape, and again after the mouse stopped answering
+    device: Option<(Backend, Target)>,
+    metrics: Metrics,
+}
+
+impl<F: Fn() -> Result<Backend>> Exporter<F> {
+    /// Read everything the metrics are made of, fresh for every scrape
+    fn scrape(&mut self) -> Scrape {
+        if self.device.is_none() {
+            self.device = self.metrics.record("open", (self.open)()).map(|device| {
+                let target = target(&device);
+                (device, target)
+            });
+        }
+
+        let Some((device, target)) = &self.device else {
+            return Scrape::default();
+        };
+        let capabilities = device.capabilities();
+
+        let battery = if capabilities.supports(Feature::Battery) {
+            self.metrics.record("battery", device.battery())
+        } else {
+            None
+        };
+        let performance = self.metrics.record("performance", device.performance());
+        let dpi =
+            performance.and_then(|p| self.metrics.record("dpi", device.read_stage(p.dpi_stage())));
+        let sensor = if capabilities.supports(Feature::SensorMode) {
+            self.metrics.record("sensor", device.sensor())
+        } else {
+            None
+        };
+
+        let scrape = Scrape {
+            target: Some(target.clone()),
+            battery,
+            performance,
+            dpi,
+            sensor,
+        };
+
+        if scrape.performance.is_none() {
+            self.device = None;
+        }
+
+        scrape
+    }
+
+    fn respond(&mut self, request: &Request) -> (&'static str, &'static str, String) {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET" | "HEAD", "/metrics") => {
+                let scrape = self.scrape();
+                (
+                    "200 OK",
+                    metrics::CONTENT_TYPE,
+                    self.metrics.render(&scrape),
+                )
+            }
+            ("GET" | "HEAD", "/") => (
+                "200 OK",
+                "text/plain; charset=utf-8",
+                "madrctl exporter, metrics are at /metrics\n".into(),
+            ),
+            ("GET" | "HEAD", _) => ("404 Not Found", "text/plain", "Not found\n".into()),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain",
+                "Method not allowed\n".into(),
+            ),
+        }
+    }
+}
+
+/// Answer the request on `stream`, scrapes take turns on the mouse
+fn serve<F: Fn() -> Result<Backend>>(
+    exporter: &Mutex<Exporter<F>>,
+    stream: TcpStream,
+) -> Result<()> {
+    let Some(request) = Request::read(&stream)? else {
+        return Ok(());
+    };
+
+    let (status, content_type, body) = exporter
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .respond(&request);
+
+    http::respond(stream, &request, status, content_type, body.as_bytes())
+}
+
+/// Labels for the device, with the serial number of its configuration interface if it has one
+fn target(device: &Backend) -> Target {
+    let vid = device.model().vid;
+    let pid = device.product_id();
+
+    let serial = device::list().ok().and_then(|entries| {
+        entries
+            .into_iter()
+            .find(|e| e.vid == vid && e.pid == pid && e.is_config_interface())
+            .and_then(|e| e.serial)
+            .filter(|s| !s.is_empty())
+    });
+
+    Target {
+        model: device.model().name.clone(),
+        vid,
+        pid,
+        serial,
+        wired: device.is_wired(),
+    }
+}
+
+/// Serve Prometheus metrics on `listen` until interrupted.
+/// `open` connects to the mouse, again after it stopped answering.
+pub fn run(open: impl Fn() -> Result<Backend> + Send, listen: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(listen).with_context(|| format!("binding {}", listen))?;
+    println!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    let exporter = Mutex::new(Exporter {
+        open,
+        device: None,
+        metrics: Metrics::new(),
+    });
+
+    // A thread per connection, so a client that is slow to send its request holds up nothing
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let exporter = &exporter;
+            scope.spawn(move || {
+                let result = stream
+                    .map_err(anyhow::Error::from)
+                    .and_then(|stream| serve(exporter, stream));
+
+                if let Err(e) = result {
+                    eprintln!("{}: {}", "warning".yellow(), e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+/// How long a client gets to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request line and headers accepted, together
+const MAX_HEAD: u64 = 8 * 1024;
+
+/// Largest request body accepted
+const MAX_BODY: usize = 64 * 1024;
+
+/// A request, with everything after `?` left out of the path
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Read the request on `stream`, `None` if the client went away without sending one
+    pub fn read(stream: &TcpStream) -> Result<Option<Self>> {
+        let mut reader = BufReader::new(Deadline {
+            stream,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        });
+        let mut head_left = MAX_HEAD;
+
+        // Browsers open connections ahead of time, some of which are never used
+        let line = match read_line(&mut reader, &mut head_left) {
+            Ok(line) if line.is_empty() => return Ok(None),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            result => result?,
+        };
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default();
+        let path = target.split('?').next().unwrap_or_default().to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let header = read_line(&mut reader, &mut head_left)?;
+            if header.len() <= 2 {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let mut request = Self {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+
+        let len = match request.header("content-length") {
+            Some(len) => len.parse::<usize>()?,
+            None => 0,
+        };
+        if len > MAX_BODY {
+            return Err(anyhow!("request body of {} bytes is too large", len));
+        }
+        request.body = vec![0; len];
+        reader.read_exact(&mut request.body)?;
+
+        Ok(Some(request))
+    }
+
+    /// Value of the header `name`, given in lowercase
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads from the stream until the deadline, however slowly the client sends
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "client took too long to send its request",
+            ));
+        }
+
+        self.stream.set_read_timeout(Some(left))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// Read a line of the request line and headers, of which `left` bytes may still come
+fn read_line(reader: &mut impl BufRead, left: &mut u64) -> io::Result<String> {
+    let mut line = String::new();
+    let len = reader.take(*left).read_line(&mut line)?;
+    *left -= len as u64;
+
+    if *left == 0 && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("request header is larger than {} bytes", MAX_HEAD),
+        ));
+    }
+
+    Ok(line)
+}
+
+/// Send a response and close the connection. The body is left out for HEAD requests.
+pub fn respond(
+    mut stream: TcpStream,
+    request: &Request,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    if request.method != "HEAD" {
+        stream.write_all(body)?;
+    }
+
+    Ok(())
+}
@@ -1,26 +1,79 @@
+mod bar;
+mod battery;
+mod decode;
+mod exporter;
+mod http;
+mod pcap;
+mod probe;
+mod serve;
+#[cfg(all(feature = "hotplug", target_os = "linux"))]
+mod watch;
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::anyhow;
 use anyhow::Result;
 use colored::Colorize;
 
-use clap::{builder::PossibleValuesParser, value_parser, Parser, Subcommand};
+use clap::{builder::PossibleValuesParser, Parser, Subcommand};
 
 use madr_lib::{
-    battery::Battery,
-    debounce::{self, Debounce},
-    device::Device,
-    dpi,
-    performance::{self, Performance, PollingRate},
-    sensor::{self, Mode, Sensor},
-    sleep,
+    backend::Backend,
+    debounce::Debounce,
+    device::{self, Device},
+    model,
+    performance::{Performance, PollingRate},
+    retry::RetryPolicy,
+    sensor::Mode,
+    telemetry,
 };
 
+/// Defaults of the global retry and locking options
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_TIMEOUT_MS: u64 = 20;
+const DEFAULT_LOCK_TIMEOUT_MS: u64 = 2000;
+
 #[derive(Parser)]
 #[command(name = "madrctl")]
 #[command(version, long_about = None)]
 #[command(about = "Control your VXE MAD R series gaming mouse from the command line")]
 struct Cli {
+    /// Record every report exchanged with the device to a JSONL file
+    #[arg(long, global = true, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Use a trace recorded with --trace as a fake device
+    #[arg(long, global = true, value_name = "FILE", conflicts_with = "trace")]
+    replay: Option<PathBuf>,
+
+    /// Open this device even if no known model matches it
+    #[arg(long, global = true, value_name = "VID:PID", value_parser = device::parse_device_id)]
+    force_device: Option<(u16, u16)>,
+
+    /// Treat the device given with --force-device as connected by cable
+    #[arg(long, global = true, requires = "force_device")]
+    wired: bool,
+
+    /// How many times to retry a failed request, 2 by default. Needs direct access
+    #[arg(long, global = true, value_name = "N")]
+    retries: Option<u32>,
+
+    /// How long to wait for a response at first, in milliseconds, 20 by default.
+    /// Grows on timeouts. Needs direct access
+    #[arg(long, global = true, value_name = "MS")]
+    timeout: Option<u64>,
+
+    /// How long to wait for another process using the device, in milliseconds, 2000 by
+    /// default. Needs direct access
+    #[arg(long, global = true, value_name = "MS")]
+    lock_timeout: Option<u64>,
+
+    /// Open the device directly even if madrd is running
+    #[arg(long, global = true)]
+    no_daemon: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -38,6 +91,72 @@ enum Commands {
     /// Get device info
     #[clap(subcommand)]
     Info(Info),
+
+    /// Log the battery and analyze the log
+    #[clap(subcommand)]
+    Battery(Battery),
+
+    /// List connected devices and whether they are supported
+    Devices,
+
+    /// Find the registers behind a setting by diffing the register space
+    Probe {
+        /// Print a madr-lib module stub with this name for the changed registers
+        #[arg(long, value_name = "NAME")]
+        stub: Option<String>,
+    },
+
+    /// Explain every byte of a single 17-byte report
+    Decode {
+        /// Report as hex, e.g. "08 07 00 00 00 06 20 35 04 51 01 54 00 00 00 00 41"
+        #[arg(required = true, num_args = 1..)]
+        hex: Vec<String>,
+    },
+
+    /// Decode the reports in a usbmon pcap/pcapng capture of web hub traffic
+    DecodePcap {
+        /// Capture file
+        file: PathBuf,
+    },
+
+    /// Print the battery and DPI for a status bar, whenever they change
+    Bar {
+        /// Output for this bar
+        #[arg(short, long, value_enum, default_value_t = bar::Format::Waybar)]
+        format: bar::Format,
+        /// Text to show, with {percentage}, {voltage}, {charging}, {stage}, {dpi}, {color},
+        /// {polling_rate} and {model} filled in
+        #[arg(short, long, default_value = bar::DEFAULT_TEMPLATE)]
+        template: String,
+        /// Seconds between polls
+        #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+        /// Print once and exit
+        #[arg(long)]
+        once: bool,
+    },
+
+    /// Serve Prometheus metrics for the mouse over HTTP
+    Exporter {
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR:PORT", default_value = "127.0.0.1:9723")]
+        listen: SocketAddr,
+    },
+
+    /// Serve a web UI and a JSON API for the mouse's settings
+    Serve {
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR:PORT", default_value = "127.0.0.1:9724")]
+        listen: SocketAddr,
+    },
+
+    /// Report devices being plugged in and out, optionally applying a profile to each
+    #[cfg(all(feature = "hotplug", target_os = "linux"))]
+    Watch {
+        /// Profile to apply whenever a mouse or receiver appears
+        #[arg(long, value_name = "PROFILE.TOML")]
+        apply: Option<PathBuf>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -56,8 +175,7 @@ enum Set {
     },
     /// Set active DPI stage
     DpiStage {
-        /// DPI stage to set active (1-8)
-        #[arg(value_parser = value_parser!(u8).range(1..=8))]
+        /// DPI stage to set active
         stage: u8,
     },
     /// Set polling rate
@@ -76,24 +194,50 @@ enum Set {
 
 #[derive(Subcommand)]
 enum Info {
+    /// Get the model and what it supports
+    Device,
+    /// Dump the HID report descriptors of every interface
+    Hid,
     /// Get battery status
     Battery,
     /// Get sensor settings
     Sensor,
 }
 
+#[derive(Subcommand)]
+enum Battery {
+    /// Sample the battery on an interval into a CSV log
+    Log {
+        /// Seconds between samples
+        #[arg(short, long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+        /// Log file, defaults to $XDG_DATA_HOME/madrctl/battery.csv
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+        /// Stop after this many samples
+        #[arg(short, long)]
+        count: Option<u64>,
+    },
+    /// Discharge rate, time left, charge cycles and battery life per setting from the log
+    Report {
+        /// Log file, defaults to $XDG_DATA_HOME/madrctl/battery.csv
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand)]
 enum Dpi {
     /// Change DPI settings for a specific stage
     ModifyStage {
-        /// DPI stage to change (1-8)
-        #[arg(short, long, value_parser = value_parser!(u8).range(1..=8))]
+        /// DPI stage to change
+        #[arg(short, long)]
         stage: u8,
         /// X DPI value
-        #[arg(short, long, value_parser = value_parser!(u16).range(50..=16000))]
+        #[arg(short, long)]
         x_dpi: Option<u16>,
         /// Y DPI value, if not specified, X DPI will be used
-        #[arg(short, long, value_parser = value_parser!(u16).range(50..=16000))]
+        #[arg(short, long)]
         y_dpi: Option<u16>,
         /// RGB color in 255,255,255 format, if not specified, color will not be changed
         #[arg(short, long)]
@@ -103,7 +247,49 @@ enum Dpi {
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let device = Device::open()?;
+
+    // Commands that work without a device attached
+    match &cli.command {
+        Commands::Decode { hex } => return decode::run(hex),
+        Commands::DecodePcap { file } => return pcap::run(file),
+        Commands::Devices => return list_devices(),
+        Commands::Battery(Battery::Report { file }) => {
+            return battery::report(&log_path(file.as_deref())?)
+        }
+        Commands::Info(Info::Hid) => return dump_report_descriptors(),
+        Commands::Bar {
+            format,
+            template,
+            interval,
+            once,
+        } => {
+            return bar::run(
+                || open_backend(&cli),
+                *format,
+                template,
+                Duration::from_secs(*interval),
+                *once,
+            )
+        }
+        Commands::Exporter { listen } => return exporter::run(|| open_backend(&cli), *listen),
+        Commands::Serve { listen } => return serve::run(|| open_backend(&cli), *listen),
+        #[cfg(all(feature = "hotplug", target_os = "linux"))]
+        Commands::Watch { apply } => {
+            warn_skipped_descriptors();
+            return watch::run(apply.as_deref(), |device| configure(device, &cli));
+        }
+        _ => {}
+    }
+
+    let device = open_backend(&cli)?;
+
+    if !device.model().verified {
+        eprintln!(
+            "{}: {} has not been tested with madrctl",
+            "warning".yellow(),
+            device.model().name
+        );
+    }
 
     match cli.command {
         Commands::Set(cmd) => match cmd {
@@ -114,7 +300,7 @@ fn main() -> Result<()> {
                     println!("warning: low debounce values are not recommended")
                 }
 
-                debounce::apply_setting(&device, Debounce::try_from(time_val)?)?;
+                device.set_debounce(Debounce::try_from(time_val)?)?;
             }
             Set::Sleep { timeout } => {
                 let duration = match timeout.as_str() {
@@ -129,37 +315,26 @@ fn main() -> Result<()> {
                     _ => return Err(anyhow!("invalid timeout value: {}", timeout)),
                 };
 
-                sleep::apply_setting(&device, duration)?;
+                device.set_sleep(duration)?;
             }
             Set::DpiStage { stage } => {
-                let settings = Performance::read(&device)?;
+                let settings = device.performance()?;
 
-                performance::apply_setting(
-                    &device,
-                    &Performance::new(stage, settings.polling_rate()),
-                )?;
+                device.set_performance(&Performance::new(stage, settings.polling_rate()))?;
             }
             Set::PollingRate { rate } => {
-                let r: u16 = rate.parse().unwrap();
-
-                // Validate polling rate for wired devices
-                if device.is_wired() && r > 1000 {
-                    return Err(anyhow!(
-                        "Wired mouse only supports up to 1000 Hz polling rate."
-                    ));
-                }
+                let r: u16 = rate
+                    .parse()
+                    .map_err(|_| anyhow!("invalid polling rate value: {}", rate))?;
 
                 let new_rate = PollingRate::try_from(r)?;
-                let settings = Performance::read(&device)?;
-                performance::apply_setting(
-                    &device,
-                    &Performance::new(settings.dpi_stage(), new_rate),
-                )?;
+                let settings = device.performance()?;
+                device.set_performance(&Performance::new(settings.dpi_stage(), new_rate))?;
             }
             Set::Sensor { preset } => {
                 let preset: Mode = preset.parse()?;
 
-                sensor::apply_setting(&device, preset)?;
+                device.set_sensor_mode(preset)?;
             }
         },
         Commands::Dpi(cmd) => match cmd {
@@ -169,12 +344,43 @@ fn main() -> Result<()> {
                 y_dpi,
                 rgb,
             } => {
-                dpi::apply_dpi_setting(&device, stage, x_dpi, y_dpi, rgb.as_deref())?;
+                device.set_dpi(stage, x_dpi, y_dpi, rgb.as_deref())?;
             }
         },
         Commands::Info(cmd) => match cmd {
+            Info::Hid => unreachable!(),
+            Info::Device => {
+                let model = device.model();
+                let caps = device.capabilities();
+
+                println!(
+                    "{} ({:04x}:{:04x}, {})",
+                    model.name.bold(),
+                    model.vid,
+                    device.product_id(),
+                    if device.is_wired() {
+                        "wired"
+                    } else {
+                        "wireless"
+                    }
+                );
+                println!(
+                    "DPI: {}-{} in steps of {}, {} stages",
+                    caps.min_dpi, caps.max_dpi, caps.dpi_step, caps.dpi_stages
+                );
+
+                let rates: Vec<String> = caps
+                    .polling_rates(device.is_wired())
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect();
+                println!("Polling rates: {}", rates.join(", "));
+
+                let features: Vec<String> = caps.features.iter().map(|f| f.to_string()).collect();
+                println!("Features: {}", features.join(", "));
+            }
             Info::Battery => {
-                let b = Battery::read(&device)?;
+                let b = device.battery()?;
 
                 let colored_percentage = match b.percentage() {
                     0..=20 => format!("{}", b.percentage()).red(),
@@ -200,7 +406,7 @@ fn main() -> Result<()> {
                 }
             }
             Info::Sensor => {
-                let s = Sensor::read(&device)?;
+                let s = device.sensor()?;
 
                 let colored_preset = match s.mode() {
                     Mode::Basic => "basic".green(),
@@ -211,6 +417,161 @@ fn main() -> Result<()> {
                 println!("Sensor is set to {} mode", colored_preset);
             }
         },
+        Commands::Battery(cmd) => match cmd {
+            Battery::Log {
+                interval,
+                file,
+                count,
+            } => battery::log(
+                &device,
+                &log_path(file.as_deref())?,
+                Duration::from_secs(interval),
+                count,
+            )?,
+            Battery::Report { .. } => unreachable!(),
+        },
+        Commands::Probe { stub } => probe::run(&device, stub.as_deref())?,
+        Commands::Decode { .. }
+        | Commands::DecodePcap { .. }
+        | Commands::Devices
+        | Commands::Bar { .. }
+        | Commands::Exporter { .. }
+        | Commands::Serve { .. } => unreachable!(),
+        #[cfg(all(feature = "hotplug", target_os = "linux"))]
+        Commands::Watch { .. } => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Battery log to use, the given one or the default
+fn log_path(file: Option<&Path>) -> Result<PathBuf> {
+    file.map(Path::to_path_buf)
+        .or_else(telemetry::default_log_path)
+        .ok_or_else(|| anyhow!("no log file given and $HOME is not set"))
+}
+
+/// Connect to madrd if it is running and the global options allow it, otherwise open the device
+fn open_backend(cli: &Cli) -> Result<Backend> {
+    // Tracing, replaying and forcing a device only make sense with the device opened here
+    let direct =
+        cli.no_daemon || cli.trace.is_some() || cli.replay.is_some() || cli.force_device.is_some();
+
+    let daemon = if direct { None } else { Backend::daemon()? };
+    match daemon {
+        // madrd has its own retry policy and holds the device lock itself
+        Some(_) if cli.retries.is_some() || cli.timeout.is_some() || cli.lock_timeout.is_some() => {
+            Err(anyhow!(
+                "--retries, --timeout and --lock-timeout don't apply while madrd serves the \
+                 device, add --no-daemon to open it directly"
+            ))
+        }
+        Some(daemon) => Ok(daemon),
+        None => Ok(Backend::Direct(open_device(cli)?)),
+    }
+}
+
+/// Open the device for direct access as the global options ask for
+fn open_device(cli: &Cli) -> Result<Device> {
+    warn_skipped_descriptors();
+
+    let device = match (&cli.replay, cli.force_device) {
+        (Some(replay), _) => Device::replay(replay)?,
+        (None, Some((vid, pid))) => Device::open_forced(vid, pid, cli.wired)?,
+        (None, None) => Device::open()?,
+    };
+
+    let device = match &cli.trace {
+        Some(trace) => device.trace(trace)?,
+        None => device,
+    };
+
+    Ok(configure(device, cli))
+}
+
+/// Apply the global retry and locking options to an opened device
+fn configure(device: Device, cli: &Cli) -> Device {
+    let timeout = cli.timeout.unwrap_or(DEFAULT_TIMEOUT_MS);
+
+    device
+        .with_retry_policy(RetryPolicy {
+            attempts: cli.retries.unwrap_or(DEFAULT_RETRIES) + 1,
+            read_timeout: Duration::from_millis(timeout),
+            max_read_timeout: Duration::from_millis(timeout.max(200)),
+            ..RetryPolicy::default()
+        })
+        .with_lock_timeout(Duration::from_millis(
+            cli.lock_timeout.unwrap_or(DEFAULT_LOCK_TIMEOUT_MS),
+        ))
+}
+
+/// Tell about the user supplied descriptors the device lookup will skip
+fn warn_skipped_descriptors() {
+    let Ok((_, skipped)) = model::load_models() else {
+        return;
+    };
+
+    for e in skipped {
+        eprintln!("{}: {}, skipping it", "warning".yellow(), e);
+    }
+}
+
+fn list_devices() -> Result<()> {
+    warn_skipped_descriptors();
+
+    let entries = madr_lib::device::list()?;
+
+    if entries.is_empty() {
+        println!("No devices found");
+        return Ok(());
+    }
+
+    for entry in entries {
+        let status = match &entry.model {
+            _ if !entry.is_config_interface() => continue,
+            Some(model) if model.verified => format!("{} ({})", model.name, "verified".green()),
+            Some(model) => format!(
+                "{} ({}, from {})",
+                model.name,
+                "unverified".yellow(),
+                model
+                    .source
+                    .as_ref()
+                    .map_or("--force-device".into(), |p| p.display().to_string())
+            ),
+            None => format!("{}, try --force-device", "unknown model".red()),
+        };
+
+        println!(
+            "{:04x}:{:04x} interface {} {} {}",
+            entry.vid, entry.pid, entry.interface, entry.path, status
+        );
+    }
+
+    Ok(())
+}
+
+fn dump_report_descriptors() -> Result<()> {
+    for entry in madr_lib::device::list()? {
+        let role = if entry.is_config_interface() {
+            " (configuration)".green().to_string()
+        } else {
+            String::new()
+        };
+
+        println!(
+            "{:04x}:{:04x} interface {} {}{}",
+            entry.vid, entry.pid, entry.interface, entry.path, role
+        );
+
+        match &entry.report_descriptor {
+            Some(descriptor) => {
+                for line in descriptor.to_string().lines() {
+                    println!("  {}", line);
+                }
+            }
+            None => println!("  {}", "report descriptor could not be read".yellow()),
+        }
     }
 
     Ok(())
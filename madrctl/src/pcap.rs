@@ -0,0 +1,388 @@
+// Minimal pcap/pcapng reader for usbmon captures
+// Only the link types produced by Linux usbmon are supported:
+//   189 LINKTYPE_USB_LINUX          (48 byte header)
+//   220 LINKTYPE_USB_LINUX_MMAPPED  (64 byte header)
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use colored::Colorize;
+
+use madr_lib::{decode, model::VXE_VID, register};
+
+const LINKTYPE_USB_LINUX: u32 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+const XFER_INTERRUPT: u8 = 1;
+const XFER_CONTROL: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Feature,
+    Output,
+    Input,
+}
+
+#[derive(Debug)]
+struct Report {
+    timestamp: f64,
+    bus: u16,
+    device: u8,
+    host_to_device: bool,
+    kind: Kind,
+    data: Vec<u8>,
+}
+
+/// A packet as captured, with what is needed to parse its link layer header
+struct Captured<'a> {
+    linktype: u32,
+    /// Byte order of the capturing host, which usbmon headers are in
+    big_endian: bool,
+    data: &'a [u8],
+}
+
+/// A usbmon packet, with its link layer header already parsed
+struct UsbPacket<'a> {
+    id: u64,
+    event: u8,
+    xfer_type: u8,
+    endpoint: u8,
+    device: u8,
+    bus: u16,
+    setup: Option<[u8; 8]>,
+    timestamp: f64,
+    data: &'a [u8],
+}
+
+fn parse_usbmon<'a>(captured: &Captured<'a>) -> Option<UsbPacket<'a>> {
+    let packet = captured.data;
+    let header_len = match captured.linktype {
+        LINKTYPE_USB_LINUX => 48,
+        LINKTYPE_USB_LINUX_MMAPPED => 64,
+        _ => return None,
+    };
+
+    if packet.len() < header_len {
+        return None;
+    }
+
+    // The header is in host byte order, the setup packet and the data are as on the bus
+    let u64_at = |o: usize| {
+        let raw = packet[o..o + 8].try_into().unwrap();
+        if captured.big_endian {
+            u64::from_be_bytes(raw)
+        } else {
+            u64::from_le_bytes(raw)
+        }
+    };
+    let u32_at = |o: usize| {
+        let raw = packet[o..o + 4].try_into().unwrap();
+        if captured.big_endian {
+            u32::from_be_bytes(raw)
+        } else {
+            u32::from_le_bytes(raw)
+        }
+    };
+    let u16_at = |o: usize| {
+        let raw = [packet[o], packet[o + 1]];
+        if captured.big_endian {
+            u16::from_be_bytes(raw)
+        } else {
+            u16::from_le_bytes(raw)
+        }
+    };
+
+    let setup = (packet[14] == 0).then(|| packet[40..48].try_into().unwrap());
+    let captured = (u32_at(36) as usize).min(packet.len() - header_len);
+
+    Some(UsbPacket {
+        id: u64_at(0),
+        event: packet[8],
+        xfer_type: packet[9],
+        endpoint: packet[10],
+        device: packet[11],
+        bus: u16_at(12),
+        setup,
+        timestamp: u64_at(16) as f64 + u32_at(24) as f64 / 1_000_000.0,
+        data: &packet[header_len..header_len + captured],
+    })
+}
+
+/// Read every packet from a pcap or pcapng file
+fn read_packets(bytes: &[u8]) -> Result<Vec<Captured<'_>>> {
+    if bytes.len() < 24 {
+        bail!("file is too short to be a capture");
+    }
+
+    match &bytes[0..4] {
+        [0x0A, 0x0D, 0x0D, 0x0A] => read_pcapng(bytes),
+        [0xD4, 0xC3, 0xB2, 0xA1] | [0x4D, 0x3C, 0xB2, 0xA1] => read_pcap(bytes, false),
+        [0xA1, 0xB2, 0xC3, 0xD4] | [0xA1, 0xB2, 0x3C, 0x4D] => read_pcap(bytes, true),
+        _ => bail!("not a pcap or pcapng file"),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u32> {
+    let raw: [u8; 4] = bytes
+        .get(offset..offset + 4)
+        .ok_or_else(|| anyhow!("truncated capture"))?
+        .try_into()?;
+
+    Ok(if big_endian {
+        u32::from_be_bytes(raw)
+    } else {
+        u32::from_le_bytes(raw)
+    })
+}
+
+fn read_u16(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u16> {
+    let raw: [u8; 2] = bytes
+        .get(offset..offset + 2)
+        .ok_or_else(|| anyhow!("truncated capture"))?
+        .try_into()?;
+
+    Ok(if big_endian {
+        u16::from_be_bytes(raw)
+    } else {
+        u16::from_le_bytes(raw)
+    })
+}
+
+fn read_pcap(bytes: &[u8], big_endian: bool) -> Result<Vec<Captured<'_>>> {
+    let linktype = read_u32(bytes, 20, big_endian)?;
+    let mut packets = Vec::new();
+    let mut offset = 24;
+
+    while offset + 16 <= bytes.len() {
+        let captured = read_u32(bytes, offset + 8, big_endian)? as usize;
+        let start = offset + 16;
+        let packet = bytes
+            .get(start..start + captured)
+            .ok_or_else(|| anyhow!("truncated packet at offset {}", offset))?;
+
+        packets.push(Captured {
+            linktype,
+            big_endian,
+            data: packet,
+        });
+        offset = start + captured;
+    }
+
+    Ok(packets)
+}
+
+fn read_pcapng(bytes: &[u8]) -> Result<Vec<Captured<'_>>> {
+    let mut packets = Vec::new();
+    let mut linktypes = Vec::new();
+    let mut big_endian = false;
+    let mut offset = 0;
+
+    while offset + 12 <= bytes.len() {
+        let block_type = read_u32(bytes, offset, big_endian)?;
+
+        // Section header block, the byte order magic decides the endianness of the section
+        if block_type == 0x0A0D0D0A {
+            big_endian = bytes[offset + 8..offset + 12] == [0x1A, 0x2B, 0x3C, 0x4D];
+            linktypes.clear();
+        }
+
+        let block_len = read_u32(bytes, offset + 4, big_endian)? as usize;
+        if block_len < 12 || offset + block_len > bytes.len() {
+            bail!("malformed pcapng block at offset {}", offset);
+        }
+
+        let body = &bytes[offset + 8..offset + block_len - 4];
+        match block_type {
+            // Interface description block
+            0x00000001 => {
+                let linktype = read_u16(body, 0, big_endian)? as u32;
+                linktypes.push(linktype);
+            }
+            // Enhanced packet block
+            0x00000006 => {
+                let interface = read_u32(body, 0, big_endian)? as usize;
+                let captured = read_u32(body, 12, big_endian)? as usize;
+                let linktype = *linktypes
+                    .get(interface)
+                    .ok_or_else(|| anyhow!("packet for unknown interface {}", interface))?;
+                let packet = body
+                    .get(20..20 + captured)
+                    .ok_or_else(|| anyhow!("truncated packet at offset {}", offset))?;
+                packets.push(Captured {
+                    linktype,
+                    big_endian,
+                    data: packet,
+                });
+            }
+            // Simple packet block, always belongs to the first interface
+            0x00000003 => {
+                let linktype = *linktypes
+                    .first()
+                    .ok_or_else(|| anyhow!("packet before interface description"))?;
+                let original = read_u32(body, 0, big_endian)? as usize;
+                let captured = original.min(body.len() - 4);
+                packets.push(Captured {
+                    linktype,
+                    big_endian,
+                    data: &body[4..4 + captured],
+                });
+            }
+            _ => {}
+        }
+
+        offset += block_len;
+    }
+
+    Ok(packets)
+}
+
+/// Extract HID reports and the vendor IDs of every device seen in the capture
+fn extract_reports(packets: &[Captured<'_>]) -> (Vec<Report>, HashMap<(u16, u8), u16>) {
+    let mut reports = Vec::new();
+    let mut vendors = HashMap::new();
+    let mut pending_setup: HashMap<u64, [u8; 8]> = HashMap::new();
+    let mut start = None;
+
+    for packet in packets.iter().filter_map(parse_usbmon) {
+        let start = *start.get_or_insert(packet.timestamp);
+        let is_in = packet.endpoint & 0x80 != 0;
+
+        let mut push = |host_to_device: bool, kind: Kind| {
+            reports.push(Report {
+                timestamp: packet.timestamp - start,
+                bus: packet.bus,
+                device: packet.device,
+                host_to_device,
+                kind,
+                data: packet.data.to_vec(),
+            })
+        };
+
+        match (packet.xfer_type, packet.event) {
+            (XFER_CONTROL, b'S') => {
+                let Some(setup) = packet.setup else { continue };
+
+                // SET_REPORT, report type is the high byte of wValue
+                if setup[0] == 0x21 && setup[1] == 0x09 && !packet.data.is_empty() {
+                    let kind = if setup[3] == 0x03 {
+                        Kind::Feature
+                    } else {
+                        Kind::Output
+                    };
+                    push(true, kind);
+                } else {
+                    pending_setup.insert(packet.id, setup);
+                }
+            }
+            (XFER_CONTROL, b'C') => {
+                let Some(setup) = pending_setup.remove(&packet.id) else {
+                    continue;
+                };
+
+                match (setup[0], setup[1], setup[3]) {
+                    // GET_REPORT
+                    (0xA1, 0x01, _) if !packet.data.is_empty() => push(false, Kind::Feature),
+                    // GET_DESCRIPTOR(DEVICE)
+                    (0x80, 0x06, 0x01) if packet.data.len() >= 10 => {
+                        let vid = u16::from_le_bytes([packet.data[8], packet.data[9]]);
+                        vendors.insert((packet.bus, packet.device), vid);
+                    }
+                    _ => {}
+                }
+            }
+            (XFER_INTERRUPT, b'S') if !is_in && !packet.data.is_empty() => push(true, Kind::Output),
+            (XFER_INTERRUPT, b'C') if is_in && !packet.data.is_empty() => push(false, Kind::Input),
+            _ => {}
+        }
+    }
+
+    (reports, vendors)
+}
+
+pub fn run(path: &Path) -> Result<()> {
+    let bytes = fs::read(path)?;
+    let packets = read_packets(&bytes)?;
+
+    if !packets
+        .iter()
+        .any(|p| p.linktype == LINKTYPE_USB_LINUX || p.linktype == LINKTYPE_USB_LINUX_MMAPPED)
+    {
+        bail!("capture does not contain usbmon packets (link type 189 or 220)");
+    }
+
+    let (reports, vendors) = extract_reports(&packets);
+
+    // Without the enumeration in the capture, fall back to anything that looks like a config report
+    let filter_by_vendor = vendors.values().any(|vid| *vid == VXE_VID);
+    if !filter_by_vendor {
+        println!(
+            "{}: no device descriptor for VID {:#06x} in capture, showing every 17-byte report",
+            "note".cyan(),
+            VXE_VID
+        );
+    }
+
+    let mut unknown = 0;
+    for report in reports.iter().filter(|r| {
+        if filter_by_vendor {
+            vendors.get(&(r.bus, r.device)) == Some(&VXE_VID)
+        } else {
+            r.data.len() == register::REPORT_LEN && r.data[0] == register::REPORT_ID
+        }
+    }) {
+        let arrow = if report.host_to_device { "->" } else { "<-" };
+        let kind = match report.kind {
+            Kind::Feature => "feature",
+            Kind::Output => "output",
+            Kind::Input => "input",
+        };
+        let prefix = format!(
+            "{:>10.6} {}.{:<3} {} {:<7}",
+            report.timestamp, report.bus, report.device, arrow, kind
+        );
+
+        match decode::decode(&report.data) {
+            Ok(message) if message.is_known() => {
+                let checksum_ok = report.data[16] == register::checksum(&report.data);
+                if checksum_ok {
+                    println!("{} {}", prefix, message);
+                } else {
+                    println!("{} {} {}", prefix, message, "(bad checksum)".red());
+                }
+            }
+            Ok(message) => {
+                unknown += 1;
+                println!(
+                    "{} {} {}",
+                    prefix,
+                    message.to_string().yellow(),
+                    "[investigate]".yellow()
+                );
+            }
+            Err(_) => {
+                unknown += 1;
+                println!(
+                    "{} {} {}",
+                    prefix,
+                    hex(&report.data).yellow(),
+                    "[investigate]".yellow()
+                );
+            }
+        }
+    }
+
+    if unknown > 0 {
+        println!("\n{} report(s) could not be decoded", unknown);
+    }
+
+    Ok(())
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,202 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+
+use madr_lib::backend::Backend;
+use madr_lib::register::{self, Change, Command, Snapshot};
+
+pub fn run(device: &Backend, stub: Option<&str>) -> Result<()> {
+    // Check the name before the user goes through the probe
+    let stub = stub.map(module_name).transpose()?;
+
+    println!("Reading register space...");
+    let before = device.snapshot()?;
+
+    println!(
+        "Now change {} setting using the web hub or the mouse, then press Enter.",
+        "one".bold()
+    );
+    io::stdout().flush()?;
+    io::stdin().lock().read_line(&mut String::new())?;
+
+    let after = device.snapshot()?;
+    let changes = before.diff(&after);
+
+    if changes.is_empty() {
+        println!("No registers changed.");
+        return Ok(());
+    }
+
+    println!("{} byte(s) changed:", changes.len());
+    for change in &changes {
+        let pattern = match after.checksum_pattern(change.address) {
+            Some(p) if p.check == change.address => {
+                format!("check byte for {:#04x}..+{}", p.start, p.len)
+            }
+            Some(p) => format!(
+                "checked by {:#04x} (0x55 - sum of {} byte(s))",
+                p.check, p.len
+            ),
+            None => "no checksum found".dimmed().to_string(),
+        };
+
+        println!(
+            "  {:#04x}: {:#04x} -> {}  {}",
+            change.address,
+            change.before,
+            format!("{:#04x}", change.after).green(),
+            pattern
+        );
+    }
+
+    if let Some(name) = stub {
+        print!("\n{}", generate_stub(&name, &after, &changes)?);
+    }
+
+    Ok(())
+}
+
+/// Turn `name` into a snake_case Rust identifier, e.g. `my-setting` or
+/// `MySetting` into `my_setting`
+fn module_name(name: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut previous: Option<char> = None;
+    for c in name.trim().chars() {
+        if c.is_ascii_uppercase() {
+            if previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit()) {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            out.push(c);
+        } else if matches!(c, '-' | '_' | ' ' | '.') {
+            if !out.is_empty() && !out.ends_with('_') {
+                out.push('_');
+            }
+        } else {
+            return Err(anyhow!("invalid stub name {name:?}: unexpected {c:?}"));
+        }
+        previous = Some(c);
+    }
+
+    let out = out.trim_end_matches('_').to_string();
+    match out.chars().next() {
+        None => Err(anyhow!("invalid stub name {name:?}: empty")),
+        Some(c) if c.is_ascii_digit() => Err(anyhow!(
+            "invalid stub name {name:?}: must not start with a digit"
+        )),
+        _ if KEYWORDS.contains(&out.as_str()) => Err(anyhow!(
+            "invalid stub name {name:?}: {out} is a Rust keyword"
+        )),
+        _ => Ok(out),
+    }
+}
+
+/// Keywords that can't name the generated module
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+    "pub", "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe",
+    "use", "where", "while",
+];
+
+/// Generate a madr-lib module that writes the block of registers that changed
+fn generate_stub(name: &str, after: &Snapshot, changes: &[Change]) -> Result<String> {
+    let first = changes[0].address;
+    let start = after
+        .checksum_pattern(first)
+        .map_or(first, |p| p.start.min(first));
+
+    let last = changes
+        .iter()
+        .map(|c| {
+            after
+                .checksum_pattern(c.address)
+                .map_or(c.address, |p| p.check)
+        })
+        .max()
+        .unwrap_or(first);
+
+    let span = last as usize - start as usize + 1;
+    let len = span.min(register::MAX_PAYLOAD_LEN as usize);
+    if span > len {
+        eprintln!(
+            "{}: changes span more than one report, only {:#04x}..{:#04x} is included",
+            "warning".yellow(),
+            start,
+            start as usize + len - 1
+        );
+    }
+
+    let payload: Vec<u8> = (0..len).map(|i| after.get(start + i as u8)).collect();
+    let report = register::build_report(Command::Write, start, &payload)
+        .map_err(|e| anyhow!("failed to build report: {e}"))?;
+
+    // A single changed value with its own check byte can be turned into a parameter
+    let parameter = match after.checksum_pattern(first) {
+        Some(p)
+            if p.len == 1
+                && changes
+                    .iter()
+                    .all(|c| c.address == p.start || c.address == p.check) =>
+        {
+            Some(p)
+        }
+        _ => None,
+    };
+
+    let mut out = String::new();
+    writeln!(out, "// {name} settings module")?;
+    writeln!(
+        out,
+        "// Generated by `madrctl probe`: register {:#04x}, {} byte(s)",
+        start, len
+    )?;
+    writeln!(out)?;
+    writeln!(out, "use crate::Result;")?;
+    writeln!(out, "use crate::device::Device;")?;
+    writeln!(out)?;
+
+    let arg = if parameter.is_some() { "value: u8" } else { "" };
+    writeln!(out, "fn get_{name}_report({arg}) -> Vec<u8> {{")?;
+    writeln!(out, "    vec![")?;
+    for (i, byte) in report.iter().enumerate() {
+        let address = (6..6 + len).contains(&i).then(|| start + (i - 6) as u8);
+
+        let line = match (parameter, address) {
+            (Some(p), Some(a)) if a == p.start => "value,".to_string(),
+            (Some(p), Some(a)) if a == p.check => "0x55u8.wrapping_sub(value),".to_string(),
+            _ => format!("0x{:02X},", byte),
+        };
+
+        let changed = address.is_some_and(|a| changes.iter().any(|c| c.address == a));
+        if changed && parameter.is_none() {
+            writeln!(out, "        {line} // changed")?;
+        } else {
+            writeln!(out, "        {line}")?;
+        }
+    }
+    writeln!(out, "    ]")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "/// Apply {name} setting")?;
+    let (params, call) = if parameter.is_some() {
+        (", value: u8", "value")
+    } else {
+        ("", "")
+    };
+    writeln!(
+        out,
+        "pub fn apply_setting(device: &Device{params}) -> Result<()> {{"
+    )?;
+    writeln!(out, "    let report = get_{name}_report({call});")?;
+    writeln!(out, "    device.set(&report)?;")?;
+    writeln!(out)?;
+    writeln!(out, "    Ok(())")?;
+    writeln!(out, "}}")?;
+
+    Ok(out)
+}
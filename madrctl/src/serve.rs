@@ -0,0 +1,244 @@
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use serde_json::{json, Value};
+
+use madr_lib::{
+    backend::Backend,
+    model::Feature,
+    profile::{Profile, StageSettings},
+    MadRError,
+};
+
+use crate::http::{self, Request};
+
+/// The web UI, a single page doing everything through the API
+const INDEX: &str = include_str!("serve.html");
+
+const JSON: &str = "application/json";
+const BAD_REQUEST: &str = "400 Bad Request";
+const SERVICE_UNAVAILABLE: &str = "503 Service Unavailable";
+
+/// A failed API call, the status and a message for the client
+struct ApiError(&'static str, String);
+
+impl From<MadRError> for ApiError {
+    fn from(e: MadRError) -> Self {
+        let status = match e {
+            MadRError::InvalidSensorSetting(_)
+            | MadRError::InvalidSleepTimeout(_)
+            | MadRError::InvalidDebounceValue(_)
+            | MadRError::InvalidDpiSetting(_)
+            | MadRError::InvalidRgbValue(_)
+            | MadRError::InvalidPerformanceSetting(_)
+            | MadRError::InvalidProfile(_)
+            | MadRError::Unsupported(_) => BAD_REQUEST,
+            MadRError::DeviceBusy(_) => "409 Conflict",
+            _ => SERVICE_UNAVAILABLE,
+        };
+
+        Self(status, e.to_string())
+    }
+}
+
+struct Server<F> {
+    open: F,
+    listen: SocketAddr,
+    /// Opened on the first request, and again after the mouse stopped answering
+    device: Mutex<Option<Backend>>,
+}
+
+impl<F: Fn() -> Result<Backend>> Server<F> {
+    fn with_device<T>(
+        &self,
+        f: impl FnOnce(&Backend) -> madr_lib::Result<T>,
+    ) -> std::result::Result<T, ApiError> {
+        let mut device = self.device.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let backend = match &mut *device {
+            Some(backend) => backend,
+            None => {
+                let opened =
+                    (self.open)().map_err(|e| ApiError(SERVICE_UNAVAILABLE, format!("{:#}", e)))?;
+                device.insert(opened)
+            }
+        };
+
+        f(backend).map_err(|e| {
+            let error = ApiError::from(e);
+            if error.0 == SERVICE_UNAVAILABLE {
+                *device = None;
+            }
+            error
+        })
+    }
+
+    fn api(&self, request: &Request) -> std::result::Result<Value, ApiError> {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/api/device") => self.with_device(|device| Ok(device_info(device))),
+            ("GET", "/api/settings") => self.with_device(settings),
+            ("PATCH", "/api/settings") => {
+                // Browsers can't send JSON to another origin without asking first, which keeps
+                // other sites from changing settings through the user's browser
+                let content_type = request.header("content-type").unwrap_or_default();
+                if !content_type.starts_with(JSON) {
+                    return Err(ApiError(
+                        "415 Unsupported Media Type",
+                        format!("expected {}", JSON),
+                    ));
+                }
+
+                let profile: Profile = serde_json::from_slice(&request.body)
+                    .map_err(|e| ApiError(BAD_REQUEST, format!("invalid settings: {}", e)))?;
+                profile.validate()?;
+                let changes = self.with_device(|device| device.apply_profile(&profile))?;
+                Ok(json!(changes))
+            }
+            (_, "/api/device" | "/api/settings") => Err(ApiError(
+                "405 Method Not Allowed",
+                "method not allowed".into(),
+            )),
+            _ => Err(ApiError("404 Not Found", "not found".into())),
+        }
+    }
+
+    fn respond(&self, stream: TcpStream) -> Result<()> {
+        let Some(request) = Request::read(&stream)? else {
+            return Ok(());
+        };
+
+        // Only answer to names of this machine, so a page on another site can't reach the API
+        // by pointing its own domain at 127.0.0.1
+        if !is_local_host(request.header("host"), self.listen) {
+            let body = json!({ "error": "unknown host" }).to_string();
+            return http::respond(stream, &request, "403 Forbidden", JSON, body.as_bytes());
+        }
+
+        if matches!(request.method.as_str(), "GET" | "HEAD") && request.path == "/" {
+            let content_type = "text/html; charset=utf-8";
+            return http::respond(stream, &request, "200 OK", content_type, INDEX.as_bytes());
+        }
+
+        let (status, body) = match self.api(&request) {
+            Ok(value) => ("200 OK", value),
+            Err(ApiError(status, message)) => (status, json!({ "error": message })),
+        };
+        http::respond(stream, &request, status, JSON, body.to_string().as_bytes())
+    }
+}
+
+/// Whether `host` names the address being listened on, or this machine
+fn is_local_host(host: Option<&str>, listen: SocketAddr) -> bool {
+    let Some(host) = host else {
+        return false;
+    };
+
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+
+    name.eq_ignore_ascii_case("localhost")
+        || name
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback() || ip == listen.ip())
+}
+
+fn device_info(device: &Backend) -> Value {
+    let model = device.model();
+
+    json!({
+        "model": model.name,
+        "vid": model.vid,
+        "pid": device.product_id(),
+        "wired": device.is_wired(),
+        "verified": model.verified,
+        "capabilities": model.capabilities,
+        "polling_rates": model.capabilities.polling_rates(device.is_wired()),
+    })
+}
+
+/// Everything the UI shows: the settings in the shape of a profile, which is also what changes
+/// are sent as, and the battery
+fn settings(device: &Backend) -> madr_lib::Result<Value> {
+    let capabilities = device.capabilities();
+    let supports = |feature| capabilities.supports(feature);
+
+    let performance = device.performance()?;
+
+    let dpi = (1..=capabilities.dpi_stages)
+        .map(|stage| {
+            let dpi = device.read_stage(stage)?;
+            let rgb = supports(Feature::DpiColors)
+                .then(|| device.read_stage_color(stage))
+                .transpose()?;
+
+            Ok(StageSettings {
+                stage,
+                x_dpi: Some(dpi.x_dpi()),
+                y_dpi: Some(dpi.y_dpi()),
+                rgb,
+            })
+        })
+        .collect::<madr_lib::Result<Vec<_>>>()?;
+
+    let profile = Profile {
+        polling_rate: Some(performance.polling_rate()),
+        dpi_stage: Some(performance.dpi_stage()),
+        dpi,
+        sensor: supports(Feature::SensorMode)
+            .then(|| device.sensor())
+            .transpose()?
+            .map(|sensor| sensor.mode()),
+        debounce: supports(Feature::Debounce)
+            .then(|| device.debounce())
+            .transpose()?,
+        sleep: supports(Feature::Sleep)
+            .then(|| device.sleep())
+            .transpose()?
+            .map(|sleep| sleep.as_secs()),
+    };
+    let battery = supports(Feature::Battery)
+        .then(|| device.battery())
+        .transpose()?;
+
+    let mut settings = json!(profile);
+    settings["battery"] = json!(battery);
+    Ok(settings)
+}
+
+/// Serve the web UI and its API on `listen` until interrupted.
+/// `open` connects to the mouse, again after it stopped answering.
+pub fn run(open: impl Fn() -> Result<Backend> + Sync, listen: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(listen).with_context(|| format!("binding {}", listen))?;
+    let listen = listener.local_addr()?;
+    println!("Serving the web UI on http://{}/", listen);
+
+    let server = Server {
+        open,
+        listen,
+        device: Mutex::new(None),
+    };
+
+    // A thread per connection, so a browser's idle speculative connection holds up nothing
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let server = &server;
+            scope.spawn(move || {
+                let result = stream
+                    .map_err(anyhow::Error::from)
+                    .and_then(|stream| server.respond(stream));
+
+                if let Err(e) = result {
+                    eprintln!("{}: {}", "warning".yellow(), e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
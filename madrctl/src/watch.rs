@@ -0,0 +1,99 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use colored::Colorize;
+
+use madr_lib::{
+    device::Device,
+    hotplug::{Event, HotplugDevice, Watcher},
+    profile::{Change, Profile},
+};
+
+/// Time for the other interfaces of a device to show up after the first one did
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Attempts at opening a device that has just been connected
+const OPEN_ATTEMPTS: u32 = 5;
+
+/// Print hotplug events, applying `profile` to every device that is connected.
+/// `configure` sets up each opened device like the global options ask for.
+pub fn run(profile: Option<&Path>, configure: impl Fn(Device) -> Device) -> Result<()> {
+    let profile = profile.map(Profile::load).transpose()?;
+    let watcher = Watcher::new()?;
+
+    for device in watcher.connected() {
+        println!("{} is connected", describe(&device));
+
+        if let Some(profile) = &profile {
+            apply(profile, &device, &configure);
+        }
+    }
+
+    println!("Watching for devices, press Ctrl-C to stop");
+
+    for event in watcher {
+        match event? {
+            Event::Connected(device) => {
+                println!("{} connected", describe(&device));
+
+                if let Some(profile) = &profile {
+                    thread::sleep(SETTLE_TIME);
+                    apply(profile, &device, &configure);
+                }
+            }
+            Event::Disconnected(device) => println!("{} disconnected", describe(&device)),
+        }
+    }
+
+    Ok(())
+}
+
+fn describe(device: &HotplugDevice) -> String {
+    format!(
+        "{} ({:04x}:{:04x}, {})",
+        device.model.name.bold(),
+        device.vid,
+        device.pid,
+        if device.is_wired() {
+            "wired"
+        } else {
+            "wireless"
+        }
+    )
+}
+
+fn apply(profile: &Profile, device: &HotplugDevice, configure: &impl Fn(Device) -> Device) {
+    match open_and_apply(profile, device, configure) {
+        Ok(changes) if changes.is_empty() => println!("  profile already applied"),
+        Ok(changes) => {
+            for change in changes {
+                println!("  {}", change);
+            }
+        }
+        Err(e) => eprintln!("  {}: {:#}", "error".red(), e),
+    }
+}
+
+fn open_and_apply(
+    profile: &Profile,
+    device: &HotplugDevice,
+    configure: &impl Fn(Device) -> Device,
+) -> Result<Vec<Change>> {
+    let mut attempt = 1;
+
+    // The configuration interface may not be ready yet
+    let opened = loop {
+        match device.open() {
+            Ok(opened) => break opened,
+            Err(_) if attempt < OPEN_ATTEMPTS => {
+                attempt += 1;
+                thread::sleep(SETTLE_TIME);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    Ok(profile.apply(&configure(opened))?)
+}
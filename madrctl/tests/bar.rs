@@ -0,0 +1,119 @@
+// `madrctl bar --once` prints one line for the status bar in each format, for an emulated mouse
+// behind madrd. A mouse that doesn't answer is shown as asleep, other errors are reported.
+
+#![cfg(unix)]
+
+mod common;
+
+use madr_lib::emulator;
+use madr_lib::fault::{Fault, FaultyTransport};
+use madr_lib::{model, Device, Feature};
+
+use common::EmulatedDaemon;
+
+/// The line `madrctl bar --once` prints
+fn bar(daemon: &EmulatedDaemon, args: &[&str]) -> String {
+    let output = daemon.madrctl(&[&["bar", "--once"], args].concat());
+    assert!(
+        output.status.success(),
+        "{args:?}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .trim_end()
+        .to_string()
+}
+
+#[test]
+fn formats() {
+    let daemon = EmulatedDaemon::start("bar");
+
+    assert_eq!(
+        bar(&daemon, &["--format", "waybar"]),
+        r#"{"class":["high"],"percentage":100,"text":"100% 400 DPI","tooltip":"VXE MAD R\nBattery: 100% (4.10V)\nDPI stage 1: 400\nPolling rate: 1000 Hz"}"#
+    );
+    assert_eq!(
+        bar(&daemon, &["--format", "i3blocks"]),
+        r#"{"full_text":"100% 400 DPI","short_text":"100%"}"#
+    );
+    assert_eq!(bar(&daemon, &["--format", "polybar"]), "100% 400 DPI");
+    assert_eq!(bar(&daemon, &["--format", "template"]), "100% 400 DPI");
+
+    let template = "{model}: {stage} {color} {polling_rate} Hz {voltage} V [{charging}]";
+    assert_eq!(
+        bar(&daemon, &["--format", "template", "--template", template]),
+        "VXE MAD R: 1 #ff0000 1000 Hz 4.10 V []"
+    );
+}
+
+#[test]
+fn low_battery() {
+    let daemon = EmulatedDaemon::start("bar-low");
+    daemon.emulator.set_battery(15, true, 3700);
+
+    assert_eq!(
+        bar(&daemon, &["--format", "waybar"]),
+        r#"{"class":["low","charging"],"percentage":15,"text":"15% 400 DPI","tooltip":"VXE MAD R\nBattery: 15% (3.70V), charging\nDPI stage 1: 400\nPolling rate: 1000 Hz"}"#
+    );
+    assert_eq!(
+        bar(&daemon, &["--format", "i3blocks"]),
+        r##"{"color":"#ff0000","full_text":"15% 400 DPI","short_text":"15%"}"##
+    );
+    assert_eq!(
+        bar(&daemon, &["--format", "polybar"]),
+        "%{F#ff0000}15% 400 DPI%{F-}"
+    );
+    assert_eq!(
+        bar(
+            &daemon,
+            &["--format", "template", "--template", "{charging}"]
+        ),
+        "charging"
+    );
+}
+
+#[test]
+fn asleep() {
+    // Every response is lost
+    let daemon = EmulatedDaemon::start_with("bar-asleep", |emulator| {
+        let transport = (0..10_000).fold(
+            FaultyTransport::new(Box::new(emulator.clone())),
+            |t, call| t.inject(call, Fault::DropResponse),
+        );
+        emulator::device(Box::new(transport), false)
+    });
+
+    assert_eq!(
+        bar(&daemon, &["--format", "waybar"]),
+        r#"{"class":["asleep"],"percentage":0,"text":"asleep","tooltip":"VXE MAD R is asleep"}"#
+    );
+    assert_eq!(
+        bar(&daemon, &["--format", "i3blocks"]),
+        r#"{"full_text":"asleep","short_text":"asleep"}"#
+    );
+    assert_eq!(bar(&daemon, &["--format", "polybar"]), "asleep");
+    assert_eq!(bar(&daemon, &["--format", "template"]), "asleep");
+}
+
+#[test]
+fn errors_are_reported() {
+    let daemon = EmulatedDaemon::start_with("bar-no-battery", |emulator| {
+        let mut model = model::builtin_models().remove(0);
+        model
+            .capabilities
+            .features
+            .retain(|f| *f != Feature::Battery);
+        let pid = emulator.device(false).product_id();
+        Device::with_transport(Box::new(emulator.clone()), model, pid)
+    });
+
+    let output = daemon.madrctl(&["bar", "--once"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Not supported by this device: battery"),
+        "{stderr}"
+    );
+    assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,75 @@
+// An emulated mouse served by madrd's own handler, and madrctl run against it
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+use std::sync::Arc;
+use std::thread;
+
+use madr_lib::emulator::Emulator;
+use madr_lib::Device;
+use madrd::{Daemon, DEFAULT_CACHE_TTL};
+
+/// madrd serving `emulator` in a runtime directory of its own
+pub struct EmulatedDaemon {
+    pub runtime_dir: PathBuf,
+    pub emulator: Emulator,
+}
+
+impl EmulatedDaemon {
+    pub fn start(name: &str) -> Self {
+        Self::start_with(name, |emulator| emulator.device(false))
+    }
+
+    /// madrd serving the device `open` makes of the emulator
+    pub fn start_with(
+        name: &str,
+        open: impl Fn(&Emulator) -> Device + Send + Sync + 'static,
+    ) -> Self {
+        let runtime_dir = env::temp_dir().join(format!("madrctl-{}-{}", name, process::id()));
+        let socket_dir = runtime_dir.join("madr");
+        fs::create_dir_all(&socket_dir).unwrap();
+
+        let path = socket_dir.join("madrd.sock");
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let emulator = Emulator::new();
+        let device = emulator.clone();
+        let daemon = Arc::new(Daemon::new(
+            Box::new(move || Ok(open(&device))),
+            DEFAULT_CACHE_TTL,
+        ));
+        thread::spawn(move || daemon.run(listener));
+
+        Self {
+            runtime_dir,
+            emulator,
+        }
+    }
+
+    /// madrctl with `args`, going through this daemon
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_madrctl"));
+        command
+            .args(args)
+            .env("XDG_RUNTIME_DIR", &self.runtime_dir)
+            .env("NO_COLOR", "1")
+            .env("RUST_BACKTRACE", "0");
+        command
+    }
+
+    pub fn madrctl(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+}
+
+impl Drop for EmulatedDaemon {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.runtime_dir);
+    }
+}
@@ -0,0 +1,33 @@
+// madrctl goes through a running madrd, and refuses the options that only apply to a device it
+// opens itself instead of silently ignoring them.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::EmulatedDaemon;
+
+#[test]
+fn device_options_are_refused() {
+    let daemon = EmulatedDaemon::start("options");
+
+    let output = daemon.madrctl(&["info", "device"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("VXE MAD R"));
+
+    for option in [
+        ["--retries", "5"],
+        ["--timeout", "50"],
+        ["--lock-timeout", "100"],
+    ] {
+        let output = daemon.madrctl(&["info", "device", option[0], option[1]]);
+        assert!(!output.status.success(), "{option:?} was ignored");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("add --no-daemon"), "{option:?}: {stderr}");
+    }
+}
@@ -0,0 +1,64 @@
+// `madrctl decode` accepts a report written as hex bytes in any of the usual separators and
+// rejects anything that isn't a whole report.
+
+use std::process::{Command, Output};
+
+const DECODED: &str = "write polling rate = 1000 Hz, DPI stage = 1";
+
+fn decode(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_madrctl"))
+        .arg("decode")
+        .args(args)
+        .env("NO_COLOR", "1")
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .unwrap()
+}
+
+fn assert_decoded(args: &[&str]) {
+    let output = decode(args);
+    assert!(
+        output.status.success(),
+        "{args:?}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().next(), Some(DECODED), "{args:?}");
+    assert!(
+        stdout.contains("0x55 - sum of bytes 0..15 = 0x41 ok"),
+        "{stdout}"
+    );
+}
+
+fn assert_error(args: &[&str], message: &str) {
+    let output = decode(args);
+    assert!(!output.status.success(), "{args:?} was decoded");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(message), "{args:?}: {stderr}");
+}
+
+#[test]
+fn separators() {
+    assert_decoded(&[
+        "08", "07", "00", "00", "00", "06", "01", "54", "04", "51", "00", "55", "00", "00", "00",
+        "00", "41",
+    ]);
+    assert_decoded(&["08 07 00 00 00 06 01 54 04 51 00 55 00 00 00 00 41"]);
+    assert_decoded(&["08:07:00:00:00:06:01:54:04:51:00:55:00:00:00:00:41"]);
+    assert_decoded(&[
+        "0x08,0x07,0x00,0x00,0x00,0x06,0x01,0x54,0x04,0x51,0x00,0x55,0x00,0x00,0x00,0x00,0x41",
+    ]);
+    assert_decoded(&["0807000000060154", "0451005500000000", "41"]);
+    assert_decoded(&[
+        "0X08 0X07 0X00 0X00 0X00 0X06 0X01 0X54 0X04 0X51 0X00 0X55 0X00 0X00 0X00 0X00 0X41",
+    ]);
+}
+
+#[test]
+fn invalid_input() {
+    assert_error(&["08 07 zz"], "invalid hex digit: z");
+    assert_error(&["08 07 0"], "odd number of hex digits");
+    assert_error(&["08 07 00 00 00 06"], "expected 17 bytes, got 6");
+}
@@ -0,0 +1,123 @@
+// `madrctl exporter` serves the metrics of an emulated mouse behind madrd over HTTP. Every
+// connection gets its own thread, so a client that never sends its request holds up nobody,
+// and a request has to arrive in one piece, within a size limit.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Stdio};
+
+use common::EmulatedDaemon;
+
+struct Exporter {
+    child: Child,
+    addr: String,
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn exporter(daemon: &EmulatedDaemon) -> Exporter {
+    let mut child = daemon
+        .command(&["exporter", "--listen", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line
+        .trim()
+        .strip_prefix("Serving metrics on http://")
+        .and_then(|url| url.strip_suffix("/metrics"))
+        .unwrap_or_else(|| panic!("unexpected output: {line}"))
+        .to_string();
+
+    Exporter { child, addr }
+}
+
+/// The whole response to `request`, empty if the connection was closed without one
+fn send(exporter: &Exporter, request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(&exporter.addr).unwrap();
+    stream.write_all(request).unwrap();
+
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response);
+    response
+}
+
+fn get(exporter: &Exporter, method: &str, path: &str) -> String {
+    send(
+        exporter,
+        format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes(),
+    )
+}
+
+#[test]
+fn metrics() {
+    let daemon = EmulatedDaemon::start("exporter");
+    daemon.emulator.set_battery(87, true, 4012);
+    let exporter = exporter(&daemon);
+
+    let response = get(&exporter, "GET", "/metrics");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(
+        head.contains("Content-Type: text/plain; version=0.0.4"),
+        "{head}"
+    );
+
+    let samples: Vec<&str> = body.lines().filter(|l| !l.starts_with('#')).collect();
+    for expected in [
+        "madr_up 1",
+        r#"madr_battery_percent{model="VXE MAD R",vid="373b",pid="1040",serial=""} 87"#,
+        r#"madr_dpi{model="VXE MAD R",vid="373b",pid="1040",serial="",axis="x"} 400"#,
+    ] {
+        assert!(samples.contains(&expected), "{expected:?} missing:\n{body}");
+    }
+
+    let head = get(&exporter, "HEAD", "/metrics");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.ends_with("\r\n\r\n"), "{head}");
+
+    assert!(get(&exporter, "GET", "/").contains("metrics are at /metrics"));
+    assert!(get(&exporter, "GET", "/nothing").starts_with("HTTP/1.1 404 Not Found"));
+    assert!(get(&exporter, "POST", "/metrics").starts_with("HTTP/1.1 405 Method Not Allowed"));
+}
+
+#[test]
+fn idle_connections_hold_up_nothing() {
+    let daemon = EmulatedDaemon::start("exporter-idle");
+    let exporter = exporter(&daemon);
+
+    // Half a request line, then nothing
+    let mut idle = TcpStream::connect(&exporter.addr).unwrap();
+    idle.write_all(b"GET /met").unwrap();
+
+    assert!(get(&exporter, "GET", "/metrics").starts_with("HTTP/1.1 200 OK"));
+}
+
+#[test]
+fn oversized_headers_are_refused() {
+    let daemon = EmulatedDaemon::start("exporter-headers");
+    let exporter = exporter(&daemon);
+
+    let mut request = b"GET /metrics HTTP/1.1\r\n".to_vec();
+    request.extend_from_slice(format!("X-Padding: {}\r\n\r\n", "a".repeat(10_000)).as_bytes());
+    assert_eq!(send(&exporter, &request), "");
+
+    let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+    assert_eq!(send(&exporter, long_line.as_bytes()), "");
+
+    assert!(get(&exporter, "GET", "/metrics").starts_with("HTTP/1.1 200 OK"));
+}
@@ -0,0 +1,82 @@
+// `madrctl decode-pcap` reads the same usbmon session from pcap and pcapng files in both byte
+// orders and both usbmon header sizes, and rejects captures that end in the middle of a packet.
+// The usbmon headers are in the byte order of the file, as captured on a host of that order.
+//
+// Every capture holds the enumeration of a mouse at 1.5, a performance write, a read request
+// and its response, a write with a broken checksum, and a report to a different device.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Reports of the mouse at 1.5, the other device is filtered out by its vendor ID
+const DECODED: [&str; 4] = [
+    "  0.000500 1.5   -> feature write polling rate = 1000 Hz, DPI stage = 1",
+    "  0.001000 1.5   -> feature read performance",
+    "  0.001600 1.5   <- feature read polling rate = 1000 Hz, DPI stage = 2",
+    "  0.002000 1.5   -> feature write polling rate = 1000 Hz, DPI stage = 1 (bad checksum)",
+];
+
+fn decode_pcap(fixture: &str) -> Output {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(fixture);
+
+    Command::new(env!("CARGO_BIN_EXE_madrctl"))
+        .arg("decode-pcap")
+        .arg(path)
+        .env("NO_COLOR", "1")
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .unwrap()
+}
+
+fn assert_decoded(fixture: &str) {
+    let output = decode_pcap(fixture);
+    assert!(
+        output.status.success(),
+        "{fixture}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines, DECODED, "{fixture}");
+}
+
+fn assert_error(fixture: &str, message: &str) {
+    let output = decode_pcap(fixture);
+    assert!(!output.status.success(), "{fixture} was decoded");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(message), "{fixture}: {stderr}");
+}
+
+#[test]
+fn pcap_little_endian() {
+    // Microsecond timestamps, LINKTYPE_USB_LINUX_MMAPPED
+    assert_decoded("usbmon-le.pcap");
+}
+
+#[test]
+fn pcap_big_endian() {
+    // Nanosecond timestamps, LINKTYPE_USB_LINUX
+    assert_decoded("usbmon-be.pcap");
+}
+
+#[test]
+fn pcapng_little_endian() {
+    // LINKTYPE_USB_LINUX
+    assert_decoded("usbmon-le.pcapng");
+}
+
+#[test]
+fn pcapng_big_endian() {
+    // LINKTYPE_USB_LINUX_MMAPPED
+    assert_decoded("usbmon-be.pcapng");
+}
+
+#[test]
+fn truncated_captures() {
+    assert_error("truncated.pcap", "truncated packet at offset 750");
+    assert_error("truncated.pcapng", "malformed pcapng block at offset 788");
+}
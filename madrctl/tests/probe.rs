@@ -0,0 +1,91 @@
+// `madrctl probe` lists the registers a setting changed with the check bytes protecting them,
+// and turns them into a module stub under a snake_case name that must be a valid identifier.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::Stdio;
+
+use madr_lib::debounce::{self, Debounce};
+
+use common::EmulatedDaemon;
+
+#[test]
+fn stub_for_a_changed_setting() {
+    let daemon = EmulatedDaemon::start("probe");
+    let mut child = daemon
+        .command(&["probe", "--stub", "DebounceTime"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    while !line.contains("press Enter") {
+        line.clear();
+        assert!(
+            stdout.read_line(&mut line).unwrap() > 0,
+            "probe ended early"
+        );
+    }
+
+    debounce::apply_setting(&daemon.emulator.device(false), Debounce::Ms4).unwrap();
+    child.stdin.take().unwrap().write_all(b"\n").unwrap();
+
+    let mut output = String::new();
+    stdout.read_to_string(&mut output).unwrap();
+    assert!(child.wait().unwrap().success());
+
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(
+        lines[..3],
+        [
+            "2 byte(s) changed:",
+            "  0xa9: 0x08 -> 0x04  checked by 0xaa (0x55 - sum of 1 byte(s))",
+            "  0xaa: 0x4d -> 0x51  check byte for 0xa9..+1",
+        ]
+    );
+
+    // The single value with its check byte becomes a parameter
+    for expected in [
+        "// debounce_time settings module",
+        "// Generated by `madrctl probe`: register 0xa9, 2 byte(s)",
+        "fn get_debounce_time_report(value: u8) -> Vec<u8> {",
+        "        0x08,",
+        "        0xA9,",
+        "        value,",
+        "        0x55u8.wrapping_sub(value),",
+        "pub fn apply_setting(device: &Device, value: u8) -> Result<()> {",
+        "    let report = get_debounce_time_report(value);",
+    ] {
+        assert!(lines.contains(&expected), "{expected:?} missing:\n{output}");
+    }
+}
+
+#[test]
+fn stub_names() {
+    let daemon = EmulatedDaemon::start("probe-names");
+
+    for (name, message) in [
+        ("fn", "fn is a Rust keyword"),
+        ("Self", "self is a Rust keyword"),
+        ("2nd-stage", "must not start with a digit"),
+        ("_._", "empty"),
+        ("dpi/stage", "unexpected '/'"),
+    ] {
+        let output = daemon.madrctl(&["probe", "--stub", name]);
+        assert!(!output.status.success(), "{name:?} was accepted");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(message), "{name:?}: {stderr}");
+        assert!(
+            !String::from_utf8(output.stdout)
+                .unwrap()
+                .contains("Reading"),
+            "{name:?} was probed"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+// `madrctl serve` only answers requests made to a name of this machine, so a page on another
+// site can't reach the API by pointing its own domain at 127.0.0.1. The Host header is checked
+// before the mouse is opened, and the index page doesn't need one. The settings API is served
+// from the emulated madrd.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Stdio};
+
+use common::EmulatedDaemon;
+use serde_json::{json, Value};
+
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn serve(daemon: &EmulatedDaemon) -> Server {
+    let mut child = daemon
+        .command(&["serve", "--listen", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line
+        .trim()
+        .strip_prefix("Serving the web UI on http://")
+        .and_then(|url| url.strip_suffix('/'))
+        .unwrap_or_else(|| panic!("unexpected output: {line}"))
+        .to_string();
+
+    Server { child, addr }
+}
+
+/// Status line of the response to GET / with `host` as the Host header
+fn status(server: &Server, host: Option<&str>) -> String {
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    let host = host.map(|h| format!("Host: {h}\r\n")).unwrap_or_default();
+    write!(stream, "GET / HTTP/1.1\r\n{host}Connection: close\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+/// Status line and JSON body of the response to `method` on `path`
+fn request(
+    server: &Server,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &str,
+) -> (String, Value) {
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {content_type}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.lines().next().unwrap().to_string();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+fn get_settings(server: &Server) -> Value {
+    let (status, settings) = request(server, "GET", "/api/settings", "application/json", "");
+    assert_eq!(status, "HTTP/1.1 200 OK", "{settings}");
+    settings
+}
+
+#[test]
+fn local_hosts_are_served() {
+    let daemon = EmulatedDaemon::start("serve-local");
+    let server = serve(&daemon);
+    let port = server.addr.rsplit_once(':').unwrap().1;
+
+    for host in [
+        server.addr.clone(),
+        "127.0.0.1".to_string(),
+        format!("localhost:{port}"),
+        "LocalHost".to_string(),
+        format!("[::1]:{port}"),
+        "127.0.0.2".to_string(),
+    ] {
+        assert_eq!(status(&server, Some(&host)), "HTTP/1.1 200 OK", "{host}");
+    }
+}
+
+#[test]
+fn other_hosts_are_refused() {
+    let daemon = EmulatedDaemon::start("serve-other");
+    let server = serve(&daemon);
+    let port = server.addr.rsplit_once(':').unwrap().1;
+
+    for host in [
+        "evil.example".to_string(),
+        format!("evil.example:{port}"),
+        format!("127.0.0.1.evil.example:{port}"),
+        "localhost.evil.example".to_string(),
+        format!("192.0.2.1:{port}"),
+        "127.0.0.1:http".to_string(),
+        String::new(),
+    ] {
+        assert_eq!(
+            status(&server, Some(&host)),
+            "HTTP/1.1 403 Forbidden",
+            "{host}"
+        );
+    }
+
+    assert_eq!(status(&server, None), "HTTP/1.1 403 Forbidden");
+}
+
+#[test]
+fn settings() {
+    let daemon = EmulatedDaemon::start("serve-settings");
+    let server = serve(&daemon);
+
+    let settings = get_settings(&server);
+    assert_eq!(settings["polling_rate"], 1000);
+    assert_eq!(settings["dpi_stage"], 1);
+    assert_eq!(
+        settings["dpi"][1],
+        json!({ "stage": 2, "x_dpi": 800, "y_dpi": 800, "rgb": "0,255,0" })
+    );
+    assert_eq!(settings["debounce"], 8);
+    assert_eq!(settings["sleep"], 60);
+    assert_eq!(
+        settings["battery"],
+        json!({ "percentage": 100, "voltage_mv": 4100, "is_charging": false })
+    );
+}
+
+#[test]
+fn settings_are_changed() {
+    let daemon = EmulatedDaemon::start("serve-patch");
+    let server = serve(&daemon);
+
+    let (status, changes) = request(
+        &server,
+        "PATCH",
+        "/api/settings",
+        "application/json; charset=utf-8",
+        r#"{"dpi_stage": 2, "dpi": [{"stage": 3, "x_dpi": 3200}]}"#,
+    );
+    assert_eq!(status, "HTTP/1.1 200 OK", "{changes}");
+    let changes: Vec<&str> = changes
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["setting"].as_str().unwrap())
+        .collect();
+    assert_eq!(changes.len(), 2, "{changes:?}");
+
+    let settings = get_settings(&server);
+    assert_eq!(settings["dpi_stage"], 2);
+    assert_eq!(settings["dpi"][2]["x_dpi"], 3200);
+    assert_eq!(settings["dpi"][2]["y_dpi"], 3200);
+}
+
+#[test]
+fn invalid_settings_are_refused() {
+    let daemon = EmulatedDaemon::start("serve-invalid");
+    let server = serve(&daemon);
+    let before = daemon.emulator.registers();
+
+    for (content_type, body, expected) in [
+        (
+            "application/json",
+            r#"{"dpi": [{"stage": 1, "y_dpi": 800}]}"#,
+            ("HTTP/1.1 400 Bad Request", "y_dpi needs x_dpi"),
+        ),
+        (
+            "application/json",
+            r#"{"dpi_stage": 2, "dpi_stages": 3}"#,
+            ("HTTP/1.1 400 Bad Request", "invalid settings"),
+        ),
+        (
+            "text/plain",
+            r#"{"dpi_stage": 2}"#,
+            (
+                "HTTP/1.1 415 Unsupported Media Type",
+                "expected application/json",
+            ),
+        ),
+    ] {
+        let (status, response) = request(&server, "PATCH", "/api/settings", content_type, body);
+        assert_eq!(status, expected.0, "{body}: {response}");
+        let error = response["error"].as_str().unwrap();
+        assert!(error.contains(expected.1), "{body}: {error}");
+    }
+
+    assert_eq!(daemon.emulator.registers(), before);
+    assert_eq!(get_settings(&server)["dpi_stage"], 1);
+}
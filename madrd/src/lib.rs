@@ -0,0 +1,184 @@
+// madrd: keeps the mouse open and serves madr-lib over a Unix socket, see madr_lib::ipc
+// Every request runs with the device to itself, so clients never interleave transactions.
+// Reads of settings are cached for a short while to keep status bars polling every second
+// from waking the mouse up each time; any write drops the cache.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use madr_lib::ipc::{Request, RpcError, RpcRequest, RpcResponse};
+use madr_lib::{Device, MadRError};
+
+#[cfg(not(unix))]
+compile_error!("madrd needs Unix domain sockets");
+
+/// How long read settings are served from the cache unless configured otherwise
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// Requests whose results are cached, they only read settings
+fn is_cacheable(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::Info
+            | Request::Battery
+            | Request::Performance
+            | Request::Sensor
+            | Request::ReadStage { .. }
+            | Request::ReadStageColor { .. }
+    )
+}
+
+/// Errors after which the device is opened again for the next request
+fn is_disconnect(error: &MadRError) -> bool {
+    match error {
+        MadRError::Io(_) | MadRError::DeviceNotFound => true,
+        #[cfg(feature = "hidapi")]
+        MadRError::HidApiInit(_) => true,
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Opened on the first request, and again after it went away
+    device: Option<Device>,
+    /// Results of cacheable requests by request, with when they were read
+    cache: HashMap<String, (Instant, Value)>,
+}
+
+/// Opens the device for `Daemon`
+pub type Opener = Box<dyn Fn() -> madr_lib::Result<Device> + Send + Sync>;
+
+/// Runs requests of any number of clients on one device
+pub struct Daemon {
+    open: Opener,
+    cache_ttl: Duration,
+    state: Mutex<State>,
+}
+
+impl Daemon {
+    /// A daemon that opens its device with `open` on the first request, and again after
+    /// the device went away
+    pub fn new(open: Opener, cache_ttl: Duration) -> Self {
+        Self {
+            open,
+            cache_ttl,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Run `request`, from the cache if it is a read that was made recently
+    pub fn execute(&self, request: &Request) -> madr_lib::Result<Value> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let key = is_cacheable(request)
+            .then(|| serde_json::to_string(request).ok())
+            .flatten();
+
+        if let Some((read_at, value)) = key.as_ref().and_then(|k| state.cache.get(k))
+            && read_at.elapsed() < self.cache_ttl
+        {
+            return Ok(value.clone());
+        }
+
+        let device = match &state.device {
+            Some(device) => device.clone(),
+            None => {
+                let device = (self.open)()?;
+                state.device = Some(device.clone());
+                device
+            }
+        };
+
+        let result = request.execute(&device);
+
+        match &result {
+            Ok(value) => {
+                if request.is_write() {
+                    state.cache.clear();
+                }
+
+                if let Some(key) = key {
+                    state.cache.insert(key, (Instant::now(), value.clone()));
+                }
+            }
+            Err(e) if is_disconnect(e) => {
+                eprintln!("madrd: closing the device after: {}", e);
+                state.device = None;
+                state.cache.clear();
+            }
+            Err(_) => {}
+        }
+
+        result
+    }
+
+    /// Answer a single line from a client
+    pub fn respond(&self, line: &str) -> RpcResponse {
+        let envelope: RpcRequest = match serde_json::from_str(line) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                let code = if e.is_data() {
+                    RpcError::INVALID_REQUEST
+                } else {
+                    RpcError::PARSE_ERROR
+                };
+                return RpcResponse::failure(Value::Null, RpcError::new(code, e.to_string()));
+            }
+        };
+
+        let request = match envelope.request() {
+            Ok(request) => request,
+            Err(error) => return RpcResponse::failure(envelope.id, error),
+        };
+
+        match self.execute(&request) {
+            Ok(result) => RpcResponse::success(envelope.id, result),
+            Err(e) => RpcResponse::failure(envelope.id, e.into()),
+        }
+    }
+
+    /// Answer the requests of one client until it disconnects
+    pub fn serve(&self, stream: UnixStream) -> std::io::Result<()> {
+        let mut writer = stream.try_clone()?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut response = serde_json::to_string(&self.respond(&line))?;
+            response.push('\n');
+            writer.write_all(response.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Serve every client that connects to `listener`, each on its own thread
+    pub fn run(self: Arc<Self>, listener: UnixListener) {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("madrd: {}", e);
+                    continue;
+                }
+            };
+
+            let daemon = self.clone();
+            thread::spawn(move || {
+                if let Err(e) = daemon.serve(stream) {
+                    eprintln!("madrd: client: {}", e);
+                }
+            });
+        }
+    }
+}
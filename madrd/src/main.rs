@@ -0,0 +1,84 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use clap::Parser;
+
+use madr_lib::ipc::{self, Request};
+use madr_lib::{Device, device};
+use madrd::{DEFAULT_CACHE_TTL, Daemon};
+
+#[derive(Parser)]
+#[command(name = "madrd")]
+#[command(version, long_about = None)]
+#[command(about = "Keep a VXE MAD R series mouse open and share it with madrctl and others")]
+struct Cli {
+    /// Socket to listen on, defaults to $XDG_RUNTIME_DIR/madr/madrd.sock
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Open this device even if no known model matches it
+    #[arg(long, value_name = "VID:PID", value_parser = device::parse_device_id)]
+    force_device: Option<(u16, u16)>,
+
+    /// Treat the device given with --force-device as connected by cable
+    #[arg(long, requires = "force_device")]
+    wired: bool,
+
+    /// How long read settings are served from the cache, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = DEFAULT_CACHE_TTL.as_millis() as u64)]
+    cache_ttl: u64,
+}
+
+/// Bind the socket, replacing one left behind by a daemon that is gone
+fn bind(path: &Path) -> anyhow::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => bail!("madrd is already running on {}", path.display()),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("binding {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let path = match cli.socket {
+        Some(path) => path,
+        None => ipc::socket_path()?,
+    };
+    let listener = bind(&path)?;
+
+    let (force_device, wired) = (cli.force_device, cli.wired);
+    let open = move || match force_device {
+        Some((vid, pid)) => Device::open_forced(vid, pid, wired),
+        None => Device::open(),
+    };
+    let daemon = Arc::new(Daemon::new(
+        Box::new(open),
+        Duration::from_millis(cli.cache_ttl),
+    ));
+
+    // Open the device right away so problems show up at startup, it is opened again on
+    // the next request if that fails
+    if let Err(e) = daemon.execute(&Request::Info) {
+        eprintln!("madrd: {}", e);
+    }
+
+    println!("madrd listening on {}", path.display());
+
+    daemon.run(listener);
+    Ok(())
+}
@@ -0,0 +1,215 @@
+// madrd's handler, fed through ipc::Client, raw lines and direct calls: requests arrive as
+// the operation they were made from, results and errors make it back, reads are cached until
+// they expire or a write comes in, and the device is opened again after it went away.
+
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use madr_lib::debounce::Debounce;
+use madr_lib::emulator::{self, Emulator};
+use madr_lib::fault::{Fault, FaultyTransport};
+use madr_lib::ipc::{Client, Request, RpcError, RpcResponse};
+use madr_lib::sensor::Mode;
+use madr_lib::{MadRError, Performance, PollingRate, RetryPolicy, performance};
+use madrd::{DEFAULT_CACHE_TTL, Daemon};
+
+/// A daemon for `emulator`
+fn daemon(emulator: &Emulator) -> Daemon {
+    let emulator = emulator.clone();
+    Daemon::new(
+        Box::new(move || Ok(emulator.device(false))),
+        DEFAULT_CACHE_TTL,
+    )
+}
+
+/// Serve an emulated device on a fresh socket
+fn emulated_daemon(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("madrd-{}-{}.sock", name, process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let daemon = Arc::new(daemon(&Emulator::new()));
+    thread::spawn(move || daemon.run(listener));
+
+    path
+}
+
+fn performance(daemon: &Daemon) -> Performance {
+    serde_json::from_value(daemon.execute(&Request::Performance).unwrap()).unwrap()
+}
+
+/// Change the performance setting behind the daemon's back
+fn change_performance(emulator: &Emulator, dpi_stage: u8) {
+    let setting = Performance::new(dpi_stage, PollingRate::Hz1000);
+    performance::apply_setting(&emulator.device(false), &setting).unwrap();
+}
+
+#[test]
+fn client_round_trip() {
+    let path = emulated_daemon("round-trip");
+    let client = Client::connect_to(&path).unwrap();
+
+    let info = client.info().unwrap();
+    assert_eq!(info.model.name, "VXE MAD R");
+    assert!(info.verified && !info.wired);
+
+    client
+        .set_performance(&Performance::new(3, PollingRate::Hz500))
+        .unwrap();
+    let performance = client.performance().unwrap();
+    assert_eq!(performance.dpi_stage(), 3);
+    assert_eq!(performance.polling_rate(), PollingRate::Hz500);
+
+    client.set_sensor_mode(Mode::Max).unwrap();
+    assert_eq!(client.sensor().unwrap().mode(), Mode::Max);
+
+    client
+        .set_dpi(2, Some(1250), None, Some("1,2,3".parse().unwrap()))
+        .unwrap();
+    assert_eq!(client.read_stage(2).unwrap().x_dpi(), 1250);
+    assert_eq!(client.read_stage_color(2).unwrap().to_string(), "1,2,3");
+
+    client.set_debounce(Debounce::Ms4).unwrap();
+    client.set_sleep(Duration::from_secs(300)).unwrap();
+    assert_eq!(client.debounce().unwrap(), Debounce::Ms4);
+    assert_eq!(client.sleep().unwrap(), Duration::from_secs(300));
+
+    assert_eq!(client.snapshot().unwrap().as_bytes().len(), 256);
+
+    // Device errors come back as errors of the call, and the connection stays usable
+    let error = client.set_dpi(2, Some(123), None, None).unwrap_err();
+    assert!(matches!(error, MadRError::Daemon(_)), "{:?}", error);
+    assert!(client.battery().is_ok());
+}
+
+#[test]
+fn malformed_requests_are_rejected() {
+    let path = emulated_daemon("malformed");
+    let stream = UnixStream::connect(&path).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+
+    let mut call = |line: &str| {
+        writeln!(writer, "{}", line).unwrap();
+        let mut response = String::new();
+        reader.read_line(&mut response).unwrap();
+        serde_json::from_str::<RpcResponse>(&response).unwrap()
+    };
+
+    let response = call("{not json");
+    assert_eq!(response.error.unwrap().code, RpcError::PARSE_ERROR);
+
+    let response = call(r#"{"jsonrpc":"2.0","id":1,"method":"self_destruct"}"#);
+    assert_eq!(response.error.unwrap().code, RpcError::METHOD_NOT_FOUND);
+
+    let response = call(r#"{"jsonrpc":"2.0","id":2,"method":"read_stage","params":{}}"#);
+    assert_eq!(response.error.unwrap().code, RpcError::INVALID_PARAMS);
+
+    let response = call(r#"{"jsonrpc":"1.0","id":3,"method":"battery"}"#);
+    assert_eq!(response.error.unwrap().code, RpcError::INVALID_REQUEST);
+
+    let response =
+        call(r#"{"jsonrpc":"2.0","id":4,"method":"set_dpi","params":{"stage":2,"x_dpi":123}}"#);
+    let error = response.error.unwrap();
+    assert_eq!(error.code, RpcError::DEVICE_ERROR);
+    assert!(
+        error.message.starts_with("Invalid DPI setting"),
+        "{}",
+        error.message
+    );
+
+    // Methods without params may leave them out or send null
+    for params in ["", r#","params":null"#] {
+        let response = call(&format!(
+            r#"{{"jsonrpc":"2.0","id":4,"method":"sensor"{}}}"#,
+            params
+        ));
+        assert_eq!(response.id, 4);
+        assert!(response.error.is_none(), "{:?}", response.error);
+    }
+
+    assert!(!Request::Battery.is_write());
+    assert!(Request::SetSleep { seconds: 60 }.is_write());
+}
+
+#[test]
+fn reads_are_cached() {
+    let emulator = Emulator::new();
+    let daemon = daemon(&emulator);
+    assert_eq!(performance(&daemon).dpi_stage(), 1);
+
+    change_performance(&emulator, 4);
+    assert_eq!(performance(&daemon).dpi_stage(), 1);
+
+    thread::sleep(DEFAULT_CACHE_TTL);
+    assert_eq!(performance(&daemon).dpi_stage(), 4);
+}
+
+#[test]
+fn writes_clear_the_cache() {
+    let emulator = Emulator::new();
+    let daemon = daemon(&emulator);
+    assert_eq!(performance(&daemon).dpi_stage(), 1);
+
+    change_performance(&emulator, 4);
+    daemon
+        .execute(&Request::SetSensorMode { mode: Mode::Max })
+        .unwrap();
+    assert_eq!(performance(&daemon).dpi_stage(), 4);
+}
+
+#[test]
+fn device_is_opened_again_after_it_went_away() {
+    let emulator = Emulator::new();
+    let opens = Arc::new(AtomicUsize::new(0));
+
+    let counter = opens.clone();
+    let daemon = Daemon::new(
+        Box::new(move || {
+            if counter.fetch_add(1, Ordering::SeqCst) > 0 {
+                return Ok(emulator.device(false));
+            }
+
+            // The first device fails its first transport call, like a receiver pulled out
+            let transport =
+                FaultyTransport::new(Box::new(emulator.clone())).inject(0, Fault::Error);
+            let device = emulator::device(Box::new(transport), false);
+            Ok(device.with_retry_policy(RetryPolicy {
+                attempts: 1,
+                ..RetryPolicy::default()
+            }))
+        }),
+        DEFAULT_CACHE_TTL,
+    );
+
+    let error = daemon.execute(&Request::Battery).unwrap_err();
+    assert!(error.to_string().contains("injected fault"), "{:?}", error);
+    assert_eq!(opens.load(Ordering::SeqCst), 1);
+
+    assert!(daemon.execute(&Request::Battery).is_ok());
+    assert_eq!(opens.load(Ordering::SeqCst), 2);
+
+    // Errors of the request itself keep the device
+    let error = daemon
+        .execute(&Request::SetDpi {
+            stage: 2,
+            x_dpi: Some(123),
+            y_dpi: None,
+            rgb: None,
+        })
+        .unwrap_err();
+    assert!(
+        matches!(error, MadRError::InvalidDpiSetting(_)),
+        "{:?}",
+        error
+    );
+    assert!(daemon.execute(&Request::Sensor).is_ok());
+    assert_eq!(opens.load(Ordering::SeqCst), 2);
+}